@@ -0,0 +1,418 @@
+use std::io::{self, ErrorKind};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bevy::{math::vec2, prelude::*};
+use rand::Rng;
+
+use crate::car::{sensor_angles_deg, CarOutcome};
+use crate::enemy::{enemy_layout, EnemyType};
+use crate::nn::Net;
+use crate::population::{breed, calc_fitness, calc_shaped_fitness, Progress};
+use crate::track::{goal_position, spawn_area, Track};
+use crate::*;
+
+// Scores a generation's brains without the ECS or rapier. Cars never touch
+// each other, so each one drives its own copy of a static world and the
+// population is split across threads, every thread stepping its share of
+// cars to the end of the run.
+// Lighter than the rendered simulation: cars are circles, enemies drift and
+// swerve on rails without being knocked around and moving trucks are left out
+
+// Enemy as spawned, its position at any tick follows from this
+struct BatchEnemy {
+    start: Vec2,
+    half_size: Vec2,
+    // Horizontal speed of a swerving enemy
+    swerve_speed: Option<f32>,
+}
+
+// Road or track walls, the generation's enemies and where the cars start,
+// shared read only by every worker
+pub struct BatchWorld {
+    walls: Vec<Rect>,
+    enemies: Vec<BatchEnemy>,
+    goal_pos: Vec2,
+    goal: Goal,
+    spawns: Vec<Vec2>,
+    ray_directions: Vec<Vec2>,
+}
+
+// How one car's run ended and what it scored
+#[derive(Clone, Copy, PartialEq)]
+pub struct CarScore {
+    pub fitness: f32,
+    pub outcome: CarOutcome,
+}
+
+struct BatchCar {
+    pos: Vec2,
+    heading: f32,
+    progress: Progress,
+    fitness: f32,
+    outcome: Option<CarOutcome>,
+}
+
+impl BatchWorld {
+    // Laid out from `rng` in the same order as the rendered generation, so a
+    // generation seed gives the same enemies and spawn spots in both
+    pub fn new(settings: &Settings, track: Option<&Track>, rng: &mut impl Rng) -> Self {
+        let walls = match track {
+            Some(track) => track
+                .walls
+                .iter()
+                .map(|&cell| {
+                    Rect::from_center_size(track.cell_center(cell), Vec2::splat(track.cell_size))
+                })
+                .collect(),
+            // Road colliders from spawn_roads
+            None => vec![
+                Rect::new(ROAD_LEFT_X - 2.5, -ROAD_END_Y, ROAD_LEFT_X + 2.5, 2.0 * ROAD_END_Y),
+                Rect::new(ROAD_RIGHT_X - 2.5, -ROAD_END_Y, ROAD_RIGHT_X + 2.5, 2.0 * ROAD_END_Y),
+                Rect::from_center_half_size(
+                    vec2(600.0, ROAD_END_Y),
+                    vec2(500.0 * SPRITE_SCALE_FACTOR, 10.0) * 0.5,
+                ),
+            ],
+        };
+
+        let enemies = enemy_layout(rng)
+            .into_iter()
+            .map(|enemy| BatchEnemy {
+                start: enemy.position,
+                half_size: enemy.enemy_type.half_extents(),
+                swerve_speed: match enemy.enemy_type {
+                    EnemyType::Horizontal(direction) => Some(direction * ENEMY_SWERVE_SPEED),
+                    _ => None,
+                },
+            })
+            .collect();
+
+        let spawns = settings.spawn_formation.positions(settings.num_cars, spawn_area(track), rng);
+        let ray_directions = sensor_angles_deg(settings)
+            .into_iter()
+            .map(|angle_deg| Vec2::from_angle(angle_deg.to_radians()))
+            .collect();
+
+        Self {
+            walls,
+            enemies,
+            goal_pos: goal_position(track),
+            goal: Goal {
+                is_finish_line: track.is_none(),
+                radius: track.map_or(0.0, |track| track.cell_size),
+            },
+            spawns,
+            ray_directions,
+        }
+    }
+
+    // Enemies and the rising row of bound trucks `secs` into the run
+    fn obstacles_at(&self, tick: u32, secs: f32, obstacles: &mut Vec<Rect>) {
+        obstacles.clear();
+        let swerve_width = ENEMY_SWERVE_MAX_X - ENEMY_SWERVE_MIN_X;
+        for enemy in self.enemies.iter() {
+            let mut pos = enemy.start + vec2(0.0, ENEMY_DRIFT_SPEED * secs);
+            if let Some(speed) = enemy.swerve_speed {
+                // Back and forth between the edges
+                let offset = pos.x - ENEMY_SWERVE_MIN_X + speed * secs;
+                let offset = offset.rem_euclid(2.0 * swerve_width);
+                pos.x = ENEMY_SWERVE_MIN_X + swerve_width - (offset - swerve_width).abs();
+            }
+            obstacles.push(Rect::from_center_half_size(pos, enemy.half_size));
+        }
+
+        // spawn_bound_trucks' row, pushed up a pixel every frame
+        let bound_y = 100.0 + tick as f32;
+        obstacles.push(Rect::new(725.0, bound_y - 45.0, 1201.0, bound_y + 45.0));
+    }
+
+    // Sensor readings like sensors_system, which rotates the rays by the
+    // quaternion's z rather than the heading itself
+    fn sense(&self, car: &BatchCar, obstacles: &[Rect], max_toi: f32) -> Vec<f64> {
+        let rot = Vec2::from_angle(Quat::from_rotation_z(car.heading).z);
+        self.ray_directions
+            .iter()
+            .map(|&dir| {
+                let dir = rot.rotate(dir);
+                let toi = self
+                    .walls
+                    .iter()
+                    .chain(obstacles.iter())
+                    .filter_map(|rect| cast_ray(rect, car.pos, dir, max_toi))
+                    .fold(max_toi, f32::min);
+                toi as f64 / max_toi as f64
+            })
+            .collect()
+    }
+
+    fn is_hit(&self, pos: Vec2, obstacles: &[Rect]) -> bool {
+        self.walls
+            .iter()
+            .chain(obstacles.iter())
+            .any(|rect| pos.distance(pos.clamp(rect.min, rect.max)) < CAR_HALF_WIDTH)
+    }
+
+    // Drives `brains` from the matching spawns until every car has finished,
+    // crashed or timed out
+    fn run(&self, settings: &Settings, brains: &[Net], spawns: &[Vec2]) -> Vec<CarScore> {
+        let mut cars: Vec<BatchCar> = spawns
+            .iter()
+            .map(|&pos| BatchCar {
+                pos,
+                heading: 0.0,
+                progress: Progress::default(),
+                fitness: 0.0,
+                outcome: None,
+            })
+            .collect();
+
+        let time_scale = settings.time_scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+        let secs_per_tick = time_scale / BATCH_TICK_RATE;
+        let num_ticks = (CAR_TIMEOUT_SECONDS / secs_per_tick).ceil() as u32;
+        let mut obstacles = Vec::with_capacity(self.enemies.len() + 1);
+        for tick in 0..num_ticks {
+            if cars.iter().all(|car| car.outcome.is_some()) {
+                break;
+            }
+            self.obstacles_at(tick, tick as f32 * secs_per_tick, &mut obstacles);
+
+            for (car, brain) in cars.iter_mut().zip(brains.iter()) {
+                if car.outcome.is_some() {
+                    continue;
+                }
+
+                // Always on the gas, steering like car_nn_controlled_system
+                let inputs = self.sense(car, &obstacles, settings.sensor_max_length);
+                let outputs = brain.predict(&inputs);
+                let steer = match outputs.last().unwrap()[1] >= 0.5 {
+                    true => 0.5,
+                    false => -0.5,
+                };
+                car.heading += steer * 5.0 * secs_per_tick;
+                car.pos += Vec2::from_angle(car.heading).rotate(Vec2::Y) * 3.5 * time_scale;
+
+                let transform = Transform::from_translation(car.pos.extend(0.0));
+                car.fitness = match settings.is_reward_shaping {
+                    true => calc_shaped_fitness(
+                        &transform,
+                        self.goal_pos,
+                        &self.goal,
+                        &mut car.progress,
+                        settings,
+                    ),
+                    false => calc_fitness(&transform, self.goal_pos),
+                };
+                if self.goal.is_reached(self.goal_pos, car.pos) {
+                    car.outcome = Some(CarOutcome::Reached);
+                } else if self.is_hit(car.pos, &obstacles) {
+                    car.outcome = Some(CarOutcome::Crashed);
+                }
+            }
+        }
+
+        cars.iter()
+            .map(|car| CarScore {
+                fitness: car.fitness,
+                outcome: car.outcome.unwrap_or(CarOutcome::TimedOut),
+            })
+            .collect()
+    }
+}
+
+// Distance along `dir` to the rect's edge, if it's within `max_toi`
+fn cast_ray(rect: &Rect, origin: Vec2, dir: Vec2, max_toi: f32) -> Option<f32> {
+    let t1 = (rect.min - origin) / dir;
+    let t2 = (rect.max - origin) / dir;
+    let t_near = t1.min(t2).max_element();
+    let t_far = t1.max(t2).min_element();
+    (t_near <= t_far && t_far >= 0.0 && t_near <= max_toi).then(|| t_near.max(0.0))
+}
+
+// Every brain's score, in order, with the population split evenly over
+// `threads` workers. One thread runs them all on the calling thread
+pub fn evaluate(
+    world: &BatchWorld,
+    settings: &Settings,
+    brains: &[Net],
+    threads: usize,
+) -> Vec<CarScore> {
+    let num_cars = brains.len().min(world.spawns.len());
+    let chunk_size = (num_cars + threads.max(1) - 1) / threads.max(1);
+    if threads <= 1 || chunk_size == 0 {
+        return world.run(settings, &brains[..num_cars], &world.spawns[..num_cars]);
+    }
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = brains[..num_cars]
+            .chunks(chunk_size)
+            .zip(world.spawns[..num_cars].chunks(chunk_size))
+            .map(|(brains, spawns)| scope.spawn(move || world.run(settings, brains, spawns)))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Batch worker panicked"))
+            .collect()
+    })
+}
+
+// Trains `generations` generations headless on the batch evaluator. With
+// `compare_serial` every generation is also scored on one thread, to time
+// the two against each other, and the run fails if they disagree
+pub fn run_batch_training(
+    mut settings: Settings,
+    generation_seed: &GenerationSeed,
+    mut sim_rng: SimRng,
+    track: Option<&Track>,
+    generations: u32,
+    threads: usize,
+    compare_serial: bool,
+) -> io::Result<()> {
+    println!(
+        "Batch training {} cars for {} generations on {} threads",
+        settings.num_cars, generations, threads
+    );
+
+    settings.network_arch.inputs = sensor_angles_deg(&settings).len();
+    let mut brains: Vec<Net> = (0..settings.num_cars)
+        .map(|_| Net::from_arch(&settings.network_arch, &mut sim_rng.0))
+        .collect();
+
+    print_header(compare_serial);
+    let (mut parallel_total, mut serial_total) = (Duration::ZERO, Duration::ZERO);
+    for generation in 0..generations {
+        let world = BatchWorld::new(&settings, track, &mut generation_seed.rng(generation));
+
+        let started_at = Instant::now();
+        let scores = evaluate(&world, &settings, &brains, threads);
+        let parallel_time = started_at.elapsed();
+        parallel_total += parallel_time;
+
+        let serial_time = match compare_serial {
+            true => {
+                let started_at = Instant::now();
+                let serial_scores = evaluate(&world, &settings, &brains, 1);
+                let serial_time = started_at.elapsed();
+                if serial_scores != scores {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("generation {} scored differently on one thread", generation),
+                    ));
+                }
+                serial_total += serial_time;
+                Some(serial_time)
+            }
+            false => None,
+        };
+
+        let fitnesses: Vec<f32> = scores.iter().map(|score| score.fitness).collect();
+        let num_reached = scores.iter().filter(|s| s.outcome == CarOutcome::Reached).count();
+        let (max_fitness, new_brains) = breed(&brains, &fitnesses, &settings, &mut sim_rng.0);
+        brains = new_brains;
+        print_row(generation, max_fitness, num_reached, parallel_time, serial_time);
+    }
+
+    if compare_serial {
+        println!(
+            "Parallel {:.2}s, serial {:.2}s, {:.1}x speedup",
+            parallel_total.as_secs_f32(),
+            serial_total.as_secs_f32(),
+            serial_total.as_secs_f32() / parallel_total.as_secs_f32().max(f32::EPSILON)
+        );
+    }
+    Ok(())
+}
+
+fn print_header(compare_serial: bool) {
+    let mut header = format!(
+        "{:>5} | {:>11} | {:>7} | {:>8}",
+        "Gen", "Max fitness", "Reached", "Eval (s)"
+    );
+    if compare_serial {
+        header.push_str(&format!(" | {:>10} | {:>7}", "Serial (s)", "Speedup"));
+    }
+    println!("{}", header);
+    println!("{}", "-".repeat(header.len()));
+}
+
+fn print_row(
+    generation: u32,
+    max_fitness: f32,
+    num_reached: usize,
+    parallel_time: Duration,
+    serial_time: Option<Duration>,
+) {
+    let mut row = format!(
+        "{:>5} | {:>11.2} | {:>7} | {:>8.3}",
+        generation,
+        max_fitness,
+        num_reached,
+        parallel_time.as_secs_f32()
+    );
+    if let Some(serial_time) = serial_time {
+        row.push_str(&format!(
+            " | {:>10.3} | {:>6.1}x",
+            serial_time.as_secs_f32(),
+            serial_time.as_secs_f32() / parallel_time.as_secs_f32().max(f32::EPSILON)
+        ));
+    }
+    println!("{}", row);
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn generation(num_cars: u32) -> (Settings, BatchWorld, Vec<Net>) {
+        let mut settings = Settings {
+            num_cars,
+            ..default()
+        };
+        settings.network_arch.inputs = sensor_angles_deg(&settings).len();
+        let world = BatchWorld::new(&settings, None, &mut StdRng::seed_from_u64(1));
+        let mut rng = StdRng::seed_from_u64(2);
+        let brains = (0..num_cars)
+            .map(|_| Net::from_arch(&settings.network_arch, &mut rng))
+            .collect();
+        (settings, world, brains)
+    }
+
+    #[test]
+    fn threads_score_like_one_thread() {
+        let (settings, world, brains) = generation(8);
+        let serial = evaluate(&world, &settings, &brains, 1);
+        assert_eq!(serial.len(), brains.len());
+        assert!(evaluate(&world, &settings, &brains, 4) == serial);
+        assert!(evaluate(&world, &settings, &brains, 3) == serial);
+    }
+
+    #[test]
+    fn world_lays_out_the_rendered_enemies() {
+        let (_, world, _) = generation(1);
+        let layout = enemy_layout(&mut StdRng::seed_from_u64(1));
+        let starts: Vec<_> = world.enemies.iter().map(|enemy| enemy.start).collect();
+        let positions: Vec<_> = layout.iter().map(|enemy| enemy.position).collect();
+        assert_eq!(starts, positions);
+    }
+
+    // cargo test --release -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_evaluate() {
+        let (settings, world, brains) = generation(NUM_AI_CARS);
+        let started_at = Instant::now();
+        let serial = evaluate(&world, &settings, &brains, 1);
+        let serial_time = started_at.elapsed();
+        let started_at = Instant::now();
+        let parallel = evaluate(&world, &settings, &brains, 4);
+        let parallel_time = started_at.elapsed();
+        assert!(serial == parallel);
+        println!(
+            "serial {:.3}s, 4 threads {:.3}s",
+            serial_time.as_secs_f32(),
+            parallel_time.as_secs_f32()
+        );
+    }
+}
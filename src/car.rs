@@ -1,14 +1,20 @@
+use std::collections::VecDeque;
 use std::f32::consts::PI;
+use std::path::Path;
 
 use bevy::{
+    app::AppExit,
     math::{vec2, vec3},
     prelude::*,
 };
-use bevy_prototype_debug_lines::{DebugLines, DebugLinesPlugin};
+use bevy_prototype_debug_lines::DebugLines;
 use bevy_rapier2d::prelude::*;
 use rand::Rng;
+use serde::Serialize;
 
-use crate::nn::Net;
+use crate::nn::{Net, NetworkArch};
+use crate::pathfinding::AStarAgent;
+use crate::save::save_demonstration;
 use crate::*;
 
 pub struct CarPlugin;
@@ -16,6 +22,24 @@ pub struct CarPlugin;
 #[derive(Component)]
 pub struct Car;
 
+// Driven from the keyboard instead of its brain, at most one at a time
+#[derive(Component)]
+pub struct ManualCar;
+
+// One frame of manual driving, what the brain would have seen and the
+// outputs it should have produced, laid out like the network's output layer
+#[derive(Serialize)]
+pub struct DemonstrationSample {
+    pub inputs: Vec<f64>,
+    pub targets: Vec<f64>,
+}
+
+// Demonstration data for seeding a network, written when recording stops
+#[derive(Resource, Default, Serialize)]
+pub struct Demonstration {
+    pub samples: Vec<DemonstrationSample>,
+}
+
 #[derive(Component)]
 pub struct Brain {
     pub nn: Net,
@@ -33,6 +57,28 @@ struct Speed(f32);
 #[derive(Component)]
 pub struct Fitness(pub f32);
 
+// How a car's run ended, cars still driving don't have one
+#[derive(Component, Clone, Copy, PartialEq)]
+pub enum CarOutcome {
+    Reached,
+    Crashed,
+    TimedOut,
+}
+
+// Time left before a car still driving counts as timed out
+#[derive(Component)]
+pub struct CarTimeout(pub Timer);
+
+impl Default for CarTimeout {
+    fn default() -> Self {
+        Self(Timer::from_seconds(CAR_TIMEOUT_SECONDS, TimerMode::Once))
+    }
+}
+
+// Ring buffer of the most recent car positions
+#[derive(Component, Default)]
+pub struct Trail(pub VecDeque<Vec3>);
+
 #[derive(Resource, Default)]
 struct RayCastSensors(Vec<(f32, f32)>);
 
@@ -44,6 +90,8 @@ pub struct CarBundle {
     sprite_bundle: SpriteBundle,
     car: Car,
     fitness: Fitness,
+    trail: Trail,
+    timeout: CarTimeout,
     brain: Brain,
     turn_speed: TurnSpeed,
     speed: Speed,
@@ -60,30 +108,53 @@ pub struct CarBundle {
 
 impl Plugin for CarPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugin(DebugLinesPlugin::default())
-            .register_type::<TurnSpeed>()
+        app.register_type::<TurnSpeed>()
             .register_type::<Speed>()
             .insert_resource(RayCastSensors::default())
+            .init_resource::<Demonstration>()
             .add_startup_system(setup)
             // .add_system(car_manual_input_system)
-            .add_system(car_nn_controlled_system)
+            .add_system(car_nn_controlled_system.run_if(is_sim_running))
+            .add_system(manual_car_select_system)
+            .add_system(
+                car_manual_drive_system
+                    .after(manual_car_select_system)
+                    .run_if(is_sim_running),
+            )
+            // Runs after the window-close systems so it sees the exit event
+            .add_system(demonstration_save_system.in_base_set(CoreSet::Last))
             // .add_system(car_gas_system)
             // .add_system(car_steer_system)
             .add_system(collision_events_system)
-            .add_system(sensors_system);
+            .add_system(finish_line_system.run_if(is_sim_running))
+            .add_system(car_timeout_system.run_if(is_sim_running))
+            .add_system(leaderboard_system)
+            .add_system(sensors_system.run_if(is_sim_running))
+            .add_system(trail_system.run_if(is_sim_running));
+    }
+}
+
+impl Brain {
+    pub fn new(nn: Net) -> Self {
+        Self {
+            nn,
+            nn_outputs: Vec::new(),
+            ray_inputs: Vec::new(),
+        }
     }
 }
 
 fn position_based_movement_system(
     controls: CarControls, 
-    transform: &mut Transform
+    transform: &mut Transform,
+    time_scale: f32,
 ) {
     let w_key = controls.0;
     let a_key = controls.1;
     let _s_key = controls.2;
     let d_key = controls.3;
 
-    let time_step = 1.0 / 60.0;
+    let time_step = time_scale / 60.0;
     let mut rotation_factor = 0.0;
     let mut movement_factor = 0.0;
 
@@ -98,43 +169,132 @@ fn position_based_movement_system(
 
     transform.rotate_z(rotation_factor * 5.0 * time_step);
     let movement_direction = transform.rotation * Vec3::Y;
-    let movement_distance = movement_factor;
+    let movement_distance = movement_factor * time_scale;
     let translation_delta = movement_direction * movement_distance;
     transform.translation += translation_delta;
 }
 
-fn setup(mut ray_cast_sensors: ResMut<RayCastSensors>) {
+fn setup(settings: Res<Settings>, mut ray_cast_sensors: ResMut<RayCastSensors>) {
     // Pre compute the raycast directions
-    let angle_per_ray = RAYCAST_SPREAD_ANGLE_DEG / (NUM_RAY_CASTS as f32) + 1.0;
-    let mut current_angle = RAYCAST_START_ANGLE_DEG;
-    for _ in 0..NUM_RAY_CASTS {
-        let angle = current_angle * (PI / 180.0);
+    for angle_deg in sensor_angles_deg(&settings) {
+        let angle = angle_deg * (PI / 180.0);
         let x = angle.cos();
         let y = angle.sin();
         ray_cast_sensors.0.push((x, y));
-
-        current_angle += angle_per_ray;
     }
 }
 
+// Rays spread evenly over the field of view, minus the ones in a dead zone
+pub fn sensor_angles_deg(settings: &Settings) -> Vec<f32> {
+    let start = settings.sensor_fov_start_deg;
+    let end = settings.sensor_fov_end_deg;
+    let angle_per_ray = match settings.sensor_ray_count {
+        0 | 1 => 0.0,
+        n => (end - start) / (n - 1) as f32,
+    };
+
+    (0..settings.sensor_ray_count)
+        .map(|i| start + i as f32 * angle_per_ray)
+        .filter(|angle| {
+            !settings
+                .sensor_dead_zones
+                .iter()
+                .any(|(min, max)| angle >= min && angle <= max)
+        })
+        .collect()
+}
+
 fn collision_events_system(
     mut commands: Commands,
     mut collision_events: EventReader<CollisionEvent>,
+    astar_query: Query<(), With<AStarAgent>>,
+    driving_query: Query<(), (With<Car>, Without<CarOutcome>)>,
 ) {
     for collision_event in collision_events.iter() {
         match collision_event {
             CollisionEvent::Started(entity1, entity2, _) => {
-                commands.entity(*entity2).remove::<Car>();
-                commands.entity(*entity1).remove::<Car>();
+                // A* cars handle their own crashes
+                for entity in [entity1, entity2] {
+                    if astar_query.contains(*entity) {
+                        continue;
+                    }
+                    if driving_query.contains(*entity) {
+                        commands.entity(*entity).insert(CarOutcome::Crashed);
+                    }
+                    commands.entity(*entity).remove::<Car>();
+                }
             }
             _ => {}
         }
     }
 }
 
+// NN cars are done once they reach the goal, crossing the finish line on
+// the default road. A* cars track their own goal
+fn finish_line_system(
+    mut commands: Commands,
+    goal_query: Query<(&Transform, &Goal)>,
+    query: Query<(Entity, &Transform), (With<Car>, Without<AStarAgent>, Without<CarOutcome>)>,
+) {
+    let Ok((goal_transform, goal)) = goal_query.get_single() else {
+        return;
+    };
+
+    let goal_pos = goal_transform.translation.truncate();
+    for (entity, transform) in query.iter() {
+        if goal.is_reached(goal_pos, transform.translation.truncate()) {
+            commands.entity(entity).insert(CarOutcome::Reached).remove::<Car>();
+        }
+    }
+}
+
+fn car_timeout_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut CarTimeout), (With<Car>, Without<CarOutcome>)>,
+) {
+    for (entity, mut timeout) in query.iter_mut() {
+        if timeout.0.tick(time.delta()).just_finished() {
+            commands.entity(entity).insert(CarOutcome::TimedOut).remove::<Car>();
+        }
+    }
+}
+
+// Every car gets one shot at the leaderboard when its run ends
+fn leaderboard_system(
+    mut sim_stats: ResMut<SimStats>,
+    goal_query: Query<&Transform, With<Goal>>,
+    query: Query<(Entity, &Transform, &Sprite, &CarTimeout, &CarOutcome), Added<CarOutcome>>,
+) {
+    if query.is_empty() {
+        return;
+    }
+    let Ok(goal) = goal_query.get_single() else {
+        return;
+    };
+
+    let generation = sim_stats.generation_count;
+    for (entity, transform, sprite, timeout, outcome) in query.iter() {
+        sim_stats.leaderboard.push(LeaderboardEntry {
+            generation,
+            car: entity,
+            color: sprite.color,
+            finish_secs: (*outcome == CarOutcome::Reached).then(|| timeout.0.elapsed_secs()),
+            // Same scale as the fitness score
+            distance: Goal::score(goal.translation.truncate(), transform.translation.truncate()),
+        });
+    }
+    sim_stats.leaderboard.sort_by(|a, b| a.cmp_rank(b));
+    sim_stats.leaderboard.truncate(LEADERBOARD_SIZE);
+}
+
 fn car_nn_controlled_system(
     _time: Res<Time>,
-    mut car_query: Query<(&mut Speed, &mut TurnSpeed, &mut Brain, &mut Transform), With<Car>>,
+    settings: Res<Settings>,
+    mut car_query: Query<
+        (&mut Speed, &mut TurnSpeed, &mut Brain, &mut Transform),
+        (With<Car>, Without<ManualCar>),
+    >,
 ) {
     for (mut speed, mut turn_speed, mut brain, mut transform) in car_query.iter_mut() {
         if brain.ray_inputs.is_empty() {
@@ -142,9 +302,17 @@ fn car_nn_controlled_system(
             turn_speed.0 = 0.0;
             return;
         }
+        if brain.ray_inputs.len() != brain.nn.n_inputs() {
+            warn!(
+                "Sensor count {} doesn't match network inputs {}",
+                brain.ray_inputs.len(),
+                brain.nn.n_inputs()
+            );
+            continue;
+        }
 
         brain.nn_outputs = brain.nn.predict(&brain.ray_inputs);
-        let nn_out = brain.nn_outputs[NUM_OUPUT_NODES - 1].clone();
+        let nn_out = brain.nn_outputs.last().unwrap().clone();
         //  nn_out = brain.nn.predict(&brain.ray_inputs).pop().unwrap();
 
         // let w_key = nn_out[0] >= NN_W_ACTIVATION_THRESHOLD;
@@ -166,13 +334,106 @@ fn car_nn_controlled_system(
         //     &mut speed,
         //     &time,
         // );
-        position_based_movement_system(CarControls(w_key, a_key, s_key, d_key), &mut transform);
+        position_based_movement_system(
+            CarControls(w_key, a_key, s_key, d_key),
+            &mut transform,
+            settings.time_scale,
+        );
+    }
+}
+
+// Hands the followed car, or failing that the lead car, to the keyboard
+fn manual_car_select_system(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    follow_target: Option<Res<FollowTarget>>,
+    manual_query: Query<Entity, With<ManualCar>>,
+    car_query: Query<(Entity, &Transform), (With<Car>, With<Brain>)>,
+) {
+    if !settings.is_manual_drive {
+        manual_query.for_each(|car| {
+            commands.entity(car).remove::<ManualCar>();
+        });
+        return;
+    }
+    if !manual_query.is_empty() {
+        return;
+    }
+
+    let followed = follow_target.and_then(|target| car_query.get(target.0).ok());
+    let car = followed.or_else(|| lead_car(car_query.iter(), |(_, t)| t.translation.y));
+    if let Some((car, _)) = car {
+        commands.entity(car).insert(ManualCar);
     }
 }
 
+fn car_manual_drive_system(
+    settings: Res<Settings>,
+    keyboard_input: Option<Res<Input<KeyCode>>>,
+    mut demonstration: ResMut<Demonstration>,
+    mut car_query: Query<(&Brain, &mut Transform), (With<Car>, With<ManualCar>)>,
+) {
+    // Headless apps have no InputPlugin, there's nothing to drive with
+    let Some(keyboard_input) = keyboard_input else {
+        return;
+    };
+    let pressed = |keys: [KeyCode; 2]| keys.iter().any(|&key| keyboard_input.pressed(key));
+    let w_key = pressed([KeyCode::W, KeyCode::Up]);
+    let a_key = pressed([KeyCode::A, KeyCode::Left]);
+    let s_key = pressed([KeyCode::S, KeyCode::Down]);
+    let d_key = pressed([KeyCode::D, KeyCode::Right]);
+
+    for (brain, mut transform) in car_query.iter_mut() {
+        if settings.recording_demonstration && !brain.ray_inputs.is_empty() {
+            // The brain steers left at 0.5 and up, right below it
+            let steer = match (a_key, d_key) {
+                (true, false) => 1.0,
+                (false, true) => 0.0,
+                _ => 0.5,
+            };
+            demonstration.samples.push(DemonstrationSample {
+                inputs: brain.ray_inputs.clone(),
+                targets: vec![w_key as u8 as f64, steer, s_key as u8 as f64],
+            });
+        }
+        position_based_movement_system(
+            CarControls(w_key, a_key, s_key, d_key),
+            &mut transform,
+            settings.time_scale,
+        );
+    }
+}
+
+// Writes the demonstration when recording is switched off or the app closes
+fn demonstration_save_system(
+    mut exit_events: EventReader<AppExit>,
+    settings: Res<Settings>,
+    mut demonstration: ResMut<Demonstration>,
+    mut was_recording: Local<bool>,
+) {
+    let is_recording = settings.recording_demonstration;
+    let is_stopped = *was_recording && !is_recording;
+    let is_exiting = exit_events.iter().next().is_some() && is_recording;
+    *was_recording = is_recording;
+    if (!is_stopped && !is_exiting) || demonstration.samples.is_empty() {
+        return;
+    }
+
+    match save_demonstration(Path::new(DEMONSTRATION_PATH), &demonstration) {
+        Ok(_) => info!(
+            "Saved {} demonstration samples to {}",
+            demonstration.samples.len(),
+            DEMONSTRATION_PATH
+        ),
+        Err(e) => error!("Failed to write demonstration: {}", e),
+    }
+    demonstration.samples.clear();
+}
+
 #[allow(dead_code)]
 fn car_manual_input_system(
     time: Res<Time>,
+    settings: Res<Settings>,
     keyboard_input: Res<Input<KeyCode>>,
     mut car_query: Query<(&mut Speed, &mut TurnSpeed, &mut Transform), With<Car>>,
 ) {
@@ -189,7 +450,11 @@ fn car_manual_input_system(
             &mut speed,
             &time,
         );
-        position_based_movement_system(CarControls(w_key, a_key, s_key, d_key), &mut transform);
+        position_based_movement_system(
+            CarControls(w_key, a_key, s_key, d_key),
+            &mut transform,
+            settings.time_scale,
+        );
     }
 }
 
@@ -270,7 +535,7 @@ fn car_steer_system(
 }
 
 fn draw_ray_cast(
-    lines: &mut DebugLines,
+    lines: Option<&mut DebugLines>,
     settings: &Settings,
     start: Vec3,
     end: Vec3,
@@ -284,22 +549,21 @@ fn draw_ray_cast(
         return;
     }
 
-    lines.line_colored(start, end, 0.0, color);
+    // No debug lines when running headless
+    if let Some(lines) = lines {
+        lines.line_colored(start, end, 0.0, color);
+    }
 }
 
 fn sensors_system(
-    mut lines: ResMut<DebugLines>,
+    mut lines: Option<ResMut<DebugLines>>,
     settings: Res<Settings>,
     ray_cast_sensors: Res<RayCastSensors>,
     rapier_context: Res<RapierContext>,
     mut query: Query<(&Transform, &Velocity, &mut Brain, &Speed, &TurnSpeed), With<Car>>,
 ) {
     for (transform, _velocity, mut brain, _speed, _turn_speed) in query.iter_mut() {
-        let raycast_filter = CollisionGroups {
-            memberships: Group::GROUP_1,
-            filters: Group::GROUP_2,
-        };
-        let filter = QueryFilter::default().groups(raycast_filter);
+        let filter = QueryFilter::default().groups(SENSOR_RAY_GROUPS);
         let ray_pos = transform.translation;
         let mut nn_inputs = Vec::new();
 
@@ -307,15 +571,16 @@ fn sensors_system(
         // let rot = velocity.linvel.y.atan2(velocity.linvel.x) - PI / 2.0;
         let rot = transform.rotation.z;
         // let rot = turn_speed.0;
+        let max_toi = settings.sensor_max_length;
         for (mut x, mut y) in ray_cast_sensors.0.iter() {
             (x, y) = rotate_point(x, y, rot);
             let dest_vec = vec2(x, y);
-            let end_point = calculate_endpoint(ray_pos, dest_vec, RAYCAST_MAX_TOI);
-            draw_ray_cast(&mut lines, &settings, ray_pos, end_point, Color::RED);
+            let end_point = calculate_endpoint(ray_pos, dest_vec, max_toi);
+            draw_ray_cast(lines.as_deref_mut(), &settings, ray_pos, end_point, Color::RED);
 
             let ray_pos_2d = vec2(ray_pos.x, ray_pos.y);
             if let Some((_, toi)) =
-                rapier_context.cast_ray(ray_pos_2d, dest_vec, RAYCAST_MAX_TOI, false, filter)
+                rapier_context.cast_ray(ray_pos_2d, dest_vec, max_toi, false, filter)
             {
                 // The first collider hit has the entity `entity` and it hit after
                 // the ray travelled a distance equal to `ray_dir * toi`.
@@ -324,12 +589,12 @@ fn sensors_system(
 
                 // Invalidate when hit length more than max toi
                 let dist_to_hit = ray_pos.distance(hit_point);
-                nn_inputs.push(dist_to_hit as f64 / RAYCAST_MAX_TOI as f64);
-                if dist_to_hit > RAYCAST_MAX_TOI {
+                nn_inputs.push(dist_to_hit as f64 / max_toi as f64);
+                if dist_to_hit > max_toi {
                     continue;
                 }
 
-                draw_ray_cast(&mut lines, &settings, ray_pos, hit_point, Color::GREEN);
+                draw_ray_cast(lines.as_deref_mut(), &settings, ray_pos, hit_point, Color::GREEN);
             } else {
                 nn_inputs.push(1.0);
             }
@@ -339,6 +604,34 @@ fn sensors_system(
     }
 }
 
+fn trail_system(
+    mut lines: Option<ResMut<DebugLines>>,
+    settings: Res<Settings>,
+    mut query: Query<(&Transform, &Sprite, &mut Trail), With<Car>>,
+) {
+    for (transform, sprite, mut trail) in query.iter_mut() {
+        trail.0.push_back(transform.translation);
+        while trail.0.len() > TRAIL_MAX_POINTS {
+            trail.0.pop_front();
+        }
+
+        if !settings.show_trails {
+            continue;
+        }
+        let Some(lines) = lines.as_mut() else {
+            continue;
+        };
+
+        // Tinted like the car sprite, fading out towards the oldest point
+        let num_points = trail.0.len() as f32;
+        for (i, (start, end)) in trail.0.iter().zip(trail.0.iter().skip(1)).enumerate() {
+            let start_color = sprite.color.with_a(i as f32 / num_points);
+            let end_color = sprite.color.with_a((i + 1) as f32 / num_points);
+            lines.line_gradient(*start, *end, 0.0, start_color, end_color);
+        }
+    }
+}
+
 fn calculate_endpoint(pos: Vec3, direction: Vec2, length: f32) -> Vec3 {
     let dir = direction.normalize();
     vec3(pos[0] + dir[0] * length, pos[1] + dir[1] * length, 0.0)
@@ -362,8 +655,7 @@ fn rotate_point(x: f32, y: f32, angle_rad: f32) -> (f32, f32) {
 }
 
 impl CarBundle {
-    pub fn new(asset_server: &AssetServer) -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn new(asset_server: &AssetServer, rng: &mut impl Rng, arch: &NetworkArch) -> Self {
         let rand_x = rng.gen_range(800.0..1100.0);
 
         Self {
@@ -379,12 +671,10 @@ impl CarBundle {
             },
             car: Car,
             fitness: Fitness(0.0),
+            trail: Trail::default(),
+            timeout: CarTimeout::default(),
             brain: Brain {
-                nn: Net::new(vec![
-                    NUM_RAY_CASTS as usize,
-                    NUM_HIDDEN_NODES,
-                    NUM_OUPUT_NODES,
-                ]),
+                nn: Net::from_arch(arch, rng),
                 ray_inputs: Vec::new(),
                 nn_outputs: Vec::new(),
             },
@@ -401,16 +691,40 @@ impl CarBundle {
             },
             sleep: Sleeping::disabled(),
             ccd: Ccd::enabled(),
-            collision_groups: CollisionGroups {
-                memberships: Group::GROUP_1,
-                filters: Group::GROUP_2,
-            },
+            collision_groups: CAR_COLLISION_GROUPS,
         }
     }
 
-    pub fn with_brain(asset_server: &AssetServer, brain: &Net) -> Self {
-        let mut car = CarBundle::new(asset_server);
+    pub fn with_brain(asset_server: &AssetServer, rng: &mut impl Rng, brain: &Net) -> Self {
+        let mut car = CarBundle::new(asset_server, rng, &brain.arch());
         car.brain.nn = brain.clone();
         car
     }
-}
\ No newline at end of file
+
+    pub fn at(mut self, position: Vec2) -> Self {
+        self.sprite_bundle.transform.translation = position.extend(0.0);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_rapier2d::rapier::geometry::InteractionGroups;
+
+    use super::*;
+
+    // rapier's own pairing test, the one cast_ray applies to every collider
+    fn ray_hits(ray: CollisionGroups, collider: CollisionGroups) -> bool {
+        InteractionGroups::from(ray).test(InteractionGroups::from(collider))
+    }
+
+    #[test]
+    fn sensor_rays_skip_cars() {
+        assert!(!ray_hits(SENSOR_RAY_GROUPS, CAR_COLLISION_GROUPS));
+    }
+
+    #[test]
+    fn sensor_rays_hit_obstacles() {
+        assert!(ray_hits(SENSOR_RAY_GROUPS, OBSTACLE_COLLISION_GROUPS));
+    }
+}
@@ -1,14 +1,41 @@
 use bevy::prelude::Color;
+use bevy_rapier2d::prelude::{CollisionGroups, Group};
 
 /// Main
 pub const NUM_ROAD_TILES: u32 = 20;
 pub const ROAD_SPRITE_W: f32 = 160.0;
 pub const ROAD_SPRITE_H: f32 = 288.0;
 pub const NUM_ENEMY_CARS: u32 = 140;
+// Moving trucks spawned on top of the regular enemies, none by default
+pub const NUM_MOVING_TRUCKS: u32 = 0;
+pub const MOVING_TRUCK_LANES: u32 = 3;
+pub const MOVING_TRUCK_SPEED: f32 = 80.0;
+// Moving trucks start this far up the road, clear of the spawn area
+pub const MOVING_TRUCK_START_Y: f32 = 1200.0;
+// Truck half width (6.0 * 3.0 scale) plus the road wall's half width
+pub const MOVING_TRUCK_EDGE_GAP: f32 = 21.0;
+// Enemies drift up the road at this speed, horizontal ones also swerve
+// across it at this times their direction
+pub const ENEMY_DRIFT_SPEED: f32 = 50.0;
+pub const ENEMY_SWERVE_SPEED: f32 = 30.0;
+// Edges the swerving enemies bounce between
+pub const ENEMY_SWERVE_MIN_X: f32 = 742.0;
+pub const ENEMY_SWERVE_MAX_X: f32 = 1170.0;
 pub const SPRITE_SCALE_FACTOR: f32 = 6.0;
 pub const BACKGROUND_COLOR: Color = Color::BLACK;
 pub const WINDOW_WIDTH: f32 = 1980.0;
 pub const WINDOW_HEIGHT: f32 = 1080.0;
+// World units kept around the cars when the camera fits them all
+pub const CAMERA_FIT_MARGIN: f32 = 150.0;
+// Clicks this close to a car select it for the camera to follow
+pub const CAR_PICK_RADIUS: f32 = 25.0;
+// Drags shorter than this either way don't place an obstacle
+pub const PLACED_OBSTACLE_MIN_SIZE: f32 = 10.0;
+pub const ROAD_CENTER_X: f32 = WINDOW_WIDTH / 2.0 - 30.0;
+pub const ROAD_LEFT_X: f32 = ROAD_SPRITE_W / 2.0 * SPRITE_SCALE_FACTOR + 238.0;
+pub const ROAD_RIGHT_X: f32 = ROAD_SPRITE_W * SPRITE_SCALE_FACTOR + 248.0;
+pub const ROAD_END_Y: f32 =
+    ROAD_SPRITE_H * SPRITE_SCALE_FACTOR * (NUM_ROAD_TILES as f32 - 0.5) + 800.0;
 
 /// Car
 pub const NUM_AI_CARS: u32 = 100;
@@ -17,26 +44,170 @@ pub const CAR_THRUST: f32 = 5.0 * 100.0;
 pub const MAX_SPEED: f32 = 10.0 * 300.0;
 pub const FRICTION: f32 = 30.0 * 100.0;
 pub const MIN_SPEED_TO_STEER: f32 = 50.0;
+// Cars still driving after this long (scaled time) are taken out as timed out
+pub const CAR_TIMEOUT_SECONDS: f32 = 120.0;
+// Starting limit when a generation is switched to ending on time
+pub const GENERATION_TIME_LIMIT_SECONDS: f32 = 60.0;
+pub const MIN_TIME_SCALE: f32 = 0.1;
+pub const MAX_TIME_SCALE: f32 = 20.0;
 pub const NUM_RAY_CASTS: u32 = 15;
 pub const RAYCAST_SPREAD_ANGLE_DEG: f32 = 130.0;
 pub const RAYCAST_START_ANGLE_DEG: f32 = 20.0;
+pub const RAYCAST_END_ANGLE_DEG: f32 = RAYCAST_START_ANGLE_DEG
+    + (NUM_RAY_CASTS - 1) as f32 * (RAYCAST_SPREAD_ANGLE_DEG / NUM_RAY_CASTS as f32 + 1.0);
 pub const RAYCAST_MAX_TOI: f32 = 200.0;
+pub const TRAIL_MAX_POINTS: usize = 120;
+// Largest size of the minimap panel, the world is scaled to fit inside
+pub const MINIMAP_WIDTH: f32 = 150.0;
+pub const MINIMAP_HEIGHT: f32 = 400.0;
 // pub const RAYCAST_THICKNESS: f32 = 0.3;
 
+/// Collision groups
+// Cars are GROUP_1 and only interact with GROUP_2, so they drive through
+// each other and no sensor ray ever reports another car
+pub const CAR_GROUP: Group = Group::GROUP_1;
+// Walls, trucks, enemies and placed obstacles
+pub const OBSTACLE_GROUP: Group = Group::GROUP_2;
+pub const CAR_COLLISION_GROUPS: CollisionGroups = CollisionGroups {
+    memberships: CAR_GROUP,
+    filters: OBSTACLE_GROUP,
+};
+// Obstacles collide with everything, cars included
+pub const OBSTACLE_COLLISION_GROUPS: CollisionGroups = CollisionGroups {
+    memberships: OBSTACLE_GROUP,
+    filters: Group::ALL,
+};
+// Car sensors and the A* obstacle scan, cast as a car so they hit obstacles only
+pub const SENSOR_RAY_GROUPS: CollisionGroups = CAR_COLLISION_GROUPS;
+
 /// NN
 pub const NUM_HIDDEN_NODES: usize = 15;
 pub const NUM_OUPUT_NODES: usize = 3;
 pub const NN_VIZ_NODE_RADIUS: f32 = 10.0;
 pub const NN_W_ACTIVATION_THRESHOLD: f64 = 0.3;
 pub const NN_S_ACTIVATION_THRESHOLD: f64 = 0.8;
+pub const NN_WEIGHT_CLAMP: f32 = 0.0;
+pub const NN_WEIGHT_DECAY: f32 = 0.0;
+pub const NN_MUTATION_RATE: f32 = 1.0;
+pub const NN_MUTATION_STRENGTH: f32 = 0.5;
+pub const NN_ELITE_COUNT: usize = 1;
+pub const NN_TOURNAMENT_SIZE: usize = 3;
+// Larger populations measure diversity against the centroid instead of
+// comparing every pair of brains
+pub const NN_DIVERSITY_EXACT_MAX_CARS: usize = 100;
+
+/// Population
+pub const GENERATION_BASE_SEED: u64 = 42;
+pub const PROGRESS_CELL_SIZE: f32 = 100.0;
+pub const PROGRESS_CELL_REWARD: f32 = 0.5;
+pub const FINISH_BONUS: f32 = 100.0;
+pub const FINISH_LINE_Y: f32 = ROAD_END_Y - 50.0;
+// Distance score of a car level with the goal, the progress icon runs from
+// 0 to this in percent of the panel
+pub const GOAL_SCORE: f32 = 100.0;
+// Best car results kept across generations
+pub const LEADERBOARD_SIZE: usize = 5;
 
 /// Others
 pub const FONT_RES_PATH: &str = "Magero.ttf";
+pub const STATS_HISTORY_PATH: &str = "stats_history.json";
+pub const STATS_HISTORY_CSV_PATH: &str = "stats_history.csv";
+pub const CAR_FITNESS_CSV_PATH: &str = "car_fitness.csv";
+pub const BRAIN_SAVE_PATH: &str = "best_brain.json";
+pub const POPULATION_SAVE_PATH: &str = "population.json";
+// Best brain and generation written on exit, loadable with --resume
+pub const AUTOSAVE_PATH: &str = "autosave.json";
+pub const ASTAR_PATH_SVG_PATH: &str = "astar_path.svg";
+pub const ASTAR_PATH_JSON_PATH: &str = "astar_path.json";
+// Grid dumps are written as astar_grid_0.json, astar_grid_1.json, ...
+pub const ASTAR_GRID_DUMP_PREFIX: &str = "astar_grid";
+pub const RECORDING_PATH: &str = "recording.json";
+pub const DEMONSTRATION_PATH: &str = "demonstration.json";
+/// Headless
+pub const HEADLESS_DEFAULT_GENERATIONS: u32 = 10;
+// A* has no generations, a block of this many ticks stands in for one
+pub const HEADLESS_ASTAR_TICKS_PER_GENERATION: u32 = 600;
+// Comparison table written by the `bench` subcommand
+pub const BENCH_REPORT_PATH: &str = "bench.md";
+// Frames per second the batch evaluator steps at, the rate car movement is
+// tuned for
+pub const BATCH_TICK_RATE: f32 = 60.0;
 /// Pathfinding
-pub const ASTAR_GRID_WIDTH: i32 = 100;
-pub const ASTAR_GRID_HEIGHT: i32 = 200;
-pub const ASTAR_CELL_SIZE: f32 = 20.0;
-pub const ASTAR_SCAN_RADIUS: f32 = 300.0;
+// Open cells kept beside the road walls
+pub const ASTAR_GRID_MARGIN_CELLS: i32 = 5;
+// Cells across the road, the cell size is picked to fit this many exactly so
+// both walls fall on cell boundaries
+pub const ASTAR_ROAD_WIDTH_CELLS: i32 = 25;
+// Grid laid over the road from the same constants spawn_roads uses, with a
+// margin past both walls and tall enough to reach past the road's end
+pub const ASTAR_GRID_ORIGIN_X: f32 = ROAD_LEFT_X - ASTAR_GRID_MARGIN_CELLS as f32 * ASTAR_CELL_SIZE;
+pub const ASTAR_GRID_WIDTH: i32 = ASTAR_ROAD_WIDTH_CELLS + 2 * ASTAR_GRID_MARGIN_CELLS;
+pub const ASTAR_GRID_HEIGHT: i32 =
+    (ROAD_END_Y / ASTAR_CELL_SIZE) as i32 + 1 + ASTAR_GRID_MARGIN_CELLS;
+pub const ASTAR_CELL_SIZE: f32 = (ROAD_RIGHT_X - ROAD_LEFT_X) / ASTAR_ROAD_WIDTH_CELLS as f32;
+// Walls closer than this to a cell boundary count as on it, the derived cell
+// size isn't exact in f32
+pub const ASTAR_WALL_ALIGN_TOLERANCE: f32 = 1e-3;
+// Also probe each scanned cell along the diagonals, catching collider edges
+// that only clip a corner
+pub const ASTAR_SCAN_DIAGONALS: bool = true;
+// Obstacle probe length as a multiple of the distance to the cell's edge
+pub const ASTAR_SCAN_RAY_LENGTH: f32 = 1.0;
 pub const ASTAR_RECALC_INTERVAL: f32 = 1.0;
+// Replan once the car has moved this far since its last plan
+pub const ASTAR_REPLAN_DISTANCE: f32 = 50.0;
+// Obstacles are rescanned this often between replans to spot a blocked path
+pub const ASTAR_OBSTACLE_CHECK_INTERVAL: f32 = 0.25;
+// Obstacles are scanned this far around a stopped car, plus however far it
+// drives in ASTAR_SCAN_SPEED_FACTOR seconds, about 300 at the default speed
+pub const ASTAR_BASE_SCAN_RADIUS: f32 = 200.0;
+pub const ASTAR_SCAN_SPEED_FACTOR: f32 = 0.7;
 pub const ASTAR_MOVEMENT_SPEED: f32 = 150.0;
-pub const ASTAR_ROTATION_SPEED: f32 = 3.0;
\ No newline at end of file
+// Fastest an A* car turns to face its target, radians per second
+pub const ASTAR_ROTATION_SPEED: f32 = 3.0;
+// Headings this close (radians) to the target count as facing it
+pub const ASTAR_HEADING_EPSILON: f32 = 0.01;
+pub const ASTAR_MIN_TURN_RADIUS: f32 = 20.0;
+// Waypoints count as reached within this distance
+pub const ASTAR_ARRIVAL_RADIUS: f32 = 15.0;
+// Cars steer toward the point this far along the path ahead of them
+pub const ASTAR_LOOK_AHEAD: f32 = 60.0;
+// Waypoints kept from a single search, the rest is dropped and replanned
+// once the car gets there
+pub const ASTAR_MAX_PATH_LEN: usize = 512;
+pub const ASTAR_MAX_NODES: usize = 5000;
+// Heuristic weight, paths cost at most this many times the optimum
+pub const ASTAR_EPSILON: f32 = 1.0;
+// Extra cost the turn penalized planner charges for every change of
+// direction, a straight step costs 10
+pub const ASTAR_TURN_COST: i32 = 10;
+// Recent (nodes explored, path cost) pairs kept for the GUI
+pub const ASTAR_SEARCH_SAMPLES: usize = 200;
+// Fewer searches run per frame while frames take longer than this, 0 never
+// throttles
+pub const ASTAR_TARGET_FRAME_MS: f32 = 16.0;
+// A car that stays within this radius while chasing the same waypoint for
+// this many frames is considered stuck and replans
+pub const ASTAR_STUCK_RADIUS: f32 = 25.0;
+pub const ASTAR_STUCK_FRAMES: u32 = 90;
+// Car collider half height (8.0) times its sprite scale (2.5)
+pub const ASTAR_CAR_HALF_EXTENT: f32 = 20.0;
+// Car collider half width (5.0) times its sprite scale (2.5)
+pub const CAR_HALF_WIDTH: f32 = 12.5;
+// Default spawn formation, columns of cars with this gap between bodies
+pub const SPAWN_FORMATION_COLS: u32 = 10;
+pub const SPAWN_FORMATION_SPACING: f32 = 5.0;
+// Random spawns retry this many times for a spot clear of the other cars
+pub const SPAWN_RANDOM_ATTEMPTS: u32 = 50;
+// Gap kept between spawned cars and the road walls, car half extent plus
+// the wall's half width
+pub const SPAWN_WALL_CLEARANCE: f32 = 25.0;
+// Spawns stop this far up the road, short of the first enemies
+pub const SPAWN_AREA_DEPTH: f32 = 200.0;
+pub const ASTAR_SCAN_FRESHNESS_SECONDS: f32 = 0.25;
+// Detected obstacles start at confidence 1 and lose this much per second
+// without being seen again, the cell opens up once it drops below the
+// threshold
+pub const ASTAR_OBSTACLE_DECAY_PER_SECOND: f32 = 1.0;
+pub const ASTAR_OBSTACLE_CONFIDENCE_THRESHOLD: f32 = 0.5;
+pub const ASTAR_PATH_SIMPLIFY_EPSILON: f32 = 0.0;
\ No newline at end of file
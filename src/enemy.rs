@@ -3,7 +3,7 @@ use bevy::{
     prelude::*,
 };
 use bevy_rapier2d::prelude::*;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 use crate::*;
 
@@ -24,22 +24,66 @@ pub enum EnemyType {
 #[derive(Component)]
 pub struct BoundControlTruck;
 
+// One scattered enemy as laid out by enemy_layout
+pub struct EnemySpawn {
+    pub enemy_type: EnemyType,
+    pub position: Vec2,
+    pub sprite: &'static str,
+}
+
+// How the moving trucks drive
+#[derive(Clone, Copy, PartialEq)]
+pub enum TruckMotion {
+    Static,
+    // Back and forth across the road, bouncing off the edges
+    Oscillating,
+    // Down the road toward the cars, wrapping back to the top
+    Oncoming,
+}
+
+// Moving trucks spawned with every batch of enemies, spread evenly over
+// `lanes` lanes across the road
+#[derive(Resource, Clone)]
+pub struct TruckSpawnConfig {
+    pub count: u32,
+    pub lanes: u32,
+    pub speed: f32,
+    pub motion: TruckMotion,
+}
+
+#[derive(Component)]
+pub struct MovingTruck {
+    motion: TruckMotion,
+    speed: f32,
+    // +1 right, -1 left, only used when oscillating
+    direction: f32,
+}
+
 impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(setup)
+        app.init_resource::<TruckSpawnConfig>()
+            .add_startup_system(setup)
             .add_system(update_enemies)
+            .add_system(moving_truck_system)
             .add_system(bound_control_system);
     }
 }
 
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    spawn_enemies(&mut commands, &asset_server);
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    generation_seed: Res<GenerationSeed>,
+    truck_config: Res<TruckSpawnConfig>,
+) {
+    let mut rng = generation_seed.rng(0);
+    spawn_enemies(&mut commands, &asset_server, &mut rng);
+    spawn_moving_trucks(&mut commands, &asset_server, &truck_config);
 }
 
 fn update_enemies(
     mut enemy_query: Query<
         (&mut Transform, &mut Velocity, &mut Enemy, &mut EnemyType),
-        With<Enemy>,
+        (With<Enemy>, Without<MovingTruck>),
     >,
 ) {
     for (mut transform, mut velocity, mut enemy, mut enemy_type) in enemy_query.iter_mut() {
@@ -47,21 +91,21 @@ fn update_enemies(
             continue;
         }
 
-        velocity.linvel = vec2(0.0, 50.0);
+        velocity.linvel = vec2(0.0, ENEMY_DRIFT_SPEED);
         enemy.is_hit = velocity.angvel != 0.0;
 
         // horizontal motion
         match enemy_type.as_mut() {
             EnemyType::Horizontal(direction) => {
-                velocity.linvel += *direction * vec2(30.0, 0.0);
+                velocity.linvel += *direction * vec2(ENEMY_SWERVE_SPEED, 0.0);
 
                 // direction update
                 // 738 -> 1180 is the road x dir
-                if transform.translation.x >= 1170.0 {
-                    transform.translation.x = 1169.0;
+                if transform.translation.x >= ENEMY_SWERVE_MAX_X {
+                    transform.translation.x = ENEMY_SWERVE_MAX_X - 1.0;
                     *direction *= -1.0;
-                } else if transform.translation.x <= 742.0 {
-                    transform.translation.x = 743.0;
+                } else if transform.translation.x <= ENEMY_SWERVE_MIN_X {
+                    transform.translation.x = ENEMY_SWERVE_MIN_X + 1.0;
                     *direction *= -1.0;
                 }
             }
@@ -70,16 +114,61 @@ fn update_enemies(
     }
 }
 
+// Kinematic, so cars can't shove them, kept between the road colliders
+fn moving_truck_system(mut query: Query<(&mut Transform, &mut Velocity, &mut MovingTruck)>) {
+    let (min_x, max_x) = (ROAD_LEFT_X + MOVING_TRUCK_EDGE_GAP, ROAD_RIGHT_X - MOVING_TRUCK_EDGE_GAP);
+    for (mut transform, mut velocity, mut truck) in query.iter_mut() {
+        velocity.linvel = match truck.motion {
+            TruckMotion::Static => Vec2::ZERO,
+            TruckMotion::Oscillating => {
+                if transform.translation.x >= max_x {
+                    transform.translation.x = max_x;
+                    truck.direction = -1.0;
+                } else if transform.translation.x <= min_x {
+                    transform.translation.x = min_x;
+                    truck.direction = 1.0;
+                }
+                vec2(truck.direction * truck.speed, 0.0)
+            }
+            TruckMotion::Oncoming => {
+                // Past the bottom of the road, back to the finish line
+                if transform.translation.y < 0.0 {
+                    transform.translation.y = FINISH_LINE_Y;
+                }
+                vec2(0.0, -truck.speed)
+            }
+        };
+    }
+}
+
 fn bound_control_system(mut query: Query<&mut Transform, With<BoundControlTruck>>) {
     for mut transform in query.iter_mut() {
         transform.translation.y += 1.0;
     }
 }
 
-pub fn spawn_enemies(commands: &mut Commands, asset_server: &AssetServer) {
+// Type, start and sprite of every scattered enemy, drawn from `rng` so a
+// generation seed always lays out the same road. Shared by the rendered
+// spawn and the batch evaluator
+pub fn enemy_layout(rng: &mut impl Rng) -> Vec<EnemySpawn> {
     let mut enemy_y = 800.0;
+    let mut layout = Vec::new();
     for _ in 0..NUM_ENEMY_CARS {
-        let enemy_type = EnemyType::random();
+        let enemy_type = EnemyType::random(rng);
+        let x = rng.gen_range(743.0..1169.0);
+        let sprite = enemy_type.get_sprite(rng);
+        layout.push(EnemySpawn {
+            enemy_type,
+            position: vec2(x, enemy_y),
+            sprite,
+        });
+        enemy_y += 200.0;
+    }
+    layout
+}
+
+pub fn spawn_enemies(commands: &mut Commands, asset_server: &AssetServer, rng: &mut impl Rng) {
+    for EnemySpawn { enemy_type, position, sprite } in enemy_layout(rng) {
         let enemy_scale = match enemy_type {
             EnemyType::Truck => 3.0,
             _ => 2.5,
@@ -88,18 +177,14 @@ pub fn spawn_enemies(commands: &mut Commands, asset_server: &AssetServer) {
             EnemyType::Truck => Collider::cuboid(6.0, 15.0),
             _ => Collider::cuboid(4.0, 8.0),
         };
-        let mut rng = rand::thread_rng();
-        let x = rng.gen_range(743.0..1169.0);
-        let y = enemy_y;
-        enemy_y += 200.0;
         commands.spawn((
             SpriteBundle {
-                transform: Transform::from_xyz(x, y, 0.0).with_scale(vec3(
+                transform: Transform::from_xyz(position.x, position.y, 0.0).with_scale(vec3(
                     enemy_scale,
                     enemy_scale,
                     1.0,
                 )),
-                texture: asset_server.load(enemy_type.get_sprite()),
+                texture: asset_server.load(sprite),
                 ..default()
             },
             RigidBody::Dynamic,
@@ -108,6 +193,7 @@ pub fn spawn_enemies(commands: &mut Commands, asset_server: &AssetServer) {
             Friction::new(100.0),
             ActiveEvents::COLLISION_EVENTS,
             collider,
+            OBSTACLE_COLLISION_GROUPS,
             Damping {
                 angular_damping: 2.0,
                 linear_damping: 2.0,
@@ -118,6 +204,41 @@ pub fn spawn_enemies(commands: &mut Commands, asset_server: &AssetServer) {
     }
 }
 
+pub fn spawn_moving_trucks(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    config: &TruckSpawnConfig,
+) {
+    let lanes = config.lanes.max(1);
+    let lane_width = (ROAD_RIGHT_X - ROAD_LEFT_X - 2.0 * MOVING_TRUCK_EDGE_GAP) / lanes as f32;
+    let spacing = (FINISH_LINE_Y - MOVING_TRUCK_START_Y) / config.count.max(1) as f32;
+    for i in 0..config.count {
+        let lane = i % lanes;
+        let x = ROAD_LEFT_X + MOVING_TRUCK_EDGE_GAP + (lane as f32 + 0.5) * lane_width;
+        let y = MOVING_TRUCK_START_Y + i as f32 * spacing;
+        commands.spawn((
+            SpriteBundle {
+                transform: Transform::from_xyz(x, y, 0.0).with_scale(vec3(3.0, 3.0, 1.0)),
+                texture: asset_server.load("enemy-truck.png"),
+                ..default()
+            },
+            RigidBody::KinematicVelocityBased,
+            Velocity::zero(),
+            ActiveEvents::COLLISION_EVENTS,
+            Collider::cuboid(6.0, 15.0),
+            OBSTACLE_COLLISION_GROUPS,
+            Enemy::default(),
+            EnemyType::Truck,
+            MovingTruck {
+                motion: config.motion,
+                speed: config.speed,
+                // Alternate lanes head off in opposite directions
+                direction: if lane % 2 == 0 { 1.0 } else { -1.0 },
+            },
+        ));
+    }
+}
+
 pub fn spawn_bound_trucks(commands: &mut Commands, asset_server: &AssetServer) {
     // Bound control trucks
     let enemy_y = 100.0;
@@ -146,6 +267,7 @@ pub fn spawn_bound_trucks(commands: &mut Commands, asset_server: &AssetServer) {
             RigidBody::Fixed,
             ActiveEvents::COLLISION_EVENTS,
             collider,
+            OBSTACLE_COLLISION_GROUPS,
             Damping {
                 angular_damping: 2.0,
                 linear_damping: 2.0,
@@ -156,17 +278,34 @@ pub fn spawn_bound_trucks(commands: &mut Commands, asset_server: &AssetServer) {
     }
 }
 
+impl Default for TruckSpawnConfig {
+    fn default() -> Self {
+        Self {
+            count: NUM_MOVING_TRUCKS,
+            lanes: MOVING_TRUCK_LANES,
+            speed: MOVING_TRUCK_SPEED,
+            motion: TruckMotion::Oscillating,
+        }
+    }
+}
+
 impl EnemyType {
-    fn random() -> Self {
+    fn random(rng: &mut impl Rng) -> Self {
         let all_vals = [Self::Horizontal(3.0), Self::Simple, Self::Truck];
-        let mut rng = thread_rng();
         let index = rng.gen_range(0..all_vals.len());
 
         all_vals[index].clone()
     }
 
-    fn get_sprite(&self) -> &str {
-        let mut rng = thread_rng();
+    // Collider half size once scaled like the sprite
+    pub fn half_extents(&self) -> Vec2 {
+        match self {
+            EnemyType::Truck => vec2(6.0, 15.0) * 3.0,
+            _ => vec2(4.0, 8.0) * 2.5,
+        }
+    }
+
+    fn get_sprite(&self, rng: &mut impl Rng) -> &'static str {
         match self {
             EnemyType::Simple => {
                 let choices = ["enemy-blue-1.png", "enemy-yellow-1.png"];
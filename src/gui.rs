@@ -1,14 +1,20 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, window::PrimaryWindow};
 use bevy_inspector_egui::{
     bevy_egui::EguiContexts,
     egui::{
         self,
         epaint::CircleShape,
-        plot::{Line, Plot, PlotPoints},
+        plot::{Line, Plot, PlotPoints, Points},
         pos2, Color32, Shape, Stroke,
     },
 };
+use bevy_pancam::PanCam;
+use bevy_prototype_debug_lines::DebugLines;
+use bevy_rapier2d::prelude::*;
 
+use crate::car::Car;
+use crate::enemy::{TruckMotion, TruckSpawnConfig};
+use crate::pathfinding::{AStarCar, PathfindingBrain};
 use crate::*;
 
 pub struct GuiPlugin;
@@ -21,19 +27,25 @@ struct MaxScoreLabel;
 struct CarsAliveLabel;
 #[derive(Component)]
 struct CarProgressIcon;
+// Block dragged out on the road with the obstacle tool
+#[derive(Component)]
+struct PlacedObstacle;
 
 impl Plugin for GuiPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.add_startup_system(setup)
-            .insert_resource(BrainToDisplay::default())
-            .insert_resource(Settings::default())
-            .insert_resource(SimStats::default())
             .add_system(stats_dialog_system)
             .add_system(generation_count_stats_system)
             .add_system(max_score_stats_system)
             .add_system(num_cars_stats_system)
             .add_system(car_progress_system)
-            .add_system(nn_viz_system);
+            .add_system(nn_viz_system)
+            .add_system(pause_input_system)
+            .add_system(minimap_system)
+            .add_system(follow_select_system)
+            .add_system(obstacle_placement_system)
+            // Ahead of the systems that consume restart_sim
+            .add_system(placed_obstacle_clear_system.in_base_set(CoreSet::First));
     }
 }
 
@@ -189,9 +201,14 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
 }
 
 fn stats_dialog_system(
+    mut commands: Commands,
     mut contexts: EguiContexts,
     sim_stats: Res<SimStats>,
     mut settings: ResMut<Settings>,
+    mut truck_config: ResMut<TruckSpawnConfig>,
+    mut mode: ResMut<AlgorithmMode>,
+    follow_target: Option<Res<FollowTarget>>,
+    followed_query: Query<(&Velocity, Option<&AStarCar>)>,
 ) {
     let ctx = contexts.ctx_mut();
 
@@ -200,9 +217,20 @@ fn stats_dialog_system(
         .min_width(200.0)
         .default_pos(egui::pos2(1800.0, 1000.0))
         .show(ctx, |ui| {
+            // Only written back on a change, switching modes respawns the cars
+            let mut selected_mode = *mode;
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut selected_mode, AlgorithmMode::NeuralNetwork, "NN + GA");
+                ui.radio_value(&mut selected_mode, AlgorithmMode::AStar, "A*");
+            });
+            if selected_mode != *mode {
+                *mode = selected_mode;
+            }
+
             egui::CollapsingHeader::new("Distance Travelled")
                 .default_open(true)
                 .show(ui, |ui| {
+                    ui.label(format!("Success rate: {:.0}%", sim_stats.success_rate * 100.0));
                     let fitness_curve: PlotPoints = (0..sim_stats.fitness.len())
                         .map(|i| [i as f64, sim_stats.fitness[i] as f64])
                         .collect();
@@ -212,6 +240,91 @@ fn stats_dialog_system(
                         .show(ui, |plot_ui| plot_ui.line(line));
                 });
 
+            egui::CollapsingHeader::new("Leaderboard")
+                .default_open(true)
+                .show(ui, |ui| {
+                    // Clicking an entry follows that car while it's still around
+                    for (rank, entry) in sim_stats.leaderboard.iter().enumerate() {
+                        let result = match entry.finish_secs {
+                            Some(secs) => format!("{:.1}s", secs),
+                            None => format!("distance {:.1}", entry.distance),
+                        };
+                        let [r, g, b, _] = entry.color.as_rgba_u8();
+                        let text = egui::RichText::new(format!(
+                            "{}. Car {}  gen {}  {}",
+                            rank + 1,
+                            entry.car.index(),
+                            entry.generation,
+                            result
+                        ))
+                        .color(Color32::from_rgb(r, g, b));
+                        let is_followed =
+                            follow_target.as_ref().map_or(false, |t| t.0 == entry.car);
+                        if ui.selectable_label(is_followed, text).clicked() {
+                            commands.insert_resource(FollowTarget(entry.car));
+                        }
+                    }
+                });
+
+            egui::CollapsingHeader::new("Network")
+                .default_open(false)
+                .show(ui, |ui| {
+                    // Fixed for the run, set with --hidden on the command line
+                    let arch = &settings.network_arch;
+                    ui.label(format!("Layers: {}", arch));
+                    ui.label(format!("Inputs: {}", arch.inputs));
+                    ui.label(format!("Hidden: {:?}", arch.hidden));
+                    ui.label(format!("Outputs: {}", arch.outputs));
+                });
+
+            egui::CollapsingHeader::new("Population Diversity")
+                .default_open(false)
+                .show(ui, |ui| {
+                    ui.label(format!("Current: {:.3}", sim_stats.population_diversity));
+                    let diversity_curve: PlotPoints = (0..sim_stats.diversity.len())
+                        .map(|i| [i as f64, sim_stats.diversity[i] as f64])
+                        .collect();
+                    let line = Line::new(diversity_curve);
+                    Plot::new("diversity_curve")
+                        .view_aspect(2.0)
+                        .show(ui, |plot_ui| plot_ui.line(line));
+                });
+
+            egui::CollapsingHeader::new("A* Search")
+                .default_open(false)
+                .show(ui, |ui| {
+                    ui.label(format!("Nodes explored: {}", sim_stats.last_nodes_explored));
+                    ui.label(format!("Path cost: {}", sim_stats.last_path_cost));
+                    // Recent searches, nodes explored against path cost
+                    let samples: PlotPoints = sim_stats
+                        .search_samples
+                        .iter()
+                        .map(|&(nodes, cost)| [nodes as f64, cost as f64])
+                        .collect();
+                    Plot::new("search_samples")
+                        .view_aspect(2.0)
+                        .show(ui, |plot_ui| plot_ui.points(Points::new(samples).radius(2.0)));
+                    let best_checkpoints = sim_stats.checkpoints_cleared.iter().max().unwrap_or(&0);
+                    ui.label(format!("Checkpoints cleared: {}", best_checkpoints));
+                });
+
+            if let Some((velocity, astar_car)) =
+                follow_target.and_then(|target| followed_query.get(target.0).ok())
+            {
+                egui::CollapsingHeader::new("Followed Car")
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        ui.label(format!("Speed: {:.1}", velocity.linvel.length()));
+                        if let Some(astar_car) = astar_car {
+                            ui.label(format!(
+                                "Waypoint: {} / {}",
+                                astar_car.current_target,
+                                astar_car.path.len()
+                            ));
+                        }
+                    });
+            }
+
             egui::CollapsingHeader::new("Settings")
                 .default_open(true)
                 .show(ui, |ui| {
@@ -220,76 +333,508 @@ fn stats_dialog_system(
                         &mut settings.is_hide_rays_at_start,
                         "Hide ray casts at start",
                     );
+                    ui.add(
+                        egui::Slider::new(&mut settings.time_scale, MIN_TIME_SCALE..=MAX_TIME_SCALE)
+                            .logarithmic(true)
+                            .text("Time scale"),
+                    );
                     ui.checkbox(&mut settings.is_camera_follow, "Camera follow");
+                    ui.checkbox(&mut settings.camera_fit_all, "Camera fit all cars");
+                    ui.checkbox(&mut settings.show_trails, "Car trails");
+                    ui.checkbox(&mut settings.show_ghost, "Ghost of last best car");
+                    ui.checkbox(&mut settings.recording, "Record run");
+                    ui.checkbox(&mut settings.is_manual_drive, "Manual drive (WASD)");
+                    ui.checkbox(&mut settings.recording_demonstration, "Record demonstration");
+                    ui.checkbox(&mut settings.is_export_car_fitness, "Export per-car fitness");
+                    ui.checkbox(&mut settings.is_export_history, "Export stats history on exit");
+                    ui.checkbox(&mut settings.draw_astar_paths, "A* paths");
+                    ui.checkbox(&mut settings.is_grid_inspector, "Grid inspector");
+                    ui.checkbox(&mut settings.is_placing_obstacles, "Place obstacles (drag)");
+                    ui.add(
+                        egui::Slider::new(&mut settings.sensor_max_length, 50.0..=500.0)
+                            .text("Sensor length"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut settings.scan_freshness_seconds, 0.0..=5.0)
+                            .text("Scan freshness (s)"),
+                    );
+                    ui.checkbox(&mut settings.force_rescan, "Force obstacle rescan");
+                    ui.checkbox(&mut settings.is_incremental_scan, "Incremental obstacle scan");
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut settings.planner, Planner::AStar, "A*");
+                        ui.radio_value(&mut settings.planner, Planner::ThetaStar, "Theta*");
+                        ui.radio_value(&mut settings.planner, Planner::Bidirectional, "Bidirectional");
+                        ui.radio_value(&mut settings.planner, Planner::JumpPoint, "JPS");
+                        ui.radio_value(&mut settings.planner, Planner::DStarLite, "D* Lite");
+                        ui.radio_value(&mut settings.planner, Planner::TurnPenalized, "Fewer turns");
+                    });
+                    ui.checkbox(&mut settings.is_astar_death, "A* cars die on collision");
+                    ui.checkbox(&mut settings.is_parallel_search, "Parallel path search");
+                    ui.add(
+                        egui::Slider::new(&mut settings.path_simplify_epsilon, 0.0..=50.0)
+                            .text("Path simplify epsilon"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut settings.astar_epsilon, 1.0..=5.0)
+                            .text("A* heuristic weight"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut settings.astar_turn_cost, 0..=100)
+                            .text("A* turn cost"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut settings.target_frame_ms, 0.0..=50.0)
+                            .text("A* target frame time (ms)"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut settings.astar_speed, 10.0..=600.0)
+                            .text("A* car speed"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut settings.astar_turn_rate, 0.5..=10.0)
+                            .text("A* turn rate (rad/s)"),
+                    );
+                    ui.checkbox(&mut settings.astar_allow_reverse, "A* cars reverse");
+                    ui.checkbox(&mut settings.is_reward_shaping, "Progress reward shaping");
+                    ui.add(
+                        egui::Slider::new(&mut settings.mutation_rate, 0.0..=1.0)
+                            .text("Mutation rate"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut settings.mutation_strength, 0.0..=2.0)
+                            .text("Mutation strength"),
+                    );
+                    ui.add(egui::Slider::new(&mut settings.elite_count, 0..=10).text("Elites"));
+                    let is_tournament =
+                        matches!(settings.selection, SelectionStrategy::Tournament { .. });
+                    ui.horizontal(|ui| {
+                        if ui.radio(!is_tournament, "Roulette wheel").clicked() {
+                            settings.selection = SelectionStrategy::RouletteWheel;
+                        }
+                        if ui.radio(is_tournament, "Tournament").clicked() && !is_tournament {
+                            settings.selection = SelectionStrategy::Tournament {
+                                size: NN_TOURNAMENT_SIZE,
+                            };
+                        }
+                    });
+                    if let SelectionStrategy::Tournament { size } = &mut settings.selection {
+                        ui.add(egui::Slider::new(size, 2..=10).text("Tournament size"));
+                    }
+                    ui.horizontal(|ui| {
+                        let crossover = &mut settings.crossover;
+                        ui.radio_value(crossover, CrossoverStrategy::SinglePoint, "Single point");
+                        ui.radio_value(crossover, CrossoverStrategy::TwoPoint, "Two point");
+                        ui.radio_value(crossover, CrossoverStrategy::Uniform, "Uniform");
+                    });
+                });
+
+            egui::CollapsingHeader::new("Spawn Formation")
+                .default_open(false)
+                .show(ui, |ui| {
+                    // Takes effect the next time cars spawn
+                    let formation = settings.spawn_formation;
+                    ui.horizontal(|ui| {
+                        let is_grid = matches!(formation, SpawnFormation::Grid { .. });
+                        if ui.radio(is_grid, "Grid").clicked() && !is_grid {
+                            settings.spawn_formation = SpawnFormation::Grid {
+                                cols: SPAWN_FORMATION_COLS,
+                                spacing: SPAWN_FORMATION_SPACING,
+                            };
+                        }
+                        if ui.radio(formation == SpawnFormation::Line, "Line").clicked() {
+                            settings.spawn_formation = SpawnFormation::Line;
+                        }
+                        let is_random = matches!(formation, SpawnFormation::Random { .. });
+                        if ui.radio(is_random, "Random").clicked() && !is_random {
+                            settings.spawn_formation = SpawnFormation::Random {
+                                area: SPAWN_AREA_DEPTH,
+                            };
+                        }
+                    });
+                    match &mut settings.spawn_formation {
+                        SpawnFormation::Grid { cols, spacing } => {
+                            ui.add(egui::Slider::new(cols, 1..=20).text("Columns"));
+                            ui.add(egui::Slider::new(spacing, 0.0..=50.0).text("Spacing"));
+                        }
+                        SpawnFormation::Line => {}
+                        SpawnFormation::Random { area } => {
+                            ui.add(egui::Slider::new(area, 0.0..=SPAWN_AREA_DEPTH).text("Area"));
+                        }
+                    }
+                });
+
+            egui::CollapsingHeader::new("Generation End")
+                .default_open(false)
+                .show(ui, |ui| {
+                    let generation_end = settings.generation_end;
+                    ui.horizontal(|ui| {
+                        let is_all_done = generation_end == GenerationEnd::AllDone;
+                        if ui.radio(is_all_done, "All cars done").clicked() {
+                            settings.generation_end = GenerationEnd::AllDone;
+                        }
+                        let is_time_limit =
+                            matches!(generation_end, GenerationEnd::TimeLimit { .. });
+                        if ui.radio(is_time_limit, "Time limit").clicked() && !is_time_limit {
+                            settings.generation_end = GenerationEnd::TimeLimit {
+                                secs: GENERATION_TIME_LIMIT_SECONDS,
+                            };
+                        }
+                    });
+                    if let GenerationEnd::TimeLimit { secs } = &mut settings.generation_end {
+                        ui.add(
+                            egui::Slider::new(secs, 5.0..=CAR_TIMEOUT_SECONDS).text("Seconds"),
+                        );
+                    }
+                    // Zero turns either stop off
+                    ui.add(
+                        egui::Slider::new(&mut settings.max_generations, 0..=500)
+                            .text("Stop at generation"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut settings.plateau_generations, 0..=50)
+                            .text("Stop after generations without improvement"),
+                    );
+                });
+
+            egui::CollapsingHeader::new("Moving Trucks")
+                .default_open(false)
+                .show(ui, |ui| {
+                    // Takes effect the next time enemies spawn
+                    ui.add(egui::Slider::new(&mut truck_config.count, 0..=50).text("Count"));
+                    ui.add(egui::Slider::new(&mut truck_config.lanes, 1..=6).text("Lanes"));
+                    ui.add(egui::Slider::new(&mut truck_config.speed, 0.0..=300.0).text("Speed"));
+                    ui.horizontal(|ui| {
+                        let motion = &mut truck_config.motion;
+                        ui.radio_value(motion, TruckMotion::Static, "Static");
+                        ui.radio_value(motion, TruckMotion::Oscillating, "Oscillating");
+                        ui.radio_value(motion, TruckMotion::Oncoming, "Oncoming");
+                    });
                 });
 
             egui::CollapsingHeader::new("Controls")
                 .default_open(true)
                 .show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        let label = if settings.paused { "Resume" } else { "Pause" };
+                        if ui.button(label).clicked() {
+                            settings.paused = !settings.paused;
+                        };
+                        if ui
+                            .add_enabled(settings.paused, egui::Button::new("Step frame"))
+                            .clicked()
+                        {
+                            settings.step_frame = true;
+                        };
+                    });
                     if ui.button("Start next generation").clicked() {
                         settings.start_next_generation = true;
                     };
                     if ui.button("Restart Simulation").clicked() {
                         settings.restart_sim = true;
                     };
+                    if ui.button("Save best brain").clicked() {
+                        settings.save_best_brain = true;
+                    };
+                    if ui.button("Load saved brain").clicked() {
+                        settings.load_saved_brain = true;
+                    };
+                    if ui.button("Save population").clicked() {
+                        settings.save_population = true;
+                    };
+                    ui.checkbox(&mut settings.autosave_on_exit, "Save best brain on exit");
+                    if ui.button("Clear obstacles").clicked() {
+                        settings.clear_placed_obstacles = true;
+                    };
+                    if ui.button("Export A* path").clicked() {
+                        settings.export_astar_path = true;
+                    };
+                    if ui.button("Dump A* grid").clicked() {
+                        settings.dump_astar_grid = true;
+                    };
+                    if ui.button("Export CSV").clicked() {
+                        settings.export_history_csv = true;
+                    };
                 });
         });
 }
 
-fn nn_viz_system(mut contexts: EguiContexts, best_brain: Res<BrainToDisplay>) {
-    if best_brain.0.is_empty() {
+fn minimap_system(
+    mut contexts: EguiContexts,
+    car_query: Query<&Transform, With<Car>>,
+    brain_query: Query<(&Transform, &PathfindingBrain), With<Car>>,
+    goal_query: Query<&Transform, With<Goal>>,
+) {
+    // A* runs show the lead car's grid, otherwise just the road
+    let lead = lead_car(brain_query.iter(), |(t, _)| t.translation.y).map(|(_, brain)| brain);
+    let (origin, size, goal) = match lead {
+        Some(brain) => (
+            brain.grid.origin,
+            Vec2::new(brain.grid.width as f32, brain.grid.height as f32) * brain.grid.cell_size,
+            brain.current_goal(),
+        ),
+        None => (
+            Vec2::new(ROAD_LEFT_X, 0.0),
+            Vec2::new(ROAD_RIGHT_X - ROAD_LEFT_X, ROAD_END_Y),
+            goal_query
+                .get_single()
+                .map_or(Vec2::new(ROAD_CENTER_X, FINISH_LINE_Y), |t| t.translation.truncate()),
+        ),
+    };
+    let scale = (MINIMAP_WIDTH / size.x).min(MINIMAP_HEIGHT / size.y);
+
+    egui::Window::new("Minimap")
+        .resizable(false)
+        .default_pos(egui::pos2(1600.0, 100.0))
+        .show(contexts.ctx_mut(), |ui| {
+            let (response, painter) =
+                ui.allocate_painter(egui::vec2(size.x * scale, size.y * scale), egui::Sense::hover());
+            let rect = response.rect;
+            // World y grows up, screen y grows down
+            let to_screen = |p: Vec2| {
+                pos2(
+                    rect.min.x + (p.x - origin.x) * scale,
+                    rect.max.y - (p.y - origin.y) * scale,
+                )
+            };
+
+            painter.rect_filled(rect, 0.0, Color32::from_gray(40));
+            if let Some(brain) = lead {
+                let cell_size = (brain.grid.cell_size * scale).max(1.0);
+                for &cell in brain.grid.obstacles.union(&brain.grid.static_obstacles) {
+                    let corner = to_screen(brain.grid.grid_to_world(cell));
+                    painter.rect_filled(
+                        egui::Rect::from_min_size(
+                            pos2(corner.x, corner.y - cell_size),
+                            egui::vec2(cell_size, cell_size),
+                        ),
+                        0.0,
+                        Color32::from_gray(160),
+                    );
+                }
+            }
+            painter.circle_filled(to_screen(goal), 4.0, Color32::YELLOW);
+            for transform in car_query.iter() {
+                painter.circle_filled(to_screen(transform.translation.truncate()), 2.0, Color32::LIGHT_BLUE);
+            }
+        });
+}
+
+// Click a car to follow it, Tab cycles through the cars still driving and
+// back to the default camera
+fn follow_select_system(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    settings: Res<Settings>,
+    mouse_input: Res<Input<MouseButton>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    follow_target: Option<Res<FollowTarget>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    cam_query: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    car_query: Query<(Entity, &Transform), With<Car>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Tab) {
+        let mut cars: Vec<Entity> = car_query.iter().map(|(e, _)| e).collect();
+        cars.sort();
+        let current = follow_target.and_then(|t| cars.iter().position(|&e| e == t.0));
+        let next = match current {
+            Some(i) => cars.get(i + 1),
+            None => cars.first(),
+        };
+        match next {
+            Some(&entity) => commands.insert_resource(FollowTarget(entity)),
+            None => commands.remove_resource::<FollowTarget>(),
+        }
+        return;
+    }
+
+    if !mouse_input.just_pressed(MouseButton::Left) || contexts.ctx_mut().wants_pointer_input() {
+        return;
+    }
+    // Clicks edit the grid while the inspector is open
+    if settings.is_grid_inspector || settings.is_placing_obstacles {
+        return;
+    }
+    let (Ok(window), Ok((camera, cam_transform))) =
+        (window_query.get_single(), cam_query.get_single()) else {
+        return;
+    };
+    let Some(world_pos) = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(cam_transform, cursor))
+        .map(|ray| ray.origin.truncate())
+    else {
+        return;
+    };
+
+    let picked = car_query
+        .iter()
+        .map(|(e, t)| (e, t.translation.truncate().distance(world_pos)))
+        .filter(|(_, distance)| *distance < CAR_PICK_RADIUS)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b));
+    if let Some((entity, _)) = picked {
+        commands.insert_resource(FollowTarget(entity));
+    }
+}
+
+// Left drag spans a fixed block that the cars' sensors and the A* scan both
+// see, panning is off meanwhile so the drag doesn't move the camera
+fn obstacle_placement_system(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut lines: ResMut<DebugLines>,
+    settings: Res<Settings>,
+    mouse_input: Res<Input<MouseButton>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut cam_query: Query<(&Camera, &GlobalTransform, &mut PanCam), With<MainCamera>>,
+    mut drag_start: Local<Option<Vec2>>,
+) {
+    let (Ok(window), Ok((camera, cam_transform, mut pan_cam))) =
+        (window_query.get_single(), cam_query.get_single_mut()) else {
+        return;
+    };
+    if settings.is_changed() {
+        pan_cam.enabled = !settings.is_placing_obstacles;
+    }
+    if !settings.is_placing_obstacles {
+        *drag_start = None;
+        return;
+    }
+    let Some(world_pos) = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(cam_transform, cursor))
+        .map(|ray| ray.origin.truncate())
+    else {
+        return;
+    };
+
+    if mouse_input.just_pressed(MouseButton::Left) && !contexts.ctx_mut().wants_pointer_input() {
+        *drag_start = Some(world_pos);
+    }
+    let Some(start) = *drag_start else {
+        return;
+    };
+    let (min, max) = (start.min(world_pos), start.max(world_pos));
+    if !mouse_input.just_released(MouseButton::Left) {
+        // Outline of the block being dragged
+        let corners = [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)];
+        for i in 0..corners.len() {
+            let next = corners[(i + 1) % corners.len()];
+            lines.line_colored(corners[i].extend(0.0), next.extend(0.0), 0.0, Color::ORANGE);
+        }
+        return;
+    }
+
+    *drag_start = None;
+    let size = max - min;
+    if size.min_element() < PLACED_OBSTACLE_MIN_SIZE {
+        return;
+    }
+    let center = (min + max) / 2.0;
+    commands.spawn((
+        SpriteBundle {
+            transform: Transform::from_xyz(center.x, center.y, 0.0),
+            sprite: Sprite {
+                color: Color::ORANGE,
+                custom_size: Some(size),
+                ..default()
+            },
+            ..default()
+        },
+        RigidBody::Fixed,
+        Collider::cuboid(size.x / 2.0, size.y / 2.0),
+        OBSTACLE_COLLISION_GROUPS,
+        ActiveEvents::COLLISION_EVENTS,
+        PlacedObstacle,
+    ));
+}
+
+fn placed_obstacle_clear_system(
+    mut commands: Commands,
+    mut settings: ResMut<Settings>,
+    obstacle_query: Query<Entity, With<PlacedObstacle>>,
+) {
+    if !settings.clear_placed_obstacles && !settings.restart_sim {
+        return;
+    }
+
+    settings.clear_placed_obstacles = false;
+    obstacle_query.for_each(|obstacle| commands.entity(obstacle).despawn());
+}
+
+fn pause_input_system(keyboard_input: Res<Input<KeyCode>>, mut settings: ResMut<Settings>) {
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        settings.paused = !settings.paused;
+    }
+}
+
+fn nn_viz_system(
+    mut contexts: EguiContexts,
+    best_brain: Res<BrainToDisplay>,
+    brain_weights: Res<BrainWeightsToDisplay>,
+) {
+    if best_brain.0.len() < 2 {
         return;
     }
 
     let ctx = contexts.ctx_mut();
     let mut shapes = Vec::new();
     let tot_height = 700.0;
+    let num_layers = best_brain.0.len();
+    let output_layer = num_layers - 1;
+
+    // Inputs on the left, outputs on the right, hidden layers spread between
+    let layer_x = |layer: usize| 75.0 + 300.0 * layer as f32 / output_layer as f32;
+    // (height, padding) of each column
+    let layer_span = |layer: usize| match layer {
+        0 => (tot_height - 100.0, 100.0),
+        l if l == output_layer => (tot_height - 300.0, 200.0),
+        _ => (tot_height, 50.0),
+    };
+    // Node points are drawn in reverse value order for every layer but the
+    // outputs
+    let value_index = |layer: usize, i: usize, n: usize| match layer == output_layer {
+        true => i,
+        false => n - 1 - i,
+    };
 
-    // NN viz points
-    let points1 = get_nn_viz_points(NUM_RAY_CASTS as usize, tot_height - 100.0);
-    let points2 = get_nn_viz_points(NUM_HIDDEN_NODES as usize, tot_height);
-    let points3 = get_nn_viz_points(NUM_OUPUT_NODES as usize, tot_height - 300.0);
-    // NN ouput
-    let values1 = best_brain.0[0].clone();
-    let values2 = best_brain.0[1].clone();
-    let values3 = best_brain.0[2].clone();
-    // x's
-    let x1 = 75.0;
-    let x2 = 225.0;
-    let x3 = 375.0;
-    // Padding
-    let padding1 = 100.0;
-    let padding2 = 50.0;
-    let padding3 = 200.0;
-    // colors
-    let colors1: Vec<Color32> = values1
-        .iter()
-        .rev()
-        .map(|v| {
-            if *v != 1.0 {
-                Color32::GREEN
-            } else {
-                Color32::RED
-            }
+    let points: Vec<Vec<f32>> = (0..num_layers)
+        .map(|l| {
+            let (height, padding) = layer_span(l);
+            get_nn_viz_points(best_brain.0[l].len(), height)
+                .iter()
+                .map(|p| p + padding)
+                .collect()
         })
         .collect();
-    let colors2: Vec<Color32> = values2
-        .iter()
-        .rev()
-        .map(|v| {
-            if *v > 0.5 {
-                Color32::GREEN
-            } else {
-                Color32::RED
+    let colors: Vec<Vec<Color32>> = (0..num_layers)
+        .map(|l| {
+            let values = &best_brain.0[l];
+            if l == output_layer {
+                let mut colors = vec![Color32::RED; values.len()];
+                if values[1] >= 0.5 {
+                    colors[0] = Color32::GREEN;
+                } else {
+                    colors[1] = Color32::GREEN;
+                }
+                return colors;
             }
+            values
+                .iter()
+                .rev()
+                .map(|v| {
+                    let is_active = match l {
+                        0 => *v != 1.0,
+                        _ => *v > 0.5,
+                    };
+                    if is_active {
+                        Color32::GREEN
+                    } else {
+                        Color32::RED
+                    }
+                })
+                .collect()
         })
         .collect();
-    let mut colors3 = vec![Color32::RED, Color32::RED, Color32::RED];
-    if values3[1] >= 0.5 {
-        colors3[0] = Color32::GREEN;
-    } else {
-        colors3[1] = Color32::GREEN;
-    }
 
     // if values3[0] >= NN_W_ACTIVATION_THRESHOLD {
     //     colors3[0] = Color32::GREEN;
@@ -298,48 +843,31 @@ fn nn_viz_system(mut contexts: EguiContexts, best_brain: Res<BrainToDisplay>) {
     //     colors3[2] = Color32::GREEN;
     // }
 
-    // layer 1 -> 2 lines
-    for (p1, c1) in points1.iter().zip(colors1.iter()) {
-        for (p2, c2) in points2.iter().zip(colors2.iter()) {
-            let mut color = Color32::RED;
-            if are_colors_equad(*c1, *c2) {
-                color = Color32::GREEN;
+    // Lines between each layer and the next, the +1 skips the bias stored
+    // at the front of every node's weights
+    for l in 0..output_layer {
+        let (n_from, n_to) = (points[l].len(), points[l + 1].len());
+        let (x_from, x_to) = (layer_x(l), layer_x(l + 1));
+        for (i, (p1, c1)) in points[l].iter().zip(colors[l].iter()).enumerate() {
+            for (j, (p2, c2)) in points[l + 1].iter().zip(colors[l + 1].iter()).enumerate() {
+                let node = value_index(l + 1, j, n_to);
+                let input = value_index(l, i, n_from) + 1;
+                let weight = get_weight(&brain_weights.0, l, node, input);
+                shapes.push(egui::Shape::line(
+                    vec![pos2(x_from, *p1), pos2(x_to, *p2)],
+                    get_edge_stroke(weight, *c1, *c2),
+                ));
             }
-            shapes.push(egui::Shape::line(
-                vec![pos2(x1, *p1 + padding1), pos2(x2, *p2 + padding2)],
-                Stroke { width: 0.3, color },
-            ));
         }
     }
 
-    // layer 2 -> 3 lines
-    for (p2, c2) in points2.iter().zip(colors2.iter()) {
-        for (p3, c3) in points3.iter().zip(colors3.iter()) {
-            let mut color = Color32::RED;
-            if are_colors_equad(*c2, *c3) {
-                color = Color32::GREEN;
-            }
-            shapes.push(egui::Shape::line(
-                vec![pos2(x2, *p2 + padding2), pos2(x3, *p3 + padding3)],
-                Stroke { width: 0.3, color },
-            ));
+    for l in 0..num_layers {
+        for (p, c) in points[l].iter().zip(colors[l].iter()) {
+            shapes.push(get_nn_node_shape(layer_x(l), *p, *c));
         }
     }
 
-    // layer 1
-    for (p, c) in points1.iter().zip(colors1.iter()) {
-        shapes.push(get_nn_node_shape(x1, *p + padding1, *c));
-    }
-    // layer 2
-    for (p, c) in points2.iter().zip(colors2.iter()) {
-        shapes.push(get_nn_node_shape(x2, *p + padding2, *c));
-    }
-    // layer 3
-    for (p, c) in points3.iter().zip(colors3.iter()) {
-        shapes.push(get_nn_node_shape(x3, *p + padding3, *c));
-    }
-
-    shapes.append(&mut arrow_keys_viz_system(colors3));
+    shapes.append(&mut arrow_keys_viz_system(colors[output_layer].clone()));
     egui::SidePanel::left("left")
         .min_width(400.0)
         .show(ctx, |ui| {
@@ -359,10 +887,11 @@ fn generation_count_stats_system(
 
 fn num_cars_stats_system(
     stats: Res<SimStats>,
+    settings: Res<Settings>,
     mut q_num_cars_text: Query<&mut Text, With<CarsAliveLabel>>,
 ) {
     let mut num_cars_text = q_num_cars_text.single_mut();
-    num_cars_text.sections[0].value = format!("Cars: \n{}/{}", stats.num_cars_alive, NUM_AI_CARS);
+    num_cars_text.sections[0].value = format!("Cars: \n{}/{}", stats.num_cars_alive, settings.num_cars);
 }
 
 fn max_score_stats_system(
@@ -451,6 +980,30 @@ fn get_nn_viz_points(n: usize, tot_size: f32) -> Vec<f32> {
     points
 }
 
+fn get_weight(weights: &[Vec<Vec<f64>>], layer: usize, node: usize, input: usize) -> Option<f64> {
+    weights.get(layer)?.get(node)?.get(input).copied()
+}
+
+// Green for positive weights, red for negative, thicker the larger they are.
+// Falls back to the activation colors until the weights are known
+fn get_edge_stroke(weight: Option<f64>, from: Color32, to: Color32) -> Stroke {
+    let Some(weight) = weight else {
+        let color = match are_colors_equad(from, to) {
+            true => Color32::GREEN,
+            false => Color32::RED,
+        };
+        return Stroke { width: 0.3, color };
+    };
+
+    let magnitude = weight.abs().min(2.0) as f32 / 2.0;
+    let alpha = (60.0 + 195.0 * magnitude) as u8;
+    let color = match weight >= 0.0 {
+        true => Color32::from_rgba_unmultiplied(0, 255, 0, alpha),
+        false => Color32::from_rgba_unmultiplied(255, 0, 0, alpha),
+    };
+    Stroke { width: 0.2 + 1.8 * magnitude, color }
+}
+
 fn are_colors_equad(first: Color32, second: Color32) -> bool {
     (first.g() == 255 && second.g() == 255) || (first.r() == 255 && second.r() == 255)
 }
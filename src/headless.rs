@@ -0,0 +1,213 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use bevy::{app::AppExit, prelude::*};
+
+use crate::car::Brain;
+use crate::pathfinding::AStarAgent;
+use crate::*;
+
+// Runs a fixed number of generations without a window, then prints a summary
+pub struct HeadlessPlugin {
+    pub generations: u32,
+    // Where the run's totals go when it's part of a `bench` comparison
+    pub report: Option<BenchReport>,
+}
+
+// Totals of every headless run that was given it, shared so they outlive
+// the apps that made them
+#[derive(Clone, Default)]
+pub struct BenchReport(pub Arc<Mutex<Vec<BenchResult>>>);
+
+pub struct BenchResult {
+    pub mode: AlgorithmMode,
+    pub generations: u32,
+    // Averaged over the generations
+    pub success_rate: f32,
+    pub mean_distance: f32,
+    // Search time per car, None for the NN which doesn't plan
+    pub planning_ms: Option<f32>,
+    pub wall_clock_secs: f32,
+}
+
+struct GenerationRow {
+    generation: u32,
+    max_score: f32,
+    num_cars_alive: usize,
+    success_rate: f32,
+    // Mean Goal::score of every car when the generation closed
+    mean_distance: f32,
+    wall_clock_secs: f32,
+    search_secs: f32,
+}
+
+#[derive(Resource)]
+struct Benchmark {
+    generations: u32,
+    started_at: Instant,
+    ticks: u32,
+    // Ticks since the last generation closed
+    generation_ticks: u32,
+    last_generation: u32,
+    // Last frame's mean distance, this frame's may already be the new cars
+    mean_distance: f32,
+    // A*'s success rate when its generation closed, before the reset
+    success_rate: Option<f32>,
+    rows: Vec<GenerationRow>,
+    report: Option<BenchReport>,
+}
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Benchmark {
+            generations: self.generations,
+            started_at: Instant::now(),
+            ticks: 0,
+            generation_ticks: 0,
+            last_generation: 0,
+            mean_distance: 0.0,
+            success_rate: None,
+            rows: Vec::new(),
+            report: self.report.clone(),
+        })
+        // After Update so settings_system can't swallow the A* generation
+        // flag before astar_reset_system sees it
+        .add_system(benchmark_system.in_base_set(CoreSet::PostUpdate));
+    }
+}
+
+fn benchmark_system(
+    mut benchmark: ResMut<Benchmark>,
+    mut sim_stats: ResMut<SimStats>,
+    mut settings: ResMut<Settings>,
+    mode: Res<AlgorithmMode>,
+    mut exit_events: EventWriter<AppExit>,
+    goal_query: Query<&Transform, With<Goal>>,
+    cars_query: Query<&Transform, Or<(With<Brain>, With<AStarAgent>)>>,
+) {
+    benchmark.ticks += 1;
+
+    let frame_mean_distance = match goal_query.get_single() {
+        Ok(goal) => {
+            let goal_pos = goal.translation.truncate();
+            let total: f32 = cars_query
+                .iter()
+                .map(|transform| Goal::score(goal_pos, transform.translation.truncate()))
+                .sum();
+            total / cars_query.iter().len().max(1) as f32
+        }
+        Err(_) => 0.0,
+    };
+    let mean_distance = std::mem::replace(&mut benchmark.mean_distance, frame_mean_distance);
+
+    if sim_stats.generation_count != benchmark.last_generation {
+        benchmark.last_generation = sim_stats.generation_count;
+        benchmark.generation_ticks = 0;
+        let success_rate = benchmark.success_rate.take().unwrap_or(sim_stats.success_rate);
+        let row = GenerationRow {
+            generation: sim_stats.generation_count,
+            max_score: sim_stats.fitness.last().copied().unwrap_or(0.0),
+            num_cars_alive: sim_stats.num_cars_alive,
+            success_rate,
+            mean_distance,
+            wall_clock_secs: benchmark.started_at.elapsed().as_secs_f32(),
+            search_secs: sim_stats.total_search_secs,
+        };
+        benchmark.rows.push(row);
+
+        if sim_stats.generation_count >= benchmark.generations {
+            print_summary(&benchmark.rows);
+            if let Some(report) = &benchmark.report {
+                let result = bench_result(*mode, &benchmark.rows, settings.num_cars);
+                report.0.lock().unwrap().push(result);
+            }
+            exit_events.send(AppExit);
+        }
+        return;
+    }
+    benchmark.generation_ticks += 1;
+
+    // A* cars don't breed, a generation closes once every car has reached
+    // the goal or crashed, or after a fixed number of ticks, and the reset
+    // puts fresh cars back on the start line. The first tick after a reset
+    // can still see the old cars' stats
+    if *mode != AlgorithmMode::AStar || settings.start_next_generation {
+        return;
+    }
+    let is_done = benchmark.generation_ticks > 1
+        && !cars_query.is_empty()
+        && sim_stats.num_cars_alive <= sim_stats.num_cars_finished;
+    if is_done || benchmark.generation_ticks >= HEADLESS_ASTAR_TICKS_PER_GENERATION {
+        let score = sim_stats.max_current_score;
+        sim_stats.fitness.push(score);
+        benchmark.success_rate = Some(sim_stats.success_rate);
+        settings.start_next_generation = true;
+    }
+}
+
+fn bench_result(mode: AlgorithmMode, rows: &[GenerationRow], num_cars: u32) -> BenchResult {
+    let num_rows = rows.len().max(1) as f32;
+    let last = rows.last();
+    let search_secs = last.map_or(0.0, |row| row.search_secs);
+    let num_runs = (num_cars as f32 * rows.len() as f32).max(1.0);
+    BenchResult {
+        mode,
+        generations: rows.len() as u32,
+        success_rate: rows.iter().map(|row| row.success_rate).sum::<f32>() / num_rows,
+        mean_distance: rows.iter().map(|row| row.mean_distance).sum::<f32>() / num_rows,
+        planning_ms: (mode == AlgorithmMode::AStar).then(|| search_secs * 1000.0 / num_runs),
+        wall_clock_secs: last.map_or(0.0, |row| row.wall_clock_secs),
+    }
+}
+
+// Side by side table of every run in the report, printed and written to `path`
+pub fn write_bench_report(path: &Path, report: &BenchReport) -> io::Result<()> {
+    let mut table = format!(
+        "| {:<34} | {:>11} | {:>12} | {:>13} | {:>13} | {:>8} |\n",
+        "Algorithm", "Generations", "Success rate", "Mean distance", "Planning (ms)", "Time (s)"
+    );
+    table.push_str(&format!(
+        "|{}|{}|{}|{}|{}|{}|\n",
+        "-".repeat(36),
+        "-".repeat(13),
+        "-".repeat(14),
+        "-".repeat(15),
+        "-".repeat(15),
+        "-".repeat(10)
+    ));
+    for result in report.0.lock().unwrap().iter() {
+        let planning = match result.planning_ms {
+            Some(ms) => format!("{:.3}", ms),
+            None => "-".to_string(),
+        };
+        table.push_str(&format!(
+            "| {:<34} | {:>11} | {:>11.0}% | {:>13.2} | {:>13} | {:>8.2} |\n",
+            result.mode.label(),
+            result.generations,
+            result.success_rate * 100.0,
+            result.mean_distance,
+            planning,
+            result.wall_clock_secs
+        ));
+    }
+
+    print!("{}", table);
+    fs::write(path, table)
+}
+
+fn print_summary(rows: &[GenerationRow]) {
+    println!(
+        "{:>5} | {:>10} | {:>10} | {:>10} | {:>10}",
+        "Gen", "Max score", "Cars alive", "Time (s)", "Search (s)"
+    );
+    println!("{}", "-".repeat(57));
+    for row in rows {
+        println!(
+            "{:>5} | {:>10.2} | {:>10} | {:>10.2} | {:>10.3}",
+            row.generation, row.max_score, row.num_cars_alive, row.wall_clock_secs, row.search_secs
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use bevy::{app::AppExit, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+pub struct HistoryPlugin;
+
+// Fields added after the first release default when reading older files
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SimStatsSnapshot {
+    pub generation: u32,
+    pub max_fitness: f32,
+    pub max_current_score: f32,
+    pub num_cars_alive: usize,
+    pub seed: u64,
+    #[serde(default)]
+    pub num_cars_finished: usize,
+    #[serde(default)]
+    pub nodes_explored: usize,
+    #[serde(default)]
+    pub path_cost: i32,
+    #[serde(default)]
+    pub search_secs: f32,
+    #[serde(default)]
+    pub population_diversity: f32,
+}
+
+// One snapshot per finished generation
+#[derive(Resource, Default)]
+pub struct SimStatsHistory(pub Vec<SimStatsSnapshot>);
+
+impl SimStatsSnapshot {
+    // Taken by the reset systems before the generation count moves on, so
+    // the stats and seed are the ones of the generation that just ended
+    pub fn ended(
+        sim_stats: &SimStats,
+        generation_seed: &GenerationSeed,
+        num_cars_alive: usize,
+        num_cars_finished: usize,
+    ) -> Self {
+        Self {
+            generation: sim_stats.generation_count,
+            max_fitness: sim_stats.fitness.last().copied().unwrap_or(0.0),
+            max_current_score: sim_stats.max_current_score,
+            num_cars_alive,
+            seed: generation_seed.for_generation(sim_stats.generation_count),
+            num_cars_finished,
+            nodes_explored: sim_stats.last_nodes_explored,
+            path_cost: sim_stats.last_path_cost,
+            search_secs: sim_stats.total_search_secs,
+            population_diversity: sim_stats.population_diversity,
+        }
+    }
+}
+
+impl SimStatsHistory {
+    // A generation no later than the last one recorded means the run was
+    // restarted, so the old run's snapshots are dropped
+    pub fn record(&mut self, snapshot: SimStatsSnapshot) {
+        self.0.retain(|recorded| recorded.generation < snapshot.generation);
+        self.0.push(snapshot);
+    }
+}
+
+#[derive(Serialize)]
+struct HistoryExport<'a> {
+    base_seed: u64,
+    settings: &'a Settings,
+    snapshots: &'a Vec<SimStatsSnapshot>,
+}
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SimStatsHistory::default())
+            .add_system(history_stats_out_system)
+            .add_system(history_csv_export_system)
+            // Runs after the window-close systems so it sees the exit event
+            .add_system(history_export_system.in_base_set(CoreSet::Last));
+    }
+}
+
+// Snapshots are recorded by the reset systems, this only writes them out
+fn history_stats_out_system(
+    settings: Res<Settings>,
+    generation_seed: Res<GenerationSeed>,
+    history: Res<SimStatsHistory>,
+) {
+    if !history.is_changed() || history.0.is_empty() {
+        return;
+    }
+
+    // Rewritten every generation so a run cut short still leaves its stats
+    if let Some(stats_out) = &settings.stats_out_path {
+        if let Err(e) = write_history(stats_out, &settings, &generation_seed, &history) {
+            error!("Failed to write {}: {}", stats_out.display(), e);
+        }
+    }
+}
+
+// The one JSON export of the history, written per generation to
+// --stats-out and on exit
+fn write_history(
+    path: &Path,
+    settings: &Settings,
+    generation_seed: &GenerationSeed,
+    history: &SimStatsHistory,
+) -> io::Result<()> {
+    let export = HistoryExport {
+        base_seed: generation_seed.base_seed,
+        settings,
+        snapshots: &history.0,
+    };
+    let json = serde_json::to_string_pretty(&export)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+    fs::write(path, json)
+}
+
+fn history_csv_export_system(mut settings: ResMut<Settings>, history: Res<SimStatsHistory>) {
+    if !settings.export_history_csv {
+        return;
+    }
+    settings.export_history_csv = false;
+
+    let mut csv = String::from("generation,best_score,cars_alive\n");
+    for snapshot in history.0.iter() {
+        csv.push_str(&format!(
+            "{},{},{}\n",
+            snapshot.generation, snapshot.max_fitness, snapshot.num_cars_alive
+        ));
+    }
+    match std::fs::write(STATS_HISTORY_CSV_PATH, csv) {
+        Ok(_) => info!("Saved stats history to {}", STATS_HISTORY_CSV_PATH),
+        Err(e) => error!("Failed to write stats history: {}", e),
+    }
+}
+
+fn history_export_system(
+    mut exit_events: EventReader<AppExit>,
+    settings: Res<Settings>,
+    generation_seed: Res<GenerationSeed>,
+    history: Res<SimStatsHistory>,
+) {
+    if exit_events.iter().next().is_none() || !settings.is_export_history {
+        return;
+    }
+
+    // Same file as the per generation writes when --stats-out is given
+    let path = settings.stats_out_path.as_deref().unwrap_or(Path::new(STATS_HISTORY_PATH));
+    match write_history(path, &settings, &generation_seed, &history) {
+        Ok(_) => info!("Saved stats history to {}", path.display()),
+        Err(e) => error!("Failed to write stats history: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ended(generation_count: u32, seed: &GenerationSeed) -> SimStatsSnapshot {
+        let sim_stats = SimStats {
+            generation_count,
+            ..default()
+        };
+        SimStatsSnapshot::ended(&sim_stats, seed, 3, 1)
+    }
+
+    #[test]
+    fn snapshot_takes_ended_generations_seed() {
+        let seed = GenerationSeed { base_seed: 100 };
+        let snapshot = ended(4, &seed);
+        assert_eq!(snapshot.generation, 4);
+        assert_eq!(snapshot.seed, seed.for_generation(4));
+        assert_eq!((snapshot.num_cars_alive, snapshot.num_cars_finished), (3, 1));
+    }
+
+    #[test]
+    fn restart_drops_old_snapshots() {
+        let seed = GenerationSeed::default();
+        let mut history = SimStatsHistory::default();
+        (0..5).for_each(|generation| history.record(ended(generation, &seed)));
+        assert_eq!(history.0.len(), 5);
+        history.record(ended(0, &seed));
+        history.record(ended(1, &seed));
+        let generations: Vec<_> = history.0.iter().map(|snapshot| snapshot.generation).collect();
+        assert_eq!(generations, [0, 1]);
+    }
+}
@@ -1,11 +1,17 @@
+pub mod batch;
 pub mod car;
 pub mod configs;
 pub mod enemy;
 pub mod gui;
+pub mod headless;
+pub mod history;
 pub mod nn;
 pub mod pathfinding;
 pub mod population;
+pub mod replay;
 pub mod resources;
+pub mod save;
+pub mod track;
 
 pub use configs::*;
 pub use resources::*;
\ No newline at end of file
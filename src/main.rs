@@ -1,108 +1,388 @@
 use bevy::{
     diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    ecs::query::QuerySingleError,
     math::vec3,
     prelude::*,
     window::{PresentMode, WindowMode},
 };
 use bevy_inspector_egui::{bevy_egui::EguiPlugin, DefaultInspectorConfigPlugin};
 use bevy_pancam::{PanCam, PanCamPlugin};
+use bevy_prototype_debug_lines::DebugLinesPlugin;
 use bevy_rapier2d::{
-    prelude::{Collider, NoUserData, RapierConfiguration, RapierPhysicsPlugin, RigidBody},
+    prelude::{
+        Collider, NoUserData, RapierConfiguration, RapierPhysicsPlugin, RigidBody, TimestepMode,
+    },
     render::RapierDebugRenderPlugin,
 };
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
 use steering::{
-    car::{Car, CarPlugin},
+    batch::run_batch_training,
+    car::{Brain, Car, CarPlugin},
     gui::GuiPlugin,
+    headless::{write_bench_report, BenchReport, HeadlessPlugin},
+    history::HistoryPlugin,
     population::PopulationPlugin,
-    pathfinding::{PathfindingPlugin, AStarPopulationPlugin},
+    pathfinding::{AStarAgent, AStarInteractionPlugin, AStarPopulationPlugin, PathfindingPlugin},
+    replay::{load_recording, RecordPlugin, Recording, Replay, ReplayPlugin},
+    track::{load_track, spawn_goal, spawn_track, Track},
 };
 use steering::{
     enemy::{spawn_bound_trucks, EnemyPlugin},
     *,
 };
 
+// Options given on the command line, anything missing keeps its default
+#[derive(Default)]
+struct CliArgs {
+    algorithm: Option<AlgorithmMode>,
+    headless: bool,
+    // `bench` subcommand, runs both algorithms headless and compares them
+    bench: bool,
+    bench_out: Option<PathBuf>,
+    // `batch` subcommand, trains the NN headless on the threaded evaluator
+    batch: bool,
+    threads: Option<usize>,
+    compare_serial: bool,
+    generations: Option<u32>,
+    num_cars: Option<u32>,
+    num_rays: Option<u32>,
+    hidden_layers: Option<Vec<usize>>,
+    seed: Option<u64>,
+    serial_search: bool,
+    track: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    stats_out: Option<PathBuf>,
+    export_history: bool,
+    resume: Option<PathBuf>,
+}
+
 fn main() {
-    // Ask user to select algorithm
-    let algorithm_choice = select_algorithm();
+    let args = parse_args();
+
+    // Only ask for the algorithm when it wasn't given as an argument,
+    // headless runs can't prompt so they default to the neural network,
+    // benches run both and replays don't run either algorithm
+    let is_unattended = args.headless || args.bench || args.batch || args.replay.is_some();
+    let mode = match (args.algorithm, is_unattended) {
+        (Some(mode), _) => mode,
+        (None, true) => AlgorithmMode::NeuralNetwork,
+        (None, false) => select_algorithm(),
+    };
+
+    let mut settings = Settings::default();
+    if let Some(num_cars) = args.num_cars {
+        settings.num_cars = num_cars;
+    }
+    if let Some(num_rays) = args.num_rays {
+        settings.sensor_ray_count = num_rays;
+    }
+    if let Some(hidden_layers) = args.hidden_layers {
+        settings.network_arch.hidden = hidden_layers;
+    }
+    settings.is_parallel_search = !args.serial_search;
+    settings.stats_out_path = args.stats_out;
+    settings.is_export_history = args.export_history;
+    settings.resume_path = args.resume;
+    let mut generation_seed = GenerationSeed::default();
+    if let Some(seed) = args.seed {
+        generation_seed.base_seed = seed;
+        settings.rng_seed = Some(seed);
+    }
+    let sim_rng = SimRng::new(settings.rng_seed);
+    let track = args.track.map(|path| match load_track(&path) {
+        Ok(track) => track,
+        Err(e) => {
+            eprintln!("Failed to load track {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    });
     
     // Build the app based on choice
-    if algorithm_choice == 2 {
-        run_astar_app();
+    if let Some(path) = args.replay {
+        match load_recording(&path) {
+            Ok(recording) => run_replay_app(recording, settings, track),
+            Err(e) => {
+                eprintln!("Failed to load recording {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    } else if args.bench {
+        let generations = args.generations.unwrap_or(HEADLESS_DEFAULT_GENERATIONS);
+        let out = args.bench_out.unwrap_or_else(|| PathBuf::from(BENCH_REPORT_PATH));
+        run_bench(generations, &out, settings, generation_seed, track);
+    } else if args.batch {
+        let generations = args.generations.unwrap_or(HEADLESS_DEFAULT_GENERATIONS);
+        let threads = args
+            .threads
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+        let result = run_batch_training(
+            settings,
+            &generation_seed,
+            sim_rng,
+            track.as_ref(),
+            generations,
+            threads,
+            args.compare_serial,
+        );
+        if let Err(e) = result {
+            eprintln!("Batch training failed: {}", e);
+            std::process::exit(1);
+        }
+    } else if args.headless {
+        let generations = args.generations.unwrap_or(HEADLESS_DEFAULT_GENERATIONS);
+        run_headless_app(mode, generations, settings, generation_seed, sim_rng, track, None);
     } else {
-        run_neural_network_app();
+        run_app(mode, settings, generation_seed, sim_rng, track);
+    }
+}
+
+fn parse_args() -> CliArgs {
+    let mut cli_args = CliArgs::default();
+    let mut args = std::env::args().skip(1).peekable();
+    match args.peek().map(String::as_str) {
+        Some("bench") => cli_args.bench = true,
+        Some("batch") => cli_args.batch = true,
+        _ => {}
+    }
+    if cli_args.bench || cli_args.batch {
+        args.next();
+    }
+    while let Some(arg) = args.next() {
+        if arg == "--headless" {
+            cli_args.headless = true;
+            continue;
+        }
+        if arg == "--serial-search" {
+            cli_args.serial_search = true;
+            continue;
+        }
+        if arg == "--compare-serial" {
+            cli_args.compare_serial = true;
+            continue;
+        }
+        if arg == "--export-history" {
+            cli_args.export_history = true;
+            continue;
+        }
+
+        let value = args.next();
+        match (arg.as_str(), value.as_deref()) {
+            ("--algorithm", Some("nn")) => cli_args.algorithm = Some(AlgorithmMode::NeuralNetwork),
+            ("--algorithm", Some("astar")) => cli_args.algorithm = Some(AlgorithmMode::AStar),
+            ("--cars", Some(n)) => match n.parse() {
+                Ok(n) => cli_args.num_cars = Some(n),
+                Err(_) => eprintln!("Ignoring invalid car count: {}", n),
+            },
+            ("--rays", Some(n)) => match n.parse() {
+                Ok(n) => cli_args.num_rays = Some(n),
+                Err(_) => eprintln!("Ignoring invalid ray count: {}", n),
+            },
+            // Comma separated sizes, e.g. 8,8 for two hidden layers of 8
+            ("--hidden", Some(sizes)) => {
+                let parsed: Result<Vec<usize>, _> =
+                    sizes.split(',').map(|n| n.trim().parse()).collect();
+                match parsed {
+                    Ok(parsed) if parsed.iter().all(|&n| n > 0) => {
+                        cli_args.hidden_layers = Some(parsed)
+                    }
+                    _ => eprintln!("Ignoring invalid hidden layer sizes: {}", sizes),
+                }
+            }
+            ("--threads", Some(n)) => match n.parse() {
+                Ok(n) if n > 0 => cli_args.threads = Some(n),
+                _ => eprintln!("Ignoring invalid thread count: {}", n),
+            },
+            ("--generations", Some(n)) => match n.parse() {
+                Ok(n) => cli_args.generations = Some(n),
+                Err(_) => eprintln!("Ignoring invalid generation count: {}", n),
+            },
+            ("--seed", Some(s)) => match s.parse() {
+                Ok(s) => cli_args.seed = Some(s),
+                Err(_) => eprintln!("Ignoring invalid seed: {}", s),
+            },
+            ("--track", Some(path)) => cli_args.track = Some(PathBuf::from(path)),
+            ("--replay", Some(path)) => cli_args.replay = Some(PathBuf::from(path)),
+            ("--stats-out", Some(path)) => cli_args.stats_out = Some(PathBuf::from(path)),
+            ("--resume", Some(path)) => cli_args.resume = Some(PathBuf::from(path)),
+            ("--bench-out", Some(path)) => cli_args.bench_out = Some(PathBuf::from(path)),
+            _ => {
+                eprintln!(
+                    "Usage: steering [bench|batch] [--algorithm astar|nn] [--cars N] [--rays N] \
+                     [--hidden N,N] [--seed S] [--headless] [--generations N] [--serial-search] \
+                     [--track FILE] [--replay FILE] [--stats-out FILE] [--export-history] \
+                     [--resume FILE] [--bench-out FILE] [--threads N] [--compare-serial]"
+                );
+                std::process::exit(1);
+            }
+        }
     }
+
+    cli_args
 }
 
-fn run_neural_network_app() {
-    println!("Running Neural Network + Genetic Algorithm");
+// Both algorithms live in the one app, only the systems of the current
+// AlgorithmMode run and the GUI can switch between them
+fn run_app(
+    mode: AlgorithmMode,
+    settings: Settings,
+    generation_seed: GenerationSeed,
+    sim_rng: SimRng,
+    track: Option<Track>,
+) {
+    println!("Running {}", mode.label());
     
-    App::new()
-        .add_plugins(
-            DefaultPlugins
-                .set(ImagePlugin::default_nearest())
-                .set(WindowPlugin {
-                    primary_window: Some(Window {
-                        resizable: false,
-                        focused: true,
-                        resolution: (WINDOW_WIDTH, WINDOW_HEIGHT).into(),
-                        ..default()
-                    }),
-                    ..default()
-                }),
-        )
-        .add_plugin(PanCamPlugin::default())
-        .add_plugin(DefaultInspectorConfigPlugin)
-        .add_plugin(EguiPlugin)
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0))
-        .add_plugin(CarPlugin)
-        .add_plugin(EnemyPlugin)
-        .add_plugin(PopulationPlugin)
-        .add_plugin(GuiPlugin)
+    let mut app = App::new();
+    add_window_plugins(&mut app);
+    add_simulation_plugins(&mut app, mode, settings, generation_seed, sim_rng, track);
+    add_algorithm_plugins(&mut app);
+    app.add_plugin(AStarInteractionPlugin);
+    add_gui_plugins(&mut app);
+    app.run();
+}
+
+fn run_headless_app(
+    mode: AlgorithmMode,
+    generations: u32,
+    settings: Settings,
+    generation_seed: GenerationSeed,
+    sim_rng: SimRng,
+    track: Option<Track>,
+    report: Option<BenchReport>,
+) {
+    println!("Running headless for {} generations", generations);
+
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(AssetPlugin::default());
+    add_simulation_plugins(&mut app, mode, settings, generation_seed, sim_rng, track);
+    add_algorithm_plugins(&mut app);
+    app.add_plugin(HeadlessPlugin { generations, report }).run();
+}
+
+// A* once to completion then `generations` of the NN, each in its own
+// headless app built from the same seed and track so both face the same
+// goal and obstacles
+fn run_bench(
+    generations: u32,
+    out: &Path,
+    settings: Settings,
+    generation_seed: GenerationSeed,
+    track: Option<Track>,
+) {
+    let report = BenchReport::default();
+    let runs = [(AlgorithmMode::AStar, 1), (AlgorithmMode::NeuralNetwork, generations)];
+    for (mode, generations) in runs {
+        let seed = GenerationSeed {
+            base_seed: generation_seed.base_seed,
+        };
+        let sim_rng = SimRng::new(settings.rng_seed);
+        let (settings, track, report) = (settings.clone(), track.clone(), report.clone());
+        run_headless_app(mode, generations, settings, seed, sim_rng, track, Some(report));
+    }
+
+    if let Err(e) = write_bench_report(out, &report) {
+        eprintln!("Failed to write bench report {}: {}", out.display(), e);
+        std::process::exit(1);
+    }
+}
+
+// Plays a recorded run back, no physics or AI, the log drives every car
+fn run_replay_app(recording: Recording, settings: Settings, track: Option<Track>) {
+    println!("Replaying recorded run");
+
+    let mut app = App::new();
+    add_window_plugins(&mut app);
+    if let Some(track) = track {
+        app.insert_resource(track);
+    }
+    app.insert_resource(Replay::new(&recording))
+        .insert_resource(recording)
+        .insert_resource(settings)
+        .insert_resource(FrameStep::default())
+        .insert_resource(MaxDistanceTravelled(0.0))
         .insert_resource(ClearColor(Color::rgb_u8(36, 36, 36)))
-        .add_startup_system(setup)
+        .add_plugin(ReplayPlugin)
+        .add_startup_system(setup_replay)
+        .add_startup_system(setup_camera)
         .add_system(bevy::window::close_on_esc)
         .add_system(camera_follow_system)
-        .add_system(settings_system)
         .run();
 }
 
-fn run_astar_app() {
-    println!("Running A* Pathfinding Algorithm");
-    
-    App::new()
-        .add_plugins(
-            DefaultPlugins
-                .set(ImagePlugin::default_nearest())
-                .set(WindowPlugin {
-                    primary_window: Some(Window {
-                        resizable: false,
-                        focused: true,
-                        resolution: (WINDOW_WIDTH, WINDOW_HEIGHT).into(),
-                        ..default()
-                    }),
+fn add_window_plugins(app: &mut App) {
+    app.add_plugins(
+        DefaultPlugins
+            .set(ImagePlugin::default_nearest())
+            .set(WindowPlugin {
+                primary_window: Some(Window {
+                    resizable: false,
+                    focused: true,
+                    resolution: (WINDOW_WIDTH, WINDOW_HEIGHT).into(),
                     ..default()
                 }),
-        )
-        .add_plugin(PanCamPlugin::default())
-        .add_plugin(DefaultInspectorConfigPlugin)
-        .add_plugin(EguiPlugin)
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0))
+                ..default()
+            }),
+    )
+    .add_plugin(PanCamPlugin::default())
+    .add_plugin(DefaultInspectorConfigPlugin)
+    .add_plugin(EguiPlugin)
+    .add_plugin(DebugLinesPlugin::default())
+    .add_plugin(FrameTimeDiagnosticsPlugin);
+}
+
+// Everything the simulation needs to run, with or without a window
+fn add_simulation_plugins(
+    app: &mut App,
+    mode: AlgorithmMode,
+    settings: Settings,
+    generation_seed: GenerationSeed,
+    sim_rng: SimRng,
+    track: Option<Track>,
+) {
+    // The track's walls go straight into every A* grid
+    if let Some(track) = track {
+        app.insert_resource(track.grid_config()).insert_resource(track);
+    }
+    app.add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0))
+        .insert_resource(BrainToDisplay::default())
+        .insert_resource(BrainWeightsToDisplay::default())
+        .insert_resource(SimStats::default())
+        .insert_resource(settings)
+        .insert_resource(mode)
+        .insert_resource(generation_seed)
+        .insert_resource(sim_rng)
+        .insert_resource(FrameStep::default())
         .add_plugin(CarPlugin)
         .add_plugin(EnemyPlugin)
+        .add_plugin(HistoryPlugin)
+        .add_plugin(RecordPlugin)
+        .add_startup_system(setup)
+        .add_system(settings_system)
+        .add_system(algorithm_mode_system)
+        .add_system(time_scale_system)
+        .add_system(pause_system.in_base_set(CoreSet::PreUpdate));
+}
+
+// Each algorithm's plugins gate their own systems on the AlgorithmMode
+fn add_algorithm_plugins(app: &mut App) {
+    app.add_plugin(PopulationPlugin)
         .add_plugin(PathfindingPlugin)
-        .add_plugin(AStarPopulationPlugin)
-        .add_plugin(GuiPlugin)
+        .add_plugin(AStarPopulationPlugin);
+}
+
+fn add_gui_plugins(app: &mut App) {
+    app.add_plugin(GuiPlugin)
         .insert_resource(ClearColor(Color::rgb_u8(36, 36, 36)))
-        .add_startup_system(setup)
+        .add_startup_system(setup_camera)
         .add_system(bevy::window::close_on_esc)
-        .add_system(camera_follow_system)
-        .add_system(settings_system)
-        .run();
+        .add_system(camera_follow_system);
 }
 
-fn select_algorithm() -> i32 {
+fn select_algorithm() -> AlgorithmMode {
     loop {
         println!("\n=== AI Car Simulation ===");
         println!("Select algorithm to test:");
@@ -116,7 +396,8 @@ fn select_algorithm() -> i32 {
         match io::stdin().read_line(&mut input) {
             Ok(_) => {
                 match input.trim().parse::<i32>() {
-                    Ok(1) | Ok(2) => return input.trim().parse::<i32>().unwrap(),
+                    Ok(1) => return AlgorithmMode::NeuralNetwork,
+                    Ok(2) => return AlgorithmMode::AStar,
                     _ => println!("Please enter 1 or 2"),
                 }
             }
@@ -129,26 +410,95 @@ fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut rapier_config: ResMut<RapierConfiguration>,
+    track: Option<Res<Track>>,
 ) {
     rapier_config.gravity = Vec2::ZERO;
 
+    match &track {
+        Some(track) => spawn_track(&mut commands, track),
+        None => spawn_roads(&mut commands, &asset_server),
+    }
+    spawn_goal(&mut commands, track.as_deref());
+    spawn_bound_trucks(&mut commands, &asset_server);
+}
+
+fn setup_replay(mut commands: Commands, asset_server: Res<AssetServer>, track: Option<Res<Track>>) {
+    match track {
+        Some(track) => spawn_track(&mut commands, &track),
+        None => spawn_roads(&mut commands, &asset_server),
+    }
+}
+
+fn setup_camera(mut commands: Commands) {
     commands
         .spawn(Camera2dBundle {
             transform: Transform::from_xyz(WINDOW_WIDTH / 2.0, WINDOW_HEIGHT / 2.0, 0.0),
             ..default()
         })
-        .insert(PanCam::default());
-
-    spawn_roads(&mut commands, &asset_server);
-    spawn_bound_trucks(&mut commands, &asset_server);
+        .insert((PanCam::default(), MainCamera));
 }
 
 fn camera_follow_system(
+    mut commands: Commands,
     settings: Res<Settings>,
     max_distance_travelled: Res<MaxDistanceTravelled>,
-    mut cam_query: Query<(&Camera, &mut Transform), Without<Car>>,
+    follow_target: Option<Res<FollowTarget>>,
+    mut cam_query: Query<
+        (&mut OrthographicProjection, &mut Transform),
+        (With<MainCamera>, Without<Car>),
+    >,
+    car_query: Query<&Transform, With<Car>>,
+    target_query: Query<&Transform, Without<Camera>>,
+    mut is_warned: Local<bool>,
 ) {
-    let (_, mut cam_transform) = cam_query.get_single_mut().unwrap();
+    // None yet on the first frames, only warn if it's something else
+    let (mut projection, mut cam_transform) = match cam_query.get_single_mut() {
+        Ok(camera) => camera,
+        Err(QuerySingleError::NoEntities(_)) => return,
+        Err(e) => {
+            if !*is_warned {
+                warn!("Camera follow disabled: {}", e);
+                *is_warned = true;
+            }
+            return;
+        }
+    };
+
+    // Selected car wins, crashed cars are still followed until despawned
+    if let Some(follow_target) = follow_target {
+        match target_query.get(follow_target.0) {
+            Ok(target) => {
+                let z = cam_transform.translation.z;
+                cam_transform.translation = cam_transform
+                    .translation
+                    .lerp(target.translation.truncate().extend(z), 0.05);
+                return;
+            }
+            Err(_) => commands.remove_resource::<FollowTarget>(),
+        }
+    }
+
+    // Bounding box of every car still driving
+    let bounds = car_query.iter().fold(None, |bounds: Option<(Vec2, Vec2)>, t| {
+        let pos = t.translation.truncate();
+        Some(match bounds {
+            Some((min, max)) => (min.min(pos), max.max(pos)),
+            None => (pos, pos),
+        })
+    });
+    if settings.camera_fit_all && car_query.iter().len() > 1 {
+        if let Some((min, max)) = bounds {
+            let center = (min + max) / 2.0;
+            let size = max - min + Vec2::splat(2.0 * CAMERA_FIT_MARGIN);
+            let scale = (size.x / WINDOW_WIDTH).max(size.y / WINDOW_HEIGHT).max(1.0);
+            cam_transform.translation = cam_transform
+                .translation
+                .lerp(center.extend(cam_transform.translation.z), 0.05);
+            projection.scale += (scale - projection.scale) * 0.05;
+            return;
+        }
+    }
+
     if settings.is_camera_follow {
         cam_transform.translation = cam_transform.translation.lerp(
             vec3(cam_transform.translation.x, max_distance_travelled.0, 0.0),
@@ -159,7 +509,7 @@ fn camera_follow_system(
 
 fn spawn_roads(commands: &mut Commands, asset_server: &AssetServer) {
     // Road
-    let rx = WINDOW_WIDTH / 2.0 - 30.0;
+    let rx = ROAD_CENTER_X;
     let mut ry = ROAD_SPRITE_H / 2.0 * SPRITE_SCALE_FACTOR;
     for _ in 0..NUM_ROAD_TILES {
         commands.spawn(SpriteBundle {
@@ -170,7 +520,7 @@ fn spawn_roads(commands: &mut Commands, asset_server: &AssetServer) {
         });
         ry += ROAD_SPRITE_H * SPRITE_SCALE_FACTOR;
     }
-    let road_end_y = ry - ROAD_SPRITE_H * SPRITE_SCALE_FACTOR + 800.0;
+    let road_end_y = ROAD_END_Y;
 
     // end checker board
     commands.spawn(SpriteBundle {
@@ -183,7 +533,7 @@ fn spawn_roads(commands: &mut Commands, asset_server: &AssetServer) {
     // Road colliders
     // left
     let ry = 5.0 * ROAD_SPRITE_H * SPRITE_SCALE_FACTOR;
-    let rx_min = ROAD_SPRITE_W / 2.0 * SPRITE_SCALE_FACTOR + 238.0;
+    let rx_min = ROAD_LEFT_X;
     commands.spawn((
         SpriteBundle {
             transform: Transform::from_xyz(rx_min, ry, 0.0).with_scale(vec3(0.5, 0.5, 1.0)),
@@ -194,9 +544,10 @@ fn spawn_roads(commands: &mut Commands, asset_server: &AssetServer) {
             5.0,
             ROAD_SPRITE_H * SPRITE_SCALE_FACTOR * NUM_ROAD_TILES as f32 * 5.0,
         ),
+        OBSTACLE_COLLISION_GROUPS,
     ));
     // right
-    let rx_max = ROAD_SPRITE_W * SPRITE_SCALE_FACTOR + 248.0;
+    let rx_max = ROAD_RIGHT_X;
     commands.spawn((
         SpriteBundle {
             transform: Transform::from_xyz(rx_max, ry, 0.0).with_scale(vec3(0.5, 0.5, 1.0)),
@@ -207,6 +558,7 @@ fn spawn_roads(commands: &mut Commands, asset_server: &AssetServer) {
             5.0,
             ROAD_SPRITE_H * SPRITE_SCALE_FACTOR * NUM_ROAD_TILES as f32 * 5.0,
         ),
+        OBSTACLE_COLLISION_GROUPS,
     ));
     // top
     commands.spawn((
@@ -216,9 +568,70 @@ fn spawn_roads(commands: &mut Commands, asset_server: &AssetServer) {
         },
         RigidBody::Fixed,
         Collider::cuboid(500.0 * SPRITE_SCALE_FACTOR, 10.0),
+        OBSTACLE_COLLISION_GROUPS,
     ));
 }
 
+// Latches a requested step before the frame's systems run, so it covers
+// exactly one update no matter when the button was clicked
+fn pause_system(
+    mut settings: ResMut<Settings>,
+    mut frame_step: ResMut<FrameStep>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    frame_step.0 = settings.paused && settings.step_frame;
+    if settings.step_frame {
+        settings.step_frame = false;
+    }
+    rapier_config.physics_pipeline_active = !settings.paused || frame_step.0;
+}
+
+fn time_scale_system(
+    mut settings: ResMut<Settings>,
+    mut time: ResMut<Time>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    let time_scale = settings.time_scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+    if time_scale != settings.time_scale {
+        settings.time_scale = time_scale;
+    }
+    // Timers and movement read the scaled delta
+    time.set_relative_speed(time_scale);
+    // Physics already sees the scaled delta, let a frame step further but
+    // split it so each substep stays at the usual 1/60 s
+    rapier_config.timestep_mode = TimestepMode::Variable {
+        max_dt: time_scale / 60.0,
+        time_scale: 1.0,
+        substeps: time_scale.ceil() as usize,
+    };
+}
+
+// Takes the previous algorithm's cars off the road when the mode changes,
+// the new mode's setup puts its own population down the same frame
+fn algorithm_mode_system(
+    mut commands: Commands,
+    mode: Res<AlgorithmMode>,
+    mut sim_stats: ResMut<SimStats>,
+    mut max_distance_travelled: ResMut<MaxDistanceTravelled>,
+    mut brain_on_display: ResMut<BrainToDisplay>,
+    car_query: Query<Entity, Or<(With<Brain>, With<AStarAgent>)>>,
+) {
+    if !mode.is_changed() || mode.is_added() {
+        return;
+    }
+
+    info!("Switching to {}", mode.label());
+    car_query.for_each(|car| commands.entity(car).despawn_recursive());
+    commands.remove_resource::<FollowTarget>();
+    *sim_stats = SimStats::default();
+    max_distance_travelled.0 = 0.0;
+    brain_on_display.0.clear();
+}
+
 fn settings_system(
     mut commands: Commands,
     mut settings: ResMut<Settings>,
@@ -1,21 +1,49 @@
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-const BRAIN_MUTATION_RATE: f32 = 5.0;
-const BRAIN_MUTATION_VARIATION: f32 = 0.5;
+use crate::resources::CrossoverStrategy;
 
-#[derive(Clone)]
+// Layer sizes a brain is built with, the inputs follow the sensor count
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct NetworkArch {
+    pub inputs: usize,
+    pub hidden: Vec<usize>,
+    pub outputs: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Net {
     n_inputs: usize,
     layers: Vec<Layer>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 struct Layer {
     nodes: Vec<Vec<f64>>,
 }
 
+impl NetworkArch {
+    pub fn layer_sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![self.inputs];
+        sizes.extend(self.hidden.iter().copied());
+        sizes.push(self.outputs);
+        sizes
+    }
+}
+
+impl std::fmt::Display for NetworkArch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let sizes: Vec<String> = self.layer_sizes().iter().map(|s| s.to_string()).collect();
+        write!(f, "{}", sizes.join("-"))
+    }
+}
+
 impl Net {
-    pub fn new(layer_sizes: Vec<usize>) -> Self {
+    pub fn from_arch(arch: &NetworkArch, rng: &mut impl Rng) -> Self {
+        Self::new(arch.layer_sizes(), rng)
+    }
+
+    pub fn new(layer_sizes: Vec<usize>, rng: &mut impl Rng) -> Self {
         if layer_sizes.len() < 2 {
             panic!("Need at least 2 layers");
         }
@@ -30,7 +58,7 @@ impl Net {
         let mut prev_layer_size = first_layer_size;
 
         for &layer_size in layer_sizes[1..].iter() {
-            layers.push(Layer::new(layer_size, prev_layer_size));
+            layers.push(Layer::new(layer_size, prev_layer_size, rng));
             prev_layer_size = layer_size;
         }
 
@@ -55,14 +83,98 @@ impl Net {
         outputs
     }
 
-    pub fn mutate(&mut self) {
-        self.layers.iter_mut().for_each(|l| l.mutate());
+    pub fn n_inputs(&self) -> usize {
+        self.n_inputs
+    }
+
+    // At least one layer, and every node holding a weight per node of the
+    // layer before plus its bias. Nets built here always are, loaded ones
+    // have to be checked before arch() or predict() can trust them
+    pub fn is_well_formed(&self) -> bool {
+        let mut prev_layer_size = self.n_inputs;
+        for layer in self.layers.iter() {
+            let is_bad_node = |node: &Vec<f64>| node.len() != prev_layer_size + 1;
+            if layer.nodes.is_empty() || layer.nodes.iter().any(is_bad_node) {
+                return false;
+            }
+            prev_layer_size = layer.nodes.len();
+        }
+        !self.layers.is_empty()
+    }
+
+    pub fn arch(&self) -> NetworkArch {
+        let sizes: Vec<usize> = self.layers.iter().map(|l| l.nodes.len()).collect();
+        NetworkArch {
+            inputs: self.n_inputs,
+            hidden: sizes[..sizes.len() - 1].to_vec(),
+            outputs: *sizes.last().unwrap(),
+        }
+    }
+
+    pub fn weights(&self) -> Vec<Vec<Vec<f64>>> {
+        self.layers.iter().map(|l| l.nodes.clone()).collect()
+    }
+
+    // Each weight is nudged with probability `rate` by up to +/- `strength`
+    pub fn mutate(&mut self, rng: &mut impl Rng, rate: f32, strength: f32) {
+        self.layers.iter_mut().for_each(|l| l.mutate(rng, rate, strength));
+    }
+
+    // Child with this net's layout, taking some weights from `other`.
+    // Weights are only copied in place so the layout never changes, parents
+    // of different layouts don't line up and the child is just a copy
+    pub fn crossover(&self, other: &Net, strategy: CrossoverStrategy, rng: &mut impl Rng) -> Net {
+        if self.arch() != other.arch() {
+            return self.clone();
+        }
+
+        let num_weights = self.num_weights();
+        let (cut_a, cut_b) = match strategy {
+            CrossoverStrategy::SinglePoint => (rng.gen_range(0..=num_weights), num_weights),
+            CrossoverStrategy::TwoPoint => {
+                let a = rng.gen_range(0..=num_weights);
+                let b = rng.gen_range(0..=num_weights);
+                (a.min(b), a.max(b))
+            }
+            CrossoverStrategy::Uniform => (0, 0),
+        };
+
+        let mut child = self.clone();
+        let weights = child.layers.iter_mut().flat_map(|l| l.nodes.iter_mut().flatten());
+        let other_weights = other.layers.iter().flat_map(|l| l.nodes.iter().flatten());
+        for (i, (weight, other_weight)) in weights.zip(other_weights).enumerate() {
+            let is_from_other = match strategy {
+                CrossoverStrategy::Uniform => rng.gen_bool(0.5),
+                _ => (cut_a..cut_b).contains(&i),
+            };
+            if is_from_other {
+                *weight = *other_weight;
+            }
+        }
+
+        child
+    }
+
+    // Every weight laid out layer by layer, node by node
+    pub fn flat_weights(&self) -> Vec<f64> {
+        self.layers.iter().flat_map(|l| l.nodes.iter().flatten()).copied().collect()
+    }
+
+    pub fn num_weights(&self) -> usize {
+        self.layers.iter().flat_map(|l| l.nodes.iter()).map(|n| n.len()).sum()
+    }
+
+    pub fn clamp_weights(&mut self, limit: f64) {
+        self.layers.iter_mut().for_each(|l| l.clamp_weights(limit));
+    }
+
+    pub fn decay_weights(&mut self, decay: f64) {
+        self.layers.iter_mut().for_each(|l| l.decay_weights(decay));
     }
 }
 
 impl Layer {
-    fn new(layer_size: usize, prev_layer_size: usize) -> Self {
-        let mut rng = rand::thread_rng();
+    fn new(layer_size: usize, prev_layer_size: usize, rng: &mut impl Rng) -> Self {
         let mut nodes: Vec<Vec<f64>> = Vec::new();
 
         for _ in 0..layer_size {
@@ -86,15 +198,36 @@ impl Layer {
         layer_results
     }
 
-    fn mutate(&mut self) {
-        let mut rng = rand::thread_rng();
+    fn mutate(&mut self, rng: &mut impl Rng, rate: f32, strength: f32) {
+        // Empty range, nothing to nudge by
+        if strength <= 0.0 {
+            return;
+        }
+
         for n in self.nodes.iter_mut() {
             for val in n.iter_mut() {
-                if rng.gen_range(0.0..1.0) >= BRAIN_MUTATION_RATE {
+                if rng.gen_range(0.0..1.0) >= rate {
                     continue;
                 }
 
-                *val += rng.gen_range(-BRAIN_MUTATION_VARIATION..BRAIN_MUTATION_VARIATION) as f64;
+                *val += rng.gen_range(-strength..strength) as f64;
+            }
+        }
+    }
+
+    fn clamp_weights(&mut self, limit: f64) {
+        for n in self.nodes.iter_mut() {
+            for val in n.iter_mut() {
+                *val = val.clamp(-limit, limit);
+            }
+        }
+    }
+
+    fn decay_weights(&mut self, decay: f64) {
+        // Shrink every weight towards zero, L2 style
+        for n in self.nodes.iter_mut() {
+            for val in n.iter_mut() {
+                *val *= 1.0 - decay;
             }
         }
     }
@@ -113,3 +246,55 @@ impl Layer {
         1f64 / (1f64 + (-y).exp())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn parents(rng: &mut StdRng) -> (Net, Net) {
+        (Net::new(vec![20, 30, 10], rng), Net::new(vec![20, 30, 10], rng))
+    }
+
+    // Which parent every child weight came from, true for `b`
+    fn origins(child: &Net, a: &Net, b: &Net) -> Vec<bool> {
+        let (a, b) = (a.flat_weights(), b.flat_weights());
+        child
+            .flat_weights()
+            .iter()
+            .enumerate()
+            .map(|(i, w)| {
+                assert!(*w == a[i] || *w == b[i], "weight {} from neither parent", i);
+                *w == b[i]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn uniform_crossover_takes_half_from_each() {
+        let mut rng = StdRng::seed_from_u64(294);
+        let (a, b) = parents(&mut rng);
+        let child = a.crossover(&b, CrossoverStrategy::Uniform, &mut rng);
+        assert!(child.arch() == a.arch());
+        let from_b = origins(&child, &a, &b).iter().filter(|&&is_b| is_b).count();
+        let share = from_b as f32 / a.num_weights() as f32;
+        assert!((0.45..0.55).contains(&share), "{} of the weights from b", share);
+    }
+
+    #[test]
+    fn point_crossovers_copy_contiguous_runs() {
+        let mut rng = StdRng::seed_from_u64(294);
+        let (a, b) = parents(&mut rng);
+        for (strategy, max_runs) in [
+            (CrossoverStrategy::SinglePoint, 2),
+            (CrossoverStrategy::TwoPoint, 3),
+        ] {
+            let child = a.crossover(&b, strategy, &mut rng);
+            assert!(child.arch() == a.arch());
+            let origins = origins(&child, &a, &b);
+            let runs = 1 + origins.windows(2).filter(|pair| pair[0] != pair[1]).count();
+            assert!(runs <= max_runs);
+        }
+    }
+}
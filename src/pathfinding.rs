@@ -1,15 +1,29 @@
 use bevy::{
+    diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
     math::vec3,
     prelude::*,
     utils::HashMap,
+    window::PrimaryWindow,
 };
+use bevy_inspector_egui::{bevy_egui::EguiContexts, egui};
+use bevy_prototype_debug_lines::DebugLines;
 use bevy_rapier2d::prelude::*;
-use std::collections::{BinaryHeap, HashSet};
-use std::cmp::Ordering;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BinaryHeap, HashSet, VecDeque};
+use std::cmp::{Ordering, Reverse};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::Path;
+use std::sync::atomic::{self, AtomicUsize};
+use std::time::{Duration, Instant};
 
-use crate::car::Car;
+use crate::car::{Car, CarOutcome, CarTimeout, Trail};
 use crate::configs::*;
+use crate::enemy::EnemyType;
+use crate::history::{SimStatsHistory, SimStatsSnapshot};
 use crate::resources::*;
+use crate::track::{goal_position, spawn_area, Track};
 
 pub struct PathfindingPlugin;
 
@@ -18,6 +32,29 @@ pub struct AStarCar {
     pub path: Vec<Vec2>,
     pub current_target: usize,
     pub recalculate_timer: Timer,
+    // Rescans obstacles between replans, replanning only if the path is hit
+    pub obstacle_check_timer: Timer,
+    pub speed: f32,
+    // Fastest turn toward the target in radians per second, when driving
+    // straight at it
+    pub turn_rate: f32,
+    // Zero lets the car turn on the spot
+    pub min_turn_radius: f32,
+    pub arrival_radius: f32,
+    // Distance along the path to the point being steered toward, zero
+    // steers at the waypoint itself
+    pub look_ahead: f32,
+    // Back up toward targets more than 90 degrees behind instead of
+    // looping around to face them
+    pub allow_reverse: bool,
+    // Set when the last search found no route and the car drives blind
+    pub is_path_blocked: bool,
+    // Frames spent near stuck_anchor without reaching stuck_target
+    pub stuck_frames: u32,
+    pub stuck_anchor: Vec2,
+    pub stuck_target: usize,
+    // Replan on the next scan, bypassing the path cache
+    pub force_replan: bool,
 }
 
 #[derive(Component)]
@@ -27,12 +64,91 @@ pub struct AStarAgent; // Marker component to distinguish A* cars from neural ne
 pub struct PathfindingBrain {
     pub grid: Grid,
     pub last_position: Vec2,
+    // (start cell, goal cell, obstacle hash) of the last search and its result
+    pub path_cache_key: Option<((i32, i32), (i32, i32), u64)>,
+    pub cached_path: Vec<Vec2>,
+    // Planner and simplification the cached path was made with
+    pub planner: Option<Planner>,
+    pub path_simplify_epsilon: f32,
+    pub goal: Vec2,
+    // Goal clicked on the road, kept instead of the Goal entity's and
+    // skipping the checkpoints until a right click clears it
+    pub is_manual_goal: bool,
+    pub is_goal_reached: bool,
+    // Visited in order before heading for the goal
+    pub checkpoints: Vec<Vec2>,
+    pub next_checkpoint: usize,
+    // Incremental planner, only used when D* Lite is enabled
+    pub dstar: Option<DStarLite>,
+    // (start, goal) handed from the scan to the search system
+    pub pending_search: Option<(Vec2, Vec2)>,
+    // (nodes expanded, path cost) of the last search, picked up for SimStats
+    pub last_search: Option<(usize, i32)>,
 }
 
+// Dimensions of the grid each A* car plans on
+#[derive(Resource, Clone)]
+pub struct GridConfig {
+    pub width: i32,
+    pub height: i32,
+    pub cell_size: f32,
+    pub origin: Vec2,
+    // Walls known up front, e.g. from a loaded track
+    pub static_obstacles: HashSet<(i32, i32)>,
+}
+
+// When the A* cars replan
+#[derive(Resource, Clone)]
+pub struct PathfindingConfig {
+    pub recalc_interval: f32,
+    pub replan_distance: f32,
+    pub obstacle_check_interval: f32,
+    // Scan radius is base_scan_radius + scan_speed_factor * speed, so fast
+    // cars look further ahead and slow ones scan less
+    pub base_scan_radius: f32,
+    pub scan_speed_factor: f32,
+}
+
+// Goal placed by clicking on the road for the lead car, where the marker
+// is drawn
+#[derive(Resource, Default)]
+pub struct ManualGoal(pub Option<Vec2>);
+
+#[derive(Component)]
+struct GoalMarker;
+
 impl Plugin for PathfindingPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(astar_pathfinding_system)
-            .add_system(astar_movement_system);
+        let is_astar = || in_mode(AlgorithmMode::AStar);
+        app.insert_resource(ManualGoal::default())
+            // A loaded track may have already put its own grid in place
+            .init_resource::<GridConfig>()
+            .init_resource::<PathfindingConfig>()
+            .add_system(astar_scan_system.run_if(is_sim_running).run_if(is_astar()))
+            .add_system(
+                astar_search_system
+                    .after(astar_scan_system)
+                    .run_if(is_sim_running)
+                    .run_if(is_astar()),
+            )
+            .add_system(astar_movement_system.run_if(is_sim_running).run_if(is_astar()))
+            .add_system(astar_collision_system.run_if(is_astar()))
+            .add_system(astar_path_export_system.run_if(is_astar()))
+            .add_system(astar_grid_dump_system.run_if(is_astar()));
+    }
+}
+
+// Mouse goal placement and path drawing, needs a window
+pub struct AStarInteractionPlugin;
+
+impl Plugin for AStarInteractionPlugin {
+    fn build(&self, app: &mut App) {
+        let is_astar = || in_mode(AlgorithmMode::AStar);
+        app.add_startup_system(spawn_goal_marker)
+            .add_system(manual_goal_input_system.run_if(is_astar()))
+            .add_system(goal_marker_system.run_if(is_astar()))
+            .add_system(grid_inspector_system.run_if(is_astar()))
+            .add_system(astar_path_debug_system.run_if(is_astar()));
     }
 }
 
@@ -43,14 +159,109 @@ pub struct Grid {
     pub height: i32,
     pub cell_size: f32,
     pub obstacles: HashSet<(i32, i32)>,
+    // Confidence of every cell in `obstacles`, refreshed to 1 on each
+    // detection and decayed between scans so a briefly missed truck keeps
+    // its cell blocked
+    pub obstacle_confidence: HashMap<(i32, i32), f32>,
+    // Time of the last decay step
+    pub decayed_at: Option<f32>,
     pub origin: Vec2,
+    // Time (in seconds) at which each cell was last raycast
+    pub scanned_at: HashMap<(i32, i32), f32>,
+    // Rays cast from every scanned cell, any hit makes it an obstacle
+    pub scan_directions: Vec<Vec2>,
+    // Multiple of the distance to the cell's edge each ray is cast for
+    pub scan_ray_length: f32,
+    // Expand diagonal neighbours too, cardinal only when false
+    pub allow_diagonal: bool,
+    // Node expansions allowed per search before settling for a partial path
+    pub max_nodes: usize,
+    pub heuristic: Heuristic,
+    // Heuristic weight for find_path, at 1 paths are optimal and above it
+    // they're bounded at epsilon times the optimal cost
+    pub epsilon: f32,
+    // Added by find_path_turn_penalized for each change of direction
+    pub turn_cost: i32,
+    // Padding around obstacles so the car body doesn't clip them
+    pub inflated_obstacles: HashSet<(i32, i32)>,
+    // Never scanned or forgotten, only come from the GridConfig
+    pub static_obstacles: HashSet<(i32, i32)>,
+    pub inflation_radius: i32,
+    // XOR of every obstacle cell's hash, kept in sync by add/remove_obstacle
+    pub obstacle_hash: u64,
+    // Center cell and half size of the last scanned window
+    pub last_scan_window: Option<((i32, i32), i32)>,
+    // Per cell cost of a straight step, cells missing here cost STRAIGHT_MOVE_COST
+    pub costs: HashMap<(i32, i32), i32>,
 }
 
-#[derive(Clone, Eq, PartialEq)]
+const STRAIGHT_MOVE_COST: i32 = 10;
+const DIAGONAL_MOVE_COST: i32 = 14; // ~10 * sqrt(2)
+
+// On disk form of a Grid, tuple keyed maps become sorted lists since JSON
+// object keys have to be strings
+#[derive(Serialize, Deserialize)]
+struct GridSnapshot {
+    width: i32,
+    height: i32,
+    cell_size: f32,
+    origin: [f32; 2],
+    allow_diagonal: bool,
+    max_nodes: usize,
+    heuristic: Heuristic,
+    epsilon: f32,
+    turn_cost: i32,
+    inflation_radius: i32,
+    fingerprint: u64,
+    obstacles: Vec<(i32, i32)>,
+    static_obstacles: Vec<(i32, i32)>,
+    costs: Vec<((i32, i32), i32)>,
+}
+
+impl From<&Grid> for GridSnapshot {
+    fn from(grid: &Grid) -> Self {
+        let sorted = |cells: &HashSet<(i32, i32)>| {
+            let mut cells: Vec<_> = cells.iter().copied().collect();
+            cells.sort_unstable();
+            cells
+        };
+        let mut costs: Vec<_> = grid.costs.iter().map(|(&pos, &cost)| (pos, cost)).collect();
+        costs.sort_unstable();
+
+        Self {
+            width: grid.width,
+            height: grid.height,
+            cell_size: grid.cell_size,
+            origin: grid.origin.to_array(),
+            allow_diagonal: grid.allow_diagonal,
+            max_nodes: grid.max_nodes,
+            heuristic: grid.heuristic,
+            epsilon: grid.epsilon,
+            turn_cost: grid.turn_cost,
+            inflation_radius: grid.inflation_radius,
+            fingerprint: grid.obstacle_fingerprint(),
+            obstacles: sorted(&grid.obstacles),
+            static_obstacles: sorted(&grid.static_obstacles),
+            costs,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Heuristic {
+    Manhattan,
+    Euclidean,
+    Chebyshev,
+    Octile,
+}
+
+#[derive(Clone)]
 struct Node {
     position: (i32, i32),
     g_cost: i32, // Distance from start
     h_cost: i32, // Heuristic distance to goal
+    // Deviation from the straight start-goal line, only used to break ties
+    cross: i32,
     parent: Option<(i32, i32)>,
 }
 
@@ -62,12 +273,27 @@ impl Node {
 
 impl Ord for Node {
     fn cmp(&self, other: &Self) -> Ordering {
-        // Reverse ordering for min-heap behavior
+        // Reverse ordering for min-heap behavior. Among equal f and h, prefer
+        // cells near the start-goal line so open grids don't flood the
+        // frontier, position breaks the remaining ties so pop order never
+        // depends on heap internals
         other.f_cost().cmp(&self.f_cost())
             .then_with(|| other.h_cost.cmp(&self.h_cost))
+            .then_with(|| other.cross.cmp(&self.cross))
+            .then_with(|| other.position.cmp(&self.position))
     }
 }
 
+// Equal exactly when Ord says so, the parent and the split of f between g
+// and h don't take part
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Node {}
+
 impl PartialOrd for Node {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
@@ -75,21 +301,53 @@ impl PartialOrd for Node {
 }
 
 impl Grid {
+    // A cell size that isn't positive would divide by zero in world_to_grid
+    // and feed NaNs into the search, so bad sizes fall back to the defaults
+    // with a warning rather than panic mid-run
     pub fn new(width: i32, height: i32, cell_size: f32, origin: Vec2) -> Self {
+        let cell_size = match cell_size.is_finite() && cell_size > 0.0 {
+            true => cell_size,
+            false => {
+                warn!("A* cell size {} isn't positive, using {}", cell_size, ASTAR_CELL_SIZE);
+                ASTAR_CELL_SIZE
+            }
+        };
+        if width < 1 || height < 1 {
+            warn!("A* grid {}x{} has no cells, using at least 1x1", width, height);
+        }
+        let (width, height) = (width.max(1), height.max(1));
+
         Self {
             width,
             height,
             cell_size,
             obstacles: HashSet::new(),
+            obstacle_confidence: HashMap::new(),
+            decayed_at: None,
             origin,
+            scanned_at: HashMap::new(),
+            scan_directions: scan_directions(ASTAR_SCAN_DIAGONALS),
+            scan_ray_length: ASTAR_SCAN_RAY_LENGTH,
+            allow_diagonal: true,
+            max_nodes: ASTAR_MAX_NODES,
+            heuristic: Heuristic::Octile,
+            epsilon: ASTAR_EPSILON,
+            turn_cost: ASTAR_TURN_COST,
+            inflated_obstacles: HashSet::new(),
+            static_obstacles: HashSet::new(),
+            inflation_radius: (ASTAR_CAR_HALF_EXTENT / cell_size).ceil() as i32,
+            obstacle_hash: 0,
+            last_scan_window: None,
+            costs: HashMap::new(),
         }
     }
 
     pub fn world_to_grid(&self, world_pos: Vec2) -> (i32, i32) {
         let relative_pos = world_pos - self.origin;
+        // Floor rather than truncate so cells left/below the origin line up
         (
-            (relative_pos.x / self.cell_size) as i32,
-            (relative_pos.y / self.cell_size) as i32,
+            (relative_pos.x / self.cell_size).floor() as i32,
+            (relative_pos.y / self.cell_size).floor() as i32,
         )
     }
 
@@ -104,55 +362,345 @@ impl Grid {
         pos.0 >= 0 && pos.0 < self.width && pos.1 >= 0 && pos.1 < self.height
     }
 
+    pub fn cost(&self, pos: (i32, i32)) -> i32 {
+        self.costs.get(&pos).copied().unwrap_or(STRAIGHT_MOVE_COST)
+    }
+
+    // Cheapest straight step anywhere on the grid
+    pub fn min_cost(&self) -> i32 {
+        self.costs
+            .values()
+            .copied()
+            .fold(STRAIGHT_MOVE_COST, i32::min)
+            .max(1)
+    }
+
+    // Nearest in-bounds cell
+    pub fn clamp_to_bounds(&self, pos: (i32, i32)) -> (i32, i32) {
+        (pos.0.clamp(0, self.width - 1), pos.1.clamp(0, self.height - 1))
+    }
+
     pub fn is_walkable(&self, pos: (i32, i32)) -> bool {
-        self.is_valid(pos) && !self.obstacles.contains(&pos)
+        self.is_valid(pos)
+            && self
+                .obstacle_confidence
+                .get(&pos)
+                .map_or(true, |&confidence| confidence < ASTAR_OBSTACLE_CONFIDENCE_THRESHOLD)
+            && !self.inflated_obstacles.contains(&pos)
+            && !self.static_obstacles.contains(&pos)
     }
 
-    pub fn update_obstacles(&mut self, rapier_context: &RapierContext, car_pos: Vec2, scan_radius: f32) {
-        self.obstacles.clear();
-        
+    // Scanned or static obstacle, ignoring the inflation padding
+    pub fn is_obstacle(&self, pos: (i32, i32)) -> bool {
+        self.static_obstacles.contains(&pos)
+            || self
+                .obstacle_confidence
+                .get(&pos)
+                .map_or(false, |&confidence| confidence >= ASTAR_OBSTACLE_CONFIDENCE_THRESHOLD)
+    }
+
+    // Sweeps a body of the given half width from a to b, true when the
+    // rectangle it covers touches no obstacle. Checked against the raw
+    // obstacles since the radius stands in for the inflation padding
+    pub fn clear_for_radius(&self, a: Vec2, b: Vec2, radius: f32) -> bool {
+        let normal = (b - a).normalize_or_zero().perp();
+        // Half a cell apart so no obstacle fits between two sweep lines
+        let lanes = (2.0 * radius / (self.cell_size * 0.5)).ceil().max(1.0) as i32;
+        (0..=lanes).all(|lane| {
+            let offset = normal * (lane as f32 / lanes as f32 * 2.0 - 1.0) * radius;
+            supercover_line(self.world_to_grid(a + offset), self.world_to_grid(b + offset))
+                .iter()
+                .all(|cell| !self.is_obstacle(*cell))
+        })
+    }
+
+    // Closest walkable cell to pos, searched in growing squares around it.
+    // None when nothing on the grid is walkable
+    pub fn nearest_walkable(&self, pos: (i32, i32)) -> Option<(i32, i32)> {
+        let pos = self.clamp_to_bounds(pos);
+        (0..self.width.max(self.height)).find_map(|radius| {
+            (-radius..=radius)
+                .flat_map(|dx| (-radius..=radius).map(move |dy| (dx, dy)))
+                .filter(|(dx, dy)| dx.abs().max(dy.abs()) == radius)
+                .map(|(dx, dy)| (pos.0 + dx, pos.1 + dy))
+                .filter(|&cell| self.is_walkable(cell))
+                .min_by_key(|&(x, y)| ((x - pos.0).pow(2) + (y - pos.1).pow(2), (x, y)))
+        })
+    }
+
+    // Breadth-first flood fill from start, stopping as soon as it reaches
+    // goal. Much cheaper than a full A* expansion when the goal is walled off.
+    // Capped at max_nodes cells like the search itself, a fill that big
+    // counts as reachable and the search's own budget takes over
+    pub fn reachable(&self, start: (i32, i32), goal: (i32, i32)) -> bool {
+        if !self.is_walkable(start) || !self.is_walkable(goal) {
+            return false;
+        }
+
+        let mut visited = HashSet::from([start]);
+        let mut frontier = VecDeque::from([start]);
+        while let Some(current) = frontier.pop_front() {
+            if current == goal || visited.len() >= self.max_nodes {
+                return true;
+            }
+            for &(dx, dy) in neighbor_offsets(self) {
+                let neighbor = (current.0 + dx, current.1 + dy);
+                if step_cost(self, current, neighbor).is_some() && visited.insert(neighbor) {
+                    frontier.push_back(neighbor);
+                }
+            }
+        }
+        false
+    }
+
+    // True when any cell along the path, including between waypoints, holds
+    // a scanned obstacle
+    pub fn path_blocked(&self, path: &[Vec2]) -> bool {
+        let cells: Vec<(i32, i32)> = path.iter().map(|&p| self.world_to_grid(p)).collect();
+        match cells.as_slice() {
+            [] => false,
+            [cell] => self.obstacles.contains(cell),
+            _ => cells.windows(2).any(|pair| {
+                supercover_line(pair[0], pair[1])
+                    .iter()
+                    .any(|cell| self.obstacles.contains(cell))
+            }),
+        }
+    }
+
+    // Dilate the obstacle set by a Chebyshev radius, kept separate from the
+    // scanned obstacles so repeated inflation doesn't keep growing them
+    pub fn inflate_obstacles(&mut self, radius_cells: i32) {
+        self.inflated_obstacles.clear();
+        for &(x, y) in self.obstacles.iter().chain(self.static_obstacles.iter()) {
+            for dx in -radius_cells..=radius_cells {
+                for dy in -radius_cells..=radius_cells {
+                    let pos = (x + dx, y + dy);
+                    if self.is_valid(pos)
+                        && !self.obstacles.contains(&pos)
+                        && !self.static_obstacles.contains(&pos)
+                    {
+                        self.inflated_obstacles.insert(pos);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn add_obstacle(&mut self, pos: (i32, i32)) {
+        self.obstacle_confidence.insert(pos, 1.0);
+        if self.obstacles.insert(pos) {
+            self.obstacle_hash ^= cell_hash(pos);
+        }
+    }
+
+    pub fn remove_obstacle(&mut self, pos: (i32, i32)) {
+        self.obstacle_confidence.remove(&pos);
+        if self.obstacles.remove(&pos) {
+            self.obstacle_hash ^= cell_hash(pos);
+        }
+    }
+
+    // Order independent hash of the obstacle set, recomputed from scratch so
+    // it also catches edits that bypassed add/remove_obstacle. Matches
+    // obstacle_hash whenever the two are in sync
+    pub fn obstacle_fingerprint(&self) -> u64 {
+        self.obstacles.iter().fold(0, |hash, &pos| hash ^ cell_hash(pos))
+    }
+
+    // Writes the obstacles and search settings as JSON, cells sorted so two
+    // snapshots of the same grid diff line by line
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(&GridSnapshot::from(self))
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+        fs::write(path, json)
+    }
+
+    // Rebuilds a grid from `save`, with inflation and the obstacle hash
+    // recomputed. Scan times and confidences aren't kept, so every loaded
+    // obstacle starts fully confident
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        let snapshot: GridSnapshot =
+            serde_json::from_str(&json).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+        let mut grid = Grid::new(
+            snapshot.width,
+            snapshot.height,
+            snapshot.cell_size,
+            Vec2::from(snapshot.origin),
+        );
+        grid.allow_diagonal = snapshot.allow_diagonal;
+        grid.max_nodes = snapshot.max_nodes;
+        grid.heuristic = snapshot.heuristic;
+        grid.epsilon = snapshot.epsilon;
+        grid.turn_cost = snapshot.turn_cost;
+        grid.inflation_radius = snapshot.inflation_radius;
+        grid.static_obstacles = snapshot.static_obstacles.into_iter().collect();
+        grid.costs = snapshot.costs.into_iter().collect();
+        for pos in snapshot.obstacles {
+            grid.add_obstacle(pos);
+        }
+        if grid.obstacle_hash != snapshot.fingerprint {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "grid snapshot obstacles don't match its fingerprint",
+            ));
+        }
+        grid.inflate_obstacles(grid.inflation_radius);
+
+        Ok(grid)
+    }
+
+    // Ages every obstacle by the time since the last call, dropping the ones
+    // that haven't been seen for long enough
+    pub fn decay_obstacles(&mut self, now: f32) {
+        let elapsed = now - self.decayed_at.replace(now).unwrap_or(now);
+        let decay = elapsed * ASTAR_OBSTACLE_DECAY_PER_SECOND;
+        let mut aged_out = Vec::new();
+        for (&pos, confidence) in self.obstacle_confidence.iter_mut() {
+            *confidence -= decay;
+            if *confidence < ASTAR_OBSTACLE_CONFIDENCE_THRESHOLD {
+                aged_out.push(pos);
+            }
+        }
+        aged_out.into_iter().for_each(|pos| self.remove_obstacle(pos));
+    }
+
+    pub fn is_fresh(&self, pos: (i32, i32), now: f32, freshness: f32) -> bool {
+        match self.scanned_at.get(&pos) {
+            Some(&t) => now - t < freshness,
+            None => false,
+        }
+    }
+
+    pub fn update_obstacles(
+        &mut self,
+        rapier_context: &RapierContext,
+        car_pos: Vec2,
+        scan_radius: f32,
+        now: f32,
+        freshness: f32,
+        force: bool,
+    ) {
         // Scan area around car for obstacles
         let car_grid = self.world_to_grid(car_pos);
         let scan_cells = (scan_radius / self.cell_size) as i32;
-        
+        self.decay_obstacles(now);
+        self.forget_outside(car_grid, scan_cells);
+        self.last_scan_window = Some((car_grid, scan_cells));
+
         for x in (car_grid.0 - scan_cells)..(car_grid.0 + scan_cells) {
             for y in (car_grid.1 - scan_cells)..(car_grid.1 + scan_cells) {
                 if !self.is_valid((x, y)) {
                     continue;
                 }
-                
-                let world_pos = self.grid_to_world((x, y));
-                
-                // Raycast to check for obstacles at this grid position
-                let ray_filter = CollisionGroups {
-                    memberships: Group::GROUP_1,
-                    filters: Group::GROUP_2,
-                };
-                let filter = QueryFilter::default().groups(ray_filter);
-                
-                // Check multiple directions from this point to ensure obstacle detection
-                let directions = [
-                    Vec2::new(0.0, 1.0),
-                    Vec2::new(1.0, 0.0),
-                    Vec2::new(0.0, -1.0),
-                    Vec2::new(-1.0, 0.0),
-                ];
-                
-                for dir in directions.iter() {
-                    if rapier_context.cast_ray(
-                        world_pos,
-                        *dir,
-                        self.cell_size * 0.5,
-                        false,
-                        filter
-                    ).is_some() {
-                        self.obstacles.insert((x, y));
-                        break;
-                    }
+
+                // Skip cells checked recently unless a rescan is forced
+                if !force && self.is_fresh((x, y), now, freshness) {
+                    continue;
                 }
+                self.scanned_at.insert((x, y), now);
+                self.scan_cell(rapier_context, (x, y));
             }
         }
     }
+
+    // Raycasts the known obstacles every call so ones still there stay
+    // confident, and otherwise only the cells that aren't fresh. Cells that
+    // just entered the window have never been scanned, and clear cells the
+    // window kept still get rescanned once they go stale
+    pub fn update_obstacles_incremental(
+        &mut self,
+        rapier_context: &RapierContext,
+        car_pos: Vec2,
+        scan_radius: f32,
+        now: f32,
+        freshness: f32,
+    ) {
+        let car_grid = self.world_to_grid(car_pos);
+        let scan_cells = (scan_radius / self.cell_size) as i32;
+        self.decay_obstacles(now);
+        self.forget_outside(car_grid, scan_cells);
+        self.last_scan_window = Some((car_grid, scan_cells));
+
+        let known_obstacles: Vec<(i32, i32)> = self.obstacles.iter().copied().collect();
+        for pos in known_obstacles {
+            self.scanned_at.insert(pos, now);
+            self.scan_cell(rapier_context, pos);
+        }
+
+        for x in (car_grid.0 - scan_cells)..(car_grid.0 + scan_cells) {
+            for y in (car_grid.1 - scan_cells)..(car_grid.1 + scan_cells) {
+                if !self.is_valid((x, y)) || self.is_fresh((x, y), now, freshness) {
+                    continue;
+                }
+
+                self.scanned_at.insert((x, y), now);
+                self.scan_cell(rapier_context, (x, y));
+            }
+        }
+    }
+
+    // Forget everything outside the scan window
+    fn forget_outside(&mut self, center: (i32, i32), scan_cells: i32) {
+        let out_of_range: Vec<(i32, i32)> = self
+            .obstacles
+            .iter()
+            .filter(|pos| !is_in_window(**pos, center, scan_cells))
+            .copied()
+            .collect();
+        out_of_range.into_iter().for_each(|pos| self.remove_obstacle(pos));
+        self.scanned_at.retain(|pos, _| is_in_window(*pos, center, scan_cells));
+    }
+
+    // A hit refreshes the cell, a miss leaves it to decay_obstacles
+    fn scan_cell(&mut self, rapier_context: &RapierContext, pos: (i32, i32)) {
+        let world_pos = self.grid_to_world(pos);
+        
+        // Raycast to check for obstacles at this grid position
+        let filter = QueryFilter::default().groups(SENSOR_RAY_GROUPS);
+        
+        // Check multiple directions from this point to ensure obstacle detection.
+        // Each ray runs out to the edge of the half cell square around the
+        // point, so the diagonals reach its corners
+        let is_hit = self.scan_directions.iter().any(|dir| {
+            let extent = dir.x.abs().max(dir.y.abs()).max(f32::EPSILON);
+            let edge_distance = self.cell_size * 0.5 / extent;
+            rapier_context
+                .cast_ray(world_pos, *dir, edge_distance * self.scan_ray_length, false, filter)
+                .is_some()
+        });
+        if is_hit {
+            self.add_obstacle(pos);
+        }
+    }
+}
+
+// Unit ray directions for the obstacle scan, cardinal plus optionally the
+// diagonals
+pub fn scan_directions(include_diagonals: bool) -> Vec<Vec2> {
+    let mut directions = vec![
+        Vec2::new(0.0, 1.0),
+        Vec2::new(1.0, 0.0),
+        Vec2::new(0.0, -1.0),
+        Vec2::new(-1.0, 0.0),
+    ];
+    if include_diagonals {
+        directions.extend(
+            [(1.0, 1.0), (1.0, -1.0), (-1.0, -1.0), (-1.0, 1.0)]
+                .map(|(x, y)| Vec2::new(x, y).normalize()),
+        );
+    }
+    directions
+}
+
+fn is_in_window(pos: (i32, i32), center: (i32, i32), scan_cells: i32) -> bool {
+    pos.0 >= center.0 - scan_cells
+        && pos.0 < center.0 + scan_cells
+        && pos.1 >= center.1 - scan_cells
+        && pos.1 < center.1 + scan_cells
 }
 
 impl AStarCar {
@@ -160,82 +708,295 @@ impl AStarCar {
         Self {
             path: Vec::new(),
             current_target: 0,
-            recalculate_timer: Timer::from_seconds(1.0, TimerMode::Repeating),
+            recalculate_timer: Timer::from_seconds(ASTAR_RECALC_INTERVAL, TimerMode::Repeating),
+            obstacle_check_timer: Timer::from_seconds(
+                ASTAR_OBSTACLE_CHECK_INTERVAL,
+                TimerMode::Repeating,
+            ),
+            speed: ASTAR_MOVEMENT_SPEED,
+            turn_rate: ASTAR_ROTATION_SPEED,
+            min_turn_radius: ASTAR_MIN_TURN_RADIUS,
+            arrival_radius: ASTAR_ARRIVAL_RADIUS,
+            look_ahead: ASTAR_LOOK_AHEAD,
+            allow_reverse: false,
+            is_path_blocked: false,
+            stuck_frames: 0,
+            stuck_anchor: Vec2::ZERO,
+            stuck_target: 0,
+            force_replan: false,
+        }
+    }
+
+    // Starts following a new path from its first waypoint, capped at
+    // ASTAR_MAX_PATH_LEN so a runaway search can't keep growing it
+    pub fn set_path(&mut self, mut path: Vec<Vec2>) {
+        if path.len() > ASTAR_MAX_PATH_LEN {
+            warn!("Path of {} waypoints cut to {}", path.len(), ASTAR_MAX_PATH_LEN);
+            path.truncate(ASTAR_MAX_PATH_LEN);
+        }
+        self.path = path;
+        self.current_target = 0;
+    }
+
+    // The target index can only ever sit one past the last waypoint, anything
+    // further means it went out of sync with the path, so drop both and
+    // replan
+    pub fn check_target(&mut self) {
+        if self.current_target <= self.path.len() {
+            return;
+        }
+        warn!(
+            "Target {} is past the end of a {} waypoint path, replanning",
+            self.current_target,
+            self.path.len()
+        );
+        self.path.clear();
+        self.current_target = 0;
+        self.force_replan = true;
+    }
+
+    // Oscillating or pinned against a wall, the car stays within
+    // ASTAR_STUCK_RADIUS without reaching a new waypoint. After
+    // ASTAR_STUCK_FRAMES of that the path is thrown away and a replan forced
+    pub fn check_stuck(&mut self, current_pos: Vec2) {
+        if current_pos.distance(self.stuck_anchor) < ASTAR_STUCK_RADIUS
+            && self.current_target == self.stuck_target
+        {
+            self.stuck_frames += 1;
+        } else {
+            self.stuck_anchor = current_pos;
+            self.stuck_target = self.current_target;
+            self.stuck_frames = 0;
+        }
+        if self.stuck_frames >= ASTAR_STUCK_FRAMES {
+            self.stuck_frames = 0;
+            self.path.clear();
+            self.current_target = 0;
+            self.force_replan = true;
+        }
+    }
+
+    // Moves past every waypoint the car is within arrival_radius of, or has
+    // already driven beyond along the path
+    pub fn advance_target(&mut self, current_pos: Vec2) {
+        while let Some(&target) = self.path.get(self.current_target) {
+            let is_passed = self
+                .path
+                .get(self.current_target + 1)
+                .map_or(false, |&next| (current_pos - target).dot(next - target) > 0.0);
+            if current_pos.distance(target) >= self.arrival_radius && !is_passed {
+                break;
+            }
+            self.current_target += 1;
+        }
+    }
+
+    // Pure pursuit target, look_ahead along the path starting from the car
+    // through the current waypoint, clamped to the path's end
+    pub fn look_ahead_point(&self, current_pos: Vec2) -> Option<Vec2> {
+        let waypoints = self.path.get(self.current_target..).filter(|w| !w.is_empty())?;
+        let mut remaining = self.look_ahead;
+        let mut from = current_pos;
+        for &to in waypoints {
+            let segment = from.distance(to);
+            if segment >= remaining {
+                return Some(from + (to - from) * (remaining / segment));
+            }
+            remaining -= segment;
+            from = to;
+        }
+        waypoints.last().copied()
+    }
+}
+
+impl GridConfig {
+    // Warns about any part of the road or the goal the grid can't see
+    pub fn validate(&self, goal: Vec2) {
+        let grid = Grid::new(self.width, self.height, self.cell_size, self.origin);
+        let road_corners = [
+            Vec2::new(ROAD_LEFT_X, 0.0),
+            Vec2::new(ROAD_RIGHT_X, ROAD_END_Y),
+        ];
+        if road_corners.iter().any(|c| !grid.is_valid(grid.world_to_grid(*c))) {
+            warn!(
+                "A* grid {}x{} cells of {} at {:?} doesn't cover the whole road",
+                self.width, self.height, self.cell_size, self.origin
+            );
+        }
+        if !grid.is_valid(grid.world_to_grid(goal)) {
+            warn!("A* goal {:?} falls outside the grid", goal);
+        }
+        // A wall splitting a cell leaves that cell half open
+        for (wall_x, offset) in [ROAD_LEFT_X, ROAD_RIGHT_X].into_iter().zip(self.wall_offsets()) {
+            if offset > ASTAR_WALL_ALIGN_TOLERANCE {
+                warn!("Road wall at x {} is {} off an A* cell boundary", wall_x, offset);
+            }
+        }
+    }
+
+    // Distance from each road wall to the nearest cell boundary
+    pub fn wall_offsets(&self) -> [f32; 2] {
+        [ROAD_LEFT_X, ROAD_RIGHT_X].map(|wall_x| {
+            let cells = (wall_x - self.origin.x) / self.cell_size;
+            (cells - cells.round()).abs() * self.cell_size
+        })
+    }
+}
+
+impl Default for PathfindingConfig {
+    fn default() -> Self {
+        Self {
+            recalc_interval: ASTAR_RECALC_INTERVAL,
+            replan_distance: ASTAR_REPLAN_DISTANCE,
+            obstacle_check_interval: ASTAR_OBSTACLE_CHECK_INTERVAL,
+            base_scan_radius: ASTAR_BASE_SCAN_RADIUS,
+            scan_speed_factor: ASTAR_SCAN_SPEED_FACTOR,
+        }
+    }
+}
+
+impl PathfindingConfig {
+    pub fn scan_radius(&self, speed: f32) -> f32 {
+        self.base_scan_radius + self.scan_speed_factor * speed.max(0.0)
+    }
+}
+
+impl Default for GridConfig {
+    fn default() -> Self {
+        Self {
+            width: ASTAR_GRID_WIDTH,
+            height: ASTAR_GRID_HEIGHT,
+            cell_size: ASTAR_CELL_SIZE,
+            origin: Vec2::new(ASTAR_GRID_ORIGIN_X, 0.0),
+            static_obstacles: HashSet::new(),
         }
     }
 }
 
 impl PathfindingBrain {
-    pub fn new() -> Self {
-        let grid = Grid::new(
-            100,  // Grid width
-            200,  // Grid height (longer for the road)
-            20.0, // Cell size in world units
-            Vec2::new(600.0, 0.0), // Origin point
+    pub fn new(grid_config: &GridConfig) -> Self {
+        let mut grid = Grid::new(
+            grid_config.width,
+            grid_config.height,
+            grid_config.cell_size,
+            grid_config.origin,
         );
+        grid.static_obstacles = grid_config.static_obstacles.clone();
         
         Self {
             grid,
             last_position: Vec2::ZERO,
+            path_cache_key: None,
+            cached_path: Vec::new(),
+            planner: None,
+            path_simplify_epsilon: 0.0,
+            // Synced from the Goal entity on every scan
+            goal: Vec2::ZERO,
+            is_manual_goal: false,
+            is_goal_reached: false,
+            checkpoints: Vec::new(),
+            next_checkpoint: 0,
+            dstar: None,
+            pending_search: None,
+            last_search: None,
+        }
+    }
+
+    // Next unreached checkpoint, or the goal once they're all cleared
+    pub fn current_goal(&self) -> Vec2 {
+        if self.is_manual_goal {
+            return self.goal;
         }
+        self.checkpoints
+            .get(self.next_checkpoint)
+            .copied()
+            .unwrap_or(self.goal)
     }
 }
 
-// A* pathfinding algorithm implementation
-pub fn find_path(grid: &Grid, start: Vec2, goal: Vec2) -> Vec<Vec2> {
+// Outcome of a search along with how much work it took
+pub struct AStarResult {
+    pub path: Option<Vec<Vec2>>,
+    pub nodes_expanded: usize,
+    pub final_cost: i32,
+}
+
+// A* pathfinding algorithm implementation, None when there's no route
+pub fn find_path(grid: &Grid, start: Vec2, goal: Vec2) -> Option<Vec<Vec2>> {
+    find_path_with_stats(grid, start, goal).path
+}
+
+pub fn find_path_with_stats(grid: &Grid, start: Vec2, goal: Vec2) -> AStarResult {
     let start_grid = grid.world_to_grid(start);
-    let goal_grid = grid.world_to_grid(goal);
+    // Goals off the grid are approached through the furthest cell we can see
+    let goal_grid = grid.clamp_to_bounds(grid.world_to_grid(goal));
     
-    if !grid.is_walkable(start_grid) || !grid.is_walkable(goal_grid) {
-        // If start or goal is blocked, create a simple forward path
-        return vec![
-            start + Vec2::new(0.0, 50.0),
-            start + Vec2::new(0.0, 100.0),
-            start + Vec2::new(0.0, 150.0),
-            goal
-        ];
+    // Also catches a walled off goal before the search burns its whole
+    // node budget on it
+    if !grid.reachable(start_grid, goal_grid) {
+        return AStarResult {
+            path: None,
+            nodes_expanded: 0,
+            final_cost: 0,
+        };
     }
     
+    let min_cost = grid.min_cost();
     let mut open_set = BinaryHeap::new();
-    let mut closed_set = HashSet::new();
     let mut came_from: HashMap<(i32, i32), (i32, i32)> = HashMap::new();
     let mut g_score: HashMap<(i32, i32), i32> = HashMap::new();
     
     let start_node = Node {
         position: start_grid,
         g_cost: 0,
-        h_cost: manhattan_distance(start_grid, goal_grid),
+        h_cost: weighted_heuristic(grid, min_cost, start_grid, goal_grid),
+        cross: 0,
         parent: None,
     };
     
+    // Closest node to the goal so far, for when the search runs out of budget
+    let mut best = (start_node.h_cost, start_grid);
+    let mut nodes_expanded = 0;
+    
     open_set.push(start_node);
     g_score.insert(start_grid, 0);
     
     while let Some(current) = open_set.pop() {
-        if current.position == goal_grid {
-            return reconstruct_path(came_from, current.position, grid);
+        // Stale heap entry, a cheaper route to this cell was queued since
+        if g_score.get(&current.position).map_or(false, |&g| current.g_cost > g) {
+            continue;
         }
         
-        closed_set.insert(current.position);
+        if current.position == goal_grid {
+            return AStarResult {
+                path: Some(reconstruct_path(came_from, current.position, grid)),
+                nodes_expanded,
+                final_cost: current.g_cost,
+            };
+        }
         
-        // Check 4 cardinal directions first (simpler pathfinding)
-        let neighbors = [
-            (0, -1), // North
-            (1,  0), // East  
-            (0,  1), // South
-            (-1, 0), // West
-        ];
+        nodes_expanded += 1;
+        if current.h_cost < best.0 {
+            best = (current.h_cost, current.position);
+        }
+        if nodes_expanded >= grid.max_nodes {
+            return AStarResult {
+                final_cost: g_score.get(&best.1).copied().unwrap_or(0),
+                path: Some(reconstruct_path(came_from, best.1, grid)),
+                nodes_expanded,
+            };
+        }
         
-        for &(dx, dy) in &neighbors {
+        for &(dx, dy) in neighbor_offsets(grid) {
             let neighbor_pos = (current.position.0 + dx, current.position.1 + dy);
             
-            if !grid.is_walkable(neighbor_pos) || closed_set.contains(&neighbor_pos) {
+            let Some(movement_cost) = step_cost(grid, current.position, neighbor_pos) else {
                 continue;
-            }
-            
-            let movement_cost = 10; // Uniform cost for cardinal directions
+            };
             let tentative_g = current.g_cost + movement_cost;
             
+            // Only a cheaper route gets queued. That includes routes into
+            // cells already expanded, which reopens them
             if let Some(&existing_g) = g_score.get(&neighbor_pos) {
                 if tentative_g >= existing_g {
                     continue;
@@ -248,7 +1009,8 @@ pub fn find_path(grid: &Grid, start: Vec2, goal: Vec2) -> Vec<Vec2> {
             let neighbor_node = Node {
                 position: neighbor_pos,
                 g_cost: tentative_g,
-                h_cost: manhattan_distance(neighbor_pos, goal_grid),
+                h_cost: weighted_heuristic(grid, min_cost, neighbor_pos, goal_grid),
+                cross: cross_tie_breaker(neighbor_pos, start_grid, goal_grid),
                 parent: Some(current.position),
             };
             
@@ -256,126 +1018,1642 @@ pub fn find_path(grid: &Grid, start: Vec2, goal: Vec2) -> Vec<Vec2> {
         }
     }
     
-    // If no path found, create a simple forward path
-    vec![
-        start + Vec2::new(0.0, 50.0),
-        start + Vec2::new(0.0, 100.0),
-        start + Vec2::new(0.0, 150.0),
-        goal
-    ]
+    AStarResult {
+        path: None,
+        nodes_expanded,
+        final_cost: 0,
+    }
 }
 
-fn manhattan_distance(a: (i32, i32), b: (i32, i32)) -> i32 {
-    (a.0 - b.0).abs() + (a.1 - b.1).abs()
+// Search state of the turn penalized planner, a cell and the step that led
+// into it, (0, 0) at the start
+type TurnState = ((i32, i32), (i32, i32));
+
+// Turn penalized A*, None when there's no route
+pub fn find_path_turn_penalized(grid: &Grid, start: Vec2, goal: Vec2) -> Option<Vec<Vec2>> {
+    find_path_turn_penalized_with_stats(grid, start, goal).path
 }
 
-fn reconstruct_path(came_from: HashMap<(i32, i32), (i32, i32)>, mut current: (i32, i32), grid: &Grid) -> Vec<Vec2> {
-    let mut path = Vec::new();
-    
-    path.push(grid.grid_to_world(current));
-    
+// A* over (cell, incoming direction) that adds the grid's turn_cost to every
+// step changing direction, so a straight corridor beats a slightly shorter
+// zigzag. Same reachability check and node budget fallback as
+// find_path_with_stats
+pub fn find_path_turn_penalized_with_stats(grid: &Grid, start: Vec2, goal: Vec2) -> AStarResult {
+    let start_grid = grid.world_to_grid(start);
+    let goal_grid = grid.clamp_to_bounds(grid.world_to_grid(goal));
+    if !grid.reachable(start_grid, goal_grid) {
+        return AStarResult {
+            path: None,
+            nodes_expanded: 0,
+            final_cost: 0,
+        };
+    }
+
+    let min_cost = grid.min_cost();
+    let start_state: TurnState = (start_grid, (0, 0));
+    let start_h = weighted_heuristic(grid, min_cost, start_grid, goal_grid);
+    let mut came_from: HashMap<TurnState, TurnState> = HashMap::new();
+    let mut g_score: HashMap<TurnState, i32> = HashMap::new();
+    g_score.insert(start_state, 0);
+    // Ordered by f, then h, then state so pops never depend on heap internals
+    let mut open_set = BinaryHeap::from([Reverse((start_h, start_h, start_state))]);
+
+    let mut best = (start_h, start_state);
+    let mut nodes_expanded = 0;
+
+    while let Some(Reverse((f_cost, h_cost, state))) = open_set.pop() {
+        let g_cost = f_cost - h_cost;
+        // Stale heap entry, a cheaper route to this state was queued since
+        if g_score.get(&state).map_or(false, |&g| g_cost > g) {
+            continue;
+        }
+
+        let (cell, heading) = state;
+        if cell == goal_grid {
+            return AStarResult {
+                path: Some(reconstruct_turn_path(&came_from, state, grid)),
+                nodes_expanded,
+                final_cost: g_cost,
+            };
+        }
+
+        nodes_expanded += 1;
+        if h_cost < best.0 {
+            best = (h_cost, state);
+        }
+        if nodes_expanded >= grid.max_nodes {
+            return AStarResult {
+                final_cost: g_score.get(&best.1).copied().unwrap_or(0),
+                path: Some(reconstruct_turn_path(&came_from, best.1, grid)),
+                nodes_expanded,
+            };
+        }
+
+        for &(dx, dy) in neighbor_offsets(grid) {
+            let neighbor = (cell.0 + dx, cell.1 + dy);
+            let Some(movement_cost) = step_cost(grid, cell, neighbor) else {
+                continue;
+            };
+            let is_turn = heading != (0, 0) && heading != (dx, dy);
+            let turn_cost = if is_turn { grid.turn_cost } else { 0 };
+            let tentative_g = g_cost + movement_cost + turn_cost;
+
+            let next_state = (neighbor, (dx, dy));
+            if g_score.get(&next_state).map_or(false, |&g| tentative_g >= g) {
+                continue;
+            }
+            came_from.insert(next_state, state);
+            g_score.insert(next_state, tentative_g);
+            let next_h = weighted_heuristic(grid, min_cost, neighbor, goal_grid);
+            open_set.push(Reverse((tentative_g + next_h, next_h, next_state)));
+        }
+    }
+
+    AStarResult {
+        path: None,
+        nodes_expanded,
+        final_cost: 0,
+    }
+}
+
+fn reconstruct_turn_path(
+    came_from: &HashMap<TurnState, TurnState>,
+    mut current: TurnState,
+    grid: &Grid,
+) -> Vec<Vec2> {
+    let mut path = vec![grid.grid_to_world(current.0)];
     while let Some(&parent) = came_from.get(&current) {
         current = parent;
-        path.push(grid.grid_to_world(current));
+        path.push(grid.grid_to_world(current.0));
     }
-    
     path.reverse();
     path
 }
 
-fn astar_pathfinding_system(
-    time: Res<Time>,
-    rapier_context: Res<RapierContext>,
-    mut query: Query<(&Transform, &mut AStarCar, &mut PathfindingBrain), (With<AStarAgent>, With<Car>)>,
-) {
-    for (transform, mut astar_car, mut brain) in query.iter_mut() {
-        astar_car.recalculate_timer.tick(time.delta());
-        
-        let current_pos = Vec2::new(transform.translation.x, transform.translation.y);
-        
-        // Recalculate path periodically or when car has moved significantly
-        if astar_car.recalculate_timer.finished() || 
-           brain.last_position.distance(current_pos) > 50.0 {
-            
-            // Update obstacle map
-            brain.grid.update_obstacles(&rapier_context, current_pos, 300.0);
-            
-            // Set goal ahead on the road
-            let goal = Vec2::new(current_pos.x, current_pos.y + 500.0);
-            
-            // Find new path
-            astar_car.path = find_path(&brain.grid, current_pos, goal);
-            astar_car.current_target = 0;
-            brain.last_position = current_pos;
+// One half of a bidirectional search, `reversed` walks edges backwards from
+// the goal so costs still match the forward direction
+struct SearchSide {
+    open_set: BinaryHeap<Node>,
+    came_from: HashMap<(i32, i32), (i32, i32)>,
+    g_score: HashMap<(i32, i32), i32>,
+    origin: (i32, i32),
+    target: (i32, i32),
+    reversed: bool,
+}
+
+impl SearchSide {
+    fn new(grid: &Grid, min_cost: i32, origin: (i32, i32), target: (i32, i32), reversed: bool) -> Self {
+        let mut open_set = BinaryHeap::new();
+        open_set.push(Node {
+            position: origin,
+            g_cost: 0,
+            h_cost: scaled_heuristic(grid, min_cost, origin, target),
+            cross: 0,
+            parent: None,
+        });
+
+        let mut g_score = HashMap::new();
+        g_score.insert(origin, 0);
+
+        Self {
+            open_set,
+            came_from: HashMap::new(),
+            g_score,
+            origin,
+            target,
+            reversed,
         }
     }
-}
 
-fn astar_movement_system(
-    time: Res<Time>,
-    mut query: Query<(&mut Transform, &mut AStarCar), (With<AStarAgent>, With<Car>)>,
+    // Lowest f still queued, a lower bound on any path through this frontier
+    fn min_f(&mut self) -> Option<i32> {
+        while let Some(top) = self.open_set.peek() {
+            if self.g_score.get(&top.position).map_or(false, |&g| top.g_cost > g) {
+                self.open_set.pop();
+                continue;
+            }
+            return Some(top.f_cost());
+        }
+        None
+    }
+
+    // Expands the best queued node, recording any cell the other side has
+    // also reached as a candidate meeting point
+    fn expand(&mut self, grid: &Grid, min_cost: i32, other: &SearchSide, meet: &mut Option<(i32, (i32, i32))>) {
+        let Some(current) = self.open_set.pop() else {
+            return;
+        };
+
+        for &(dx, dy) in neighbor_offsets(grid) {
+            let neighbor_pos = (current.position.0 + dx, current.position.1 + dy);
+            let cost = match self.reversed {
+                true => step_cost(grid, neighbor_pos, current.position),
+                false => step_cost(grid, current.position, neighbor_pos),
+            };
+            let Some(movement_cost) = cost else {
+                continue;
+            };
+            let tentative_g = current.g_cost + movement_cost;
+            if self.g_score.get(&neighbor_pos).map_or(false, |&g| tentative_g >= g) {
+                continue;
+            }
+
+            self.came_from.insert(neighbor_pos, current.position);
+            self.g_score.insert(neighbor_pos, tentative_g);
+            if let Some(&other_g) = other.g_score.get(&neighbor_pos) {
+                if meet.map_or(true, |(cost, _)| tentative_g + other_g < cost) {
+                    *meet = Some((tentative_g + other_g, neighbor_pos));
+                }
+            }
+            self.open_set.push(Node {
+                position: neighbor_pos,
+                g_cost: tentative_g,
+                h_cost: scaled_heuristic(grid, min_cost, neighbor_pos, self.target),
+                cross: cross_tie_breaker(neighbor_pos, self.origin, self.target),
+                parent: Some(current.position),
+            });
+        }
+    }
+}
+
+// Bidirectional A*, None when there's no route
+pub fn find_path_bidirectional(grid: &Grid, start: Vec2, goal: Vec2) -> Option<Vec<Vec2>> {
+    find_path_bidirectional_with_stats(grid, start, goal).path
+}
+
+// Searches from both ends at once, always growing the smaller frontier, and
+// stitches the two halves together where they meet. If the node budget runs
+// out before they do, falls back to the forward half's closest cell like
+// find_path_with_stats
+pub fn find_path_bidirectional_with_stats(grid: &Grid, start: Vec2, goal: Vec2) -> AStarResult {
+    let start_grid = grid.world_to_grid(start);
+    let goal_grid = grid.clamp_to_bounds(grid.world_to_grid(goal));
+
+    if !grid.is_walkable(start_grid) || !grid.is_walkable(goal_grid) {
+        return AStarResult {
+            path: None,
+            nodes_expanded: 0,
+            final_cost: 0,
+        };
+    }
+
+    let min_cost = grid.min_cost();
+    let mut forward = SearchSide::new(grid, min_cost, start_grid, goal_grid, false);
+    let mut backward = SearchSide::new(grid, min_cost, goal_grid, start_grid, true);
+    let mut meet = (start_grid == goal_grid).then_some((0, start_grid));
+    let mut nodes_expanded = 0;
+
+    loop {
+        let (Some(forward_f), Some(backward_f)) = (forward.min_f(), backward.min_f()) else {
+            break;
+        };
+        // Neither frontier can improve on the best meeting point any more
+        if meet.map_or(false, |(cost, _)| forward_f.max(backward_f) >= cost) {
+            break;
+        }
+        if nodes_expanded >= grid.max_nodes {
+            let best = forward
+                .g_score
+                .keys()
+                .min_by_key(|&&pos| (scaled_heuristic(grid, min_cost, pos, goal_grid), pos))
+                .copied()
+                .unwrap_or(start_grid);
+            return AStarResult {
+                final_cost: forward.g_score.get(&best).copied().unwrap_or(0),
+                path: Some(reconstruct_path(forward.came_from, best, grid)),
+                nodes_expanded,
+            };
+        }
+
+        nodes_expanded += 1;
+        if forward.open_set.len() <= backward.open_set.len() {
+            forward.expand(grid, min_cost, &backward, &mut meet);
+        } else {
+            backward.expand(grid, min_cost, &forward, &mut meet);
+        }
+    }
+
+    let Some((final_cost, meet_cell)) = meet else {
+        return AStarResult {
+            path: None,
+            nodes_expanded,
+            final_cost: 0,
+        };
+    };
+
+    // Start to the meeting cell, then follow the backward parents to the goal
+    let mut path = reconstruct_path(forward.came_from, meet_cell, grid);
+    let mut current = meet_cell;
+    while let Some(&next) = backward.came_from.get(&current) {
+        current = next;
+        path.push(grid.grid_to_world(current));
+    }
+
+    AStarResult {
+        path: Some(path),
+        nodes_expanded,
+        final_cost,
+    }
+}
+
+// Jump Point Search, None when there's no route
+pub fn find_path_jps(grid: &Grid, start: Vec2, goal: Vec2) -> Option<Vec<Vec2>> {
+    find_path_jps_with_stats(grid, start, goal).path
+}
+
+// A* that only queues jump points, the cells where an optimal path might
+// turn, skipping the symmetric runs of open cells between them. Only valid
+// on uniform cost grids with diagonals, anything else goes to plain A*
+pub fn find_path_jps_with_stats(grid: &Grid, start: Vec2, goal: Vec2) -> AStarResult {
+    if !grid.costs.is_empty() || !grid.allow_diagonal {
+        return find_path_with_stats(grid, start, goal);
+    }
+
+    let start_grid = grid.world_to_grid(start);
+    let goal_grid = grid.clamp_to_bounds(grid.world_to_grid(goal));
+
+    if !grid.is_walkable(start_grid) || !grid.is_walkable(goal_grid) {
+        return AStarResult {
+            path: None,
+            nodes_expanded: 0,
+            final_cost: 0,
+        };
+    }
+
+    let mut open_set = BinaryHeap::new();
+    let mut came_from: HashMap<(i32, i32), (i32, i32)> = HashMap::new();
+    let mut g_score: HashMap<(i32, i32), i32> = HashMap::new();
+
+    let start_node = Node {
+        position: start_grid,
+        g_cost: 0,
+        h_cost: Heuristic::Octile.distance(start_grid, goal_grid),
+        cross: 0,
+        parent: None,
+    };
+
+    let mut best = (start_node.h_cost, start_grid);
+    let mut nodes_expanded = 0;
+
+    open_set.push(start_node);
+    g_score.insert(start_grid, 0);
+
+    while let Some(current) = open_set.pop() {
+        if g_score.get(&current.position).map_or(false, |&g| current.g_cost > g) {
+            continue;
+        }
+
+        if current.position == goal_grid {
+            return AStarResult {
+                path: Some(expand_jump_path(reconstruct_path(came_from, current.position, grid), grid)),
+                nodes_expanded,
+                final_cost: current.g_cost,
+            };
+        }
+
+        nodes_expanded += 1;
+        if current.h_cost < best.0 {
+            best = (current.h_cost, current.position);
+        }
+        if nodes_expanded >= grid.max_nodes {
+            return AStarResult {
+                final_cost: g_score.get(&best.1).copied().unwrap_or(0),
+                path: Some(expand_jump_path(reconstruct_path(came_from, best.1, grid), grid)),
+                nodes_expanded,
+            };
+        }
+
+        for direction in jps_directions(grid, current.position, current.parent) {
+            let Some(jump_point) = jump(grid, current.position, direction, goal_grid) else {
+                continue;
+            };
+            let tentative_g =
+                current.g_cost + Heuristic::Octile.distance(current.position, jump_point);
+            if g_score.get(&jump_point).map_or(false, |&g| tentative_g >= g) {
+                continue;
+            }
+
+            came_from.insert(jump_point, current.position);
+            g_score.insert(jump_point, tentative_g);
+            open_set.push(Node {
+                position: jump_point,
+                g_cost: tentative_g,
+                h_cost: Heuristic::Octile.distance(jump_point, goal_grid),
+                cross: cross_tie_breaker(jump_point, start_grid, goal_grid),
+                parent: Some(current.position),
+            });
+        }
+    }
+
+    AStarResult {
+        path: None,
+        nodes_expanded,
+        final_cost: 0,
+    }
+}
+
+// Directions worth jumping in from `pos`, pruned by the direction we
+// arrived from. Diagonals need both sides open, same as step_cost
+fn jps_directions(grid: &Grid, pos: (i32, i32), parent: Option<(i32, i32)>) -> Vec<(i32, i32)> {
+    let Some(parent) = parent else {
+        return neighbor_offsets(grid)
+            .iter()
+            .copied()
+            .filter(|&(dx, dy)| step_cost(grid, pos, (pos.0 + dx, pos.1 + dy)).is_some())
+            .collect();
+    };
+
+    let (x, y) = pos;
+    let (dx, dy) = ((x - parent.0).signum(), (y - parent.1).signum());
+    let open = |cell: (i32, i32)| grid.is_walkable(cell);
+    let mut directions = Vec::new();
+    if dx != 0 && dy != 0 {
+        if open((x, y + dy)) {
+            directions.push((0, dy));
+        }
+        if open((x + dx, y)) {
+            directions.push((dx, 0));
+        }
+        if open((x, y + dy)) && open((x + dx, y)) {
+            directions.push((dx, dy));
+        }
+    } else {
+        // Straight on, plus turning off to either side. The diagonals ahead
+        // only open up once the straight cell and that side are both clear
+        let (side_a, side_b) = match dx != 0 {
+            true => ((0, 1), (0, -1)),
+            false => ((1, 0), (-1, 0)),
+        };
+        let is_ahead_open = open((x + dx, y + dy));
+        if is_ahead_open {
+            directions.push((dx, dy));
+        }
+        for side in [side_a, side_b] {
+            if !open((x + side.0, y + side.1)) {
+                continue;
+            }
+            directions.push(side);
+            if is_ahead_open {
+                directions.push((dx + side.0, dy + side.1));
+            }
+        }
+    }
+
+    directions
+}
+
+// Walks from `from` in `direction` until it reaches the goal or a cell with
+// a forced neighbour, None when it runs into a wall first
+fn jump(grid: &Grid, from: (i32, i32), direction: (i32, i32), goal: (i32, i32)) -> Option<(i32, i32)> {
+    let (dx, dy) = direction;
+    let (mut x, mut y) = (from.0 + dx, from.1 + dy);
+    let open = |cell: (i32, i32)| grid.is_walkable(cell);
+    loop {
+        if !open((x, y)) {
+            return None;
+        }
+        if (x, y) == goal {
+            return Some((x, y));
+        }
+
+        if dx != 0 && dy != 0 {
+            // A diagonal stops wherever one of its straight components would
+            if jump(grid, (x, y), (dx, 0), goal).is_some()
+                || jump(grid, (x, y), (0, dy), goal).is_some()
+            {
+                return Some((x, y));
+            }
+        } else if dx != 0 {
+            // Wall behind us on a side that's open here, the path may turn
+            if (open((x, y + 1)) && !open((x - dx, y + 1)))
+                || (open((x, y - 1)) && !open((x - dx, y - 1)))
+            {
+                return Some((x, y));
+            }
+        } else if (open((x + 1, y)) && !open((x + 1, y - dy)))
+            || (open((x - 1, y)) && !open((x - 1, y - dy)))
+        {
+            return Some((x, y));
+        }
+
+        // Diagonals can't squeeze past a corner
+        if !open((x + dx, y)) || !open((x, y + dy)) {
+            return None;
+        }
+        x += dx;
+        y += dy;
+    }
+}
+
+// Fills in the cells between consecutive jump points, every leg is either
+// straight or a pure diagonal
+fn expand_jump_path(jump_points: Vec<Vec2>, grid: &Grid) -> Vec<Vec2> {
+    let mut path = Vec::new();
+    for pair in jump_points.windows(2) {
+        let (from, to) = (grid.world_to_grid(pair[0]), grid.world_to_grid(pair[1]));
+        let (dx, dy) = ((to.0 - from.0).signum(), (to.1 - from.1).signum());
+        let mut cell = from;
+        while cell != to {
+            path.push(grid.grid_to_world(cell));
+            cell = (cell.0 + dx, cell.1 + dy);
+        }
+    }
+    if let Some(&last) = jump_points.last() {
+        path.push(last);
+    }
+
+    path
+}
+
+// Theta* any-angle search, None when there's no route
+pub fn find_path_theta(grid: &Grid, start: Vec2, goal: Vec2) -> Option<Vec<Vec2>> {
+    find_path_theta_with_stats(grid, start, goal).path
+}
+
+// Same search as find_path_with_stats, except a neighbour takes the current
+// node's parent as its own whenever the two can see each other, so the
+// path runs straight between corners instead of hopping cell to cell
+pub fn find_path_theta_with_stats(grid: &Grid, start: Vec2, goal: Vec2) -> AStarResult {
+    let start_grid = grid.world_to_grid(start);
+    let goal_grid = grid.clamp_to_bounds(grid.world_to_grid(goal));
+    
+    if !grid.is_walkable(start_grid) || !grid.is_walkable(goal_grid) {
+        return AStarResult {
+            path: None,
+            nodes_expanded: 0,
+            final_cost: 0,
+        };
+    }
+    
+    // Straight line distance stays admissible for any-angle moves
+    let min_cost = grid.min_cost();
+    let heuristic = |pos: (i32, i32)| Heuristic::Euclidean.distance(pos, goal_grid) * min_cost / STRAIGHT_MOVE_COST;
+    let mut open_set = BinaryHeap::new();
+    let mut came_from: HashMap<(i32, i32), (i32, i32)> = HashMap::new();
+    let mut g_score: HashMap<(i32, i32), i32> = HashMap::new();
+    
+    let start_node = Node {
+        position: start_grid,
+        g_cost: 0,
+        h_cost: heuristic(start_grid),
+        cross: 0,
+        parent: None,
+    };
+    
+    let mut best = (start_node.h_cost, start_grid);
+    let mut nodes_expanded = 0;
+    
+    open_set.push(start_node);
+    g_score.insert(start_grid, 0);
+    
+    while let Some(current) = open_set.pop() {
+        if g_score.get(&current.position).map_or(false, |&g| current.g_cost > g) {
+            continue;
+        }
+        
+        if current.position == goal_grid {
+            return AStarResult {
+                path: Some(reconstruct_path(came_from, current.position, grid)),
+                nodes_expanded,
+                final_cost: current.g_cost,
+            };
+        }
+        
+        nodes_expanded += 1;
+        if current.h_cost < best.0 {
+            best = (current.h_cost, current.position);
+        }
+        if nodes_expanded >= grid.max_nodes {
+            return AStarResult {
+                final_cost: g_score.get(&best.1).copied().unwrap_or(0),
+                path: Some(reconstruct_path(came_from, best.1, grid)),
+                nodes_expanded,
+            };
+        }
+        
+        let parent = came_from.get(&current.position).copied();
+        
+        for &(dx, dy) in neighbor_offsets(grid) {
+            let neighbor_pos = (current.position.0 + dx, current.position.1 + dy);
+            
+            let Some(movement_cost) = step_cost(grid, current.position, neighbor_pos) else {
+                continue;
+            };
+            
+            // Skip the current node entirely when its parent can see the neighbour
+            let (via, tentative_g) = match parent {
+                Some(parent) if cells_in_sight(grid, parent, neighbor_pos) => {
+                    (parent, g_score[&parent] + line_cost(grid, parent, neighbor_pos))
+                }
+                _ => (current.position, current.g_cost + movement_cost),
+            };
+            
+            if let Some(&existing_g) = g_score.get(&neighbor_pos) {
+                if tentative_g >= existing_g {
+                    continue;
+                }
+            }
+            
+            came_from.insert(neighbor_pos, via);
+            g_score.insert(neighbor_pos, tentative_g);
+            
+            open_set.push(Node {
+                position: neighbor_pos,
+                g_cost: tentative_g,
+                h_cost: heuristic(neighbor_pos),
+                cross: cross_tie_breaker(neighbor_pos, start_grid, goal_grid),
+                parent: Some(via),
+            });
+        }
+    }
+    
+    AStarResult {
+        path: None,
+        nodes_expanded,
+        final_cost: 0,
+    }
+}
+
+fn cells_in_sight(grid: &Grid, from: (i32, i32), to: (i32, i32)) -> bool {
+    supercover_line(from, to).iter().all(|cell| grid.is_walkable(*cell))
+}
+
+// Straight segment cost, charged at the most expensive cell it crosses
+fn line_cost(grid: &Grid, from: (i32, i32), to: (i32, i32)) -> i32 {
+    let max_cost = supercover_line(from, to)
+        .iter()
+        .map(|cell| grid.cost(*cell))
+        .max()
+        .unwrap_or(STRAIGHT_MOVE_COST);
+    let (dx, dy) = ((to.0 - from.0) as f32, (to.1 - from.1) as f32);
+    ((dx * dx + dy * dy).sqrt() * max_cost as f32).round() as i32
+}
+
+// "Infinite" cost, low enough that adding a heuristic can't overflow
+const DSTAR_INF: i32 = i32::MAX / 4;
+
+type DStarKey = (i32, i32);
+
+// D* Lite (Koenig & Likhachev) searches backwards from the goal and keeps its
+// g/rhs values and queue between frames, so a replan only repairs the cells
+// whose obstacle status changed instead of starting over
+pub struct DStarLite {
+    goal: (i32, i32),
+    last_start: (i32, i32),
+    k_m: i32,
+    min_cost: i32,
+    g: HashMap<(i32, i32), i32>,
+    rhs: HashMap<(i32, i32), i32>,
+    open: BinaryHeap<Reverse<(DStarKey, (i32, i32))>>,
+    // Current key of every queued cell, older heap entries are stale
+    queued: HashMap<(i32, i32), DStarKey>,
+    // Obstacles the g/rhs values were computed against
+    blocked: HashSet<(i32, i32)>,
+}
+
+impl DStarLite {
+    pub fn new(grid: &Grid, start: (i32, i32), goal: (i32, i32)) -> Self {
+        let mut dstar = Self {
+            goal,
+            last_start: start,
+            k_m: 0,
+            min_cost: grid.min_cost(),
+            g: HashMap::new(),
+            rhs: HashMap::new(),
+            open: BinaryHeap::new(),
+            queued: HashMap::new(),
+            blocked: blocked_cells(grid),
+        };
+        dstar.rhs.insert(goal, 0);
+        let key = dstar.calculate_key(grid, goal);
+        dstar.push(goal, key);
+        dstar
+    }
+
+    pub fn goal(&self) -> (i32, i32) {
+        self.goal
+    }
+
+    // Bring the plan up to date with the grid and extract a path from start
+    pub fn replan(&mut self, grid: &Grid, start: (i32, i32)) -> AStarResult {
+        // Cheaper terrain would make the old heuristic overestimate
+        if grid.min_cost() != self.min_cost {
+            *self = Self::new(grid, start, self.goal);
+        }
+
+        self.k_m += scaled_heuristic(grid, self.min_cost, self.last_start, start);
+        self.last_start = start;
+
+        let blocked = blocked_cells(grid);
+        let changed: Vec<(i32, i32)> = blocked.symmetric_difference(&self.blocked).copied().collect();
+        self.blocked = blocked;
+        for cell in changed {
+            self.update_vertex(grid, cell);
+            for &(dx, dy) in neighbor_offsets(grid) {
+                self.update_vertex(grid, (cell.0 + dx, cell.1 + dy));
+            }
+        }
+
+        let nodes_expanded = self.compute_shortest_path(grid);
+        let final_cost = self.g_value(start);
+        AStarResult {
+            path: self.extract_path(grid, start),
+            nodes_expanded,
+            final_cost: if final_cost < DSTAR_INF { final_cost } else { 0 },
+        }
+    }
+
+    fn g_value(&self, pos: (i32, i32)) -> i32 {
+        self.g.get(&pos).copied().unwrap_or(DSTAR_INF)
+    }
+
+    fn rhs_value(&self, pos: (i32, i32)) -> i32 {
+        self.rhs.get(&pos).copied().unwrap_or(DSTAR_INF)
+    }
+
+    fn calculate_key(&self, grid: &Grid, pos: (i32, i32)) -> DStarKey {
+        let best = self.g_value(pos).min(self.rhs_value(pos));
+        let h = scaled_heuristic(grid, self.min_cost, self.last_start, pos);
+        (best + h + self.k_m, best)
+    }
+
+    fn push(&mut self, pos: (i32, i32), key: DStarKey) {
+        self.queued.insert(pos, key);
+        self.open.push(Reverse((key, pos)));
+    }
+
+    // Smallest live key in the queue, dropping stale entries on the way
+    fn top(&mut self) -> Option<(DStarKey, (i32, i32))> {
+        while let Some(&Reverse((key, pos))) = self.open.peek() {
+            if self.queued.get(&pos) == Some(&key) {
+                return Some((key, pos));
+            }
+            self.open.pop();
+        }
+        None
+    }
+
+    // Cheapest one step lookahead through the successors of a cell
+    fn best_successor(&self, grid: &Grid, pos: (i32, i32)) -> Option<((i32, i32), i32)> {
+        neighbor_offsets(grid)
+            .iter()
+            .filter_map(|&(dx, dy)| {
+                let next = (pos.0 + dx, pos.1 + dy);
+                let cost = step_cost(grid, pos, next)?;
+                Some((next, (cost + self.g_value(next)).min(DSTAR_INF)))
+            })
+            .min_by_key(|&(next, cost)| (cost, next))
+    }
+
+    fn update_vertex(&mut self, grid: &Grid, pos: (i32, i32)) {
+        if !grid.is_valid(pos) {
+            return;
+        }
+        if pos != self.goal {
+            let rhs = self.best_successor(grid, pos).map_or(DSTAR_INF, |(_, cost)| cost);
+            self.rhs.insert(pos, rhs);
+        }
+        self.queued.remove(&pos);
+        if self.g_value(pos) != self.rhs_value(pos) {
+            let key = self.calculate_key(grid, pos);
+            self.push(pos, key);
+        }
+    }
+
+    fn compute_shortest_path(&mut self, grid: &Grid) -> usize {
+        let start = self.last_start;
+        let mut nodes_expanded = 0;
+
+        while let Some((old_key, pos)) = self.top() {
+            if old_key >= self.calculate_key(grid, start)
+                && self.rhs_value(start) == self.g_value(start)
+            {
+                break;
+            }
+            if nodes_expanded >= grid.max_nodes {
+                break;
+            }
+            nodes_expanded += 1;
+
+            let new_key = self.calculate_key(grid, pos);
+            if old_key < new_key {
+                // The car moved since this cell was queued
+                self.push(pos, new_key);
+                continue;
+            }
+
+            self.queued.remove(&pos);
+            if self.g_value(pos) > self.rhs_value(pos) {
+                self.g.insert(pos, self.rhs_value(pos));
+            } else {
+                self.g.insert(pos, DSTAR_INF);
+                self.update_vertex(grid, pos);
+            }
+            for &(dx, dy) in neighbor_offsets(grid) {
+                self.update_vertex(grid, (pos.0 - dx, pos.1 - dy));
+            }
+        }
+
+        nodes_expanded
+    }
+
+    // Follow the cheapest successors from start down to the goal
+    fn extract_path(&self, grid: &Grid, start: (i32, i32)) -> Option<Vec<Vec2>> {
+        if self.g_value(start) >= DSTAR_INF {
+            return None;
+        }
+
+        let mut current = start;
+        let mut path = vec![grid.grid_to_world(current)];
+        let mut visited = HashSet::from([current]);
+        while current != self.goal {
+            let (next, cost) = self.best_successor(grid, current)?;
+            // Out of date values can loop, bail out rather than spin
+            if cost >= DSTAR_INF || !visited.insert(next) {
+                return None;
+            }
+            current = next;
+            path.push(grid.grid_to_world(current));
+        }
+
+        Some(path)
+    }
+}
+
+// Every cell the planner has to route around
+fn blocked_cells(grid: &Grid) -> HashSet<(i32, i32)> {
+    grid.obstacles
+        .iter()
+        .chain(grid.inflated_obstacles.iter())
+        .chain(grid.static_obstacles.iter())
+        .copied()
+        .collect()
+}
+
+// 4 cardinal directions, plus the diagonals when enabled
+fn neighbor_offsets(grid: &Grid) -> &'static [(i32, i32)] {
+    if grid.allow_diagonal {
+        &[
+            (0, -1), // North
+            (1,  0), // East
+            (0,  1), // South
+            (-1, 0), // West
+            (1, -1),
+            (1,  1),
+            (-1, 1),
+            (-1, -1),
+        ]
+    } else {
+        &[
+            (0, -1), // North
+            (1,  0), // East
+            (0,  1), // South
+            (-1, 0), // West
+        ]
+    }
+}
+
+// Cost of stepping into a neighbouring cell, None when the move isn't allowed
+fn step_cost(grid: &Grid, from: (i32, i32), to: (i32, i32)) -> Option<i32> {
+    if !grid.is_walkable(from) || !grid.is_walkable(to) {
+        return None;
+    }
+
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let is_diagonal = dx != 0 && dy != 0;
+    // Don't cut corners past an obstacle
+    if is_diagonal
+        && (!grid.is_walkable((from.0 + dx, from.1)) || !grid.is_walkable((from.0, from.1 + dy)))
+    {
+        return None;
+    }
+
+    // Cost of entering the neighbour, diagonals scaled by ~sqrt(2)
+    let cell_cost = grid.cost(to);
+    match is_diagonal {
+        true => Some(cell_cost * DIAGONAL_MOVE_COST / STRAIGHT_MOVE_COST),
+        false => Some(cell_cost),
+    }
+}
+
+// Old fallback behaviour, drive straight ahead when there's no route
+pub fn find_path_or_forward(grid: &Grid, start: Vec2, goal: Vec2) -> Vec<Vec2> {
+    find_path(grid, start, goal).unwrap_or_else(|| forward_path(start, goal))
+}
+
+fn forward_path(start: Vec2, goal: Vec2) -> Vec<Vec2> {
+    vec![
+        start + Vec2::new(0.0, 50.0),
+        start + Vec2::new(0.0, 100.0),
+        start + Vec2::new(0.0, 150.0),
+        goal
+    ]
+}
+
+// String pulling, drops every waypoint whose neighbours can see each other
+// with room for the car body on both sides, otherwise shortcuts clip corners
+pub fn smooth_path(grid: &Grid, path: &[Vec2]) -> Vec<Vec2> {
+    if path.len() < 3 {
+        return path.to_vec();
+    }
+
+    let mut smoothed = vec![path[0]];
+    let mut anchor = path[0];
+    for i in 1..path.len() - 1 {
+        let next = path[i + 1];
+        if !has_line_of_sight(grid, anchor, next)
+            || !grid.clear_for_radius(anchor, next, ASTAR_CAR_HALF_EXTENT)
+        {
+            smoothed.push(path[i]);
+            anchor = path[i];
+        }
+    }
+    smoothed.push(path[path.len() - 1]);
+
+    smoothed
+}
+
+pub fn has_line_of_sight(grid: &Grid, from: Vec2, to: Vec2) -> bool {
+    cells_in_sight(grid, grid.world_to_grid(from), grid.world_to_grid(to))
+}
+
+// Every cell the segment touches, including both sides of exact corners
+fn supercover_line(from: (i32, i32), to: (i32, i32)) -> Vec<(i32, i32)> {
+    let nx = (to.0 - from.0).abs();
+    let ny = (to.1 - from.1).abs();
+    let sx = (to.0 - from.0).signum();
+    let sy = (to.1 - from.1).signum();
+
+    let (mut x, mut y) = from;
+    let (mut ix, mut iy) = (0, 0);
+    let mut cells = vec![from];
+    while ix < nx || iy < ny {
+        let decision = (1 + 2 * ix) * ny - (1 + 2 * iy) * nx;
+        if decision == 0 {
+            // Passing exactly through a corner
+            cells.push((x + sx, y));
+            cells.push((x, y + sy));
+            x += sx;
+            y += sy;
+            ix += 1;
+            iy += 1;
+        } else if decision < 0 {
+            x += sx;
+            ix += 1;
+        } else {
+            y += sy;
+            iy += 1;
+        }
+        cells.push((x, y));
+    }
+
+    cells
+}
+
+// Ramer-Douglas-Peucker simplification, keeps only the points that deviate
+// more than `epsilon` from the line between the kept neighbours
+pub fn simplify_path(path: &[Vec2], epsilon: f32) -> Vec<Vec2> {
+    if path.len() < 3 {
+        return path.to_vec();
+    }
+
+    let first = path[0];
+    let last = path[path.len() - 1];
+    let mut max_dist = 0.0;
+    let mut max_idx = 0;
+    for (i, point) in path.iter().enumerate().take(path.len() - 1).skip(1) {
+        let dist = distance_to_line(*point, first, last);
+        if dist > max_dist {
+            max_dist = dist;
+            max_idx = i;
+        }
+    }
+
+    if max_dist <= epsilon {
+        return vec![first, last];
+    }
+
+    let mut simplified = simplify_path(&path[..=max_idx], epsilon);
+    simplified.pop();
+    simplified.extend(simplify_path(&path[max_idx..], epsilon));
+    simplified
+}
+
+fn distance_to_line(point: Vec2, start: Vec2, end: Vec2) -> f32 {
+    let line = end - start;
+    if line.length_squared() == 0.0 {
+        return point.distance(start);
+    }
+
+    (line.perp_dot(point - start) / line.length()).abs()
+}
+
+// Heuristic in the grid's cost units, scaled down to the cheapest cell so it
+// never overestimates and A* stays optimal
+fn scaled_heuristic(grid: &Grid, min_cost: i32, a: (i32, i32), b: (i32, i32)) -> i32 {
+    grid.heuristic.distance(a, b) * min_cost / STRAIGHT_MOVE_COST
+}
+
+// Scaled heuristic inflated by the grid's epsilon, no longer admissible
+// above 1 so the search settles for a bounded suboptimal path sooner
+fn weighted_heuristic(grid: &Grid, min_cost: i32, a: (i32, i32), b: (i32, i32)) -> i32 {
+    (scaled_heuristic(grid, min_cost, a, b) as f32 * grid.epsilon.max(1.0)) as i32
+}
+
+// Cross product of (pos - goal) and (start - goal), zero on the straight line
+fn cross_tie_breaker(pos: (i32, i32), start: (i32, i32), goal: (i32, i32)) -> i32 {
+    let (dx1, dy1) = (pos.0 - goal.0, pos.1 - goal.1);
+    let (dx2, dy2) = (start.0 - goal.0, start.1 - goal.1);
+    (dx1 * dy2 - dx2 * dy1).abs()
+}
+
+// Cheap, well mixed per cell hash (splitmix64 finalizer)
+fn cell_hash(pos: (i32, i32)) -> u64 {
+    let mut h = ((pos.0 as u32 as u64) << 32) | pos.1 as u32 as u64;
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^ (h >> 31)
+}
+
+impl Heuristic {
+    // Estimated cost between two cells, in the same units as g_cost
+    pub fn distance(&self, a: (i32, i32), b: (i32, i32)) -> i32 {
+        let dx = (a.0 - b.0).abs();
+        let dy = (a.1 - b.1).abs();
+        match self {
+            Heuristic::Manhattan => STRAIGHT_MOVE_COST * (dx + dy),
+            Heuristic::Euclidean => {
+                (STRAIGHT_MOVE_COST as f32 * ((dx * dx + dy * dy) as f32).sqrt()) as i32
+            }
+            Heuristic::Chebyshev => STRAIGHT_MOVE_COST * dx.max(dy),
+            Heuristic::Octile => {
+                STRAIGHT_MOVE_COST * (dx + dy)
+                    + (DIAGONAL_MOVE_COST - 2 * STRAIGHT_MOVE_COST) * dx.min(dy)
+            }
+        }
+    }
+}
+
+fn reconstruct_path(came_from: HashMap<(i32, i32), (i32, i32)>, mut current: (i32, i32), grid: &Grid) -> Vec<Vec2> {
+    let mut path = Vec::new();
+    
+    path.push(grid.grid_to_world(current));
+    
+    while let Some(&parent) = came_from.get(&current) {
+        current = parent;
+        path.push(grid.grid_to_world(current));
+    }
+    
+    path.reverse();
+    path
+}
+
+// Obstacle scanning needs the RapierContext so it stays on the main thread,
+// cars that need a new path are flagged for astar_search_system
+fn astar_scan_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<Settings>,
+    pathfinding_config: Res<PathfindingConfig>,
+    rapier_context: Res<RapierContext>,
+    goal_query: Query<&Transform, With<Goal>>,
+    mut query: Query<
+        (Entity, &Transform, &Velocity, &mut AStarCar, &mut PathfindingBrain),
+        (With<AStarAgent>, With<Car>),
+    >,
 ) {
-    for (mut transform, mut astar_car) in query.iter_mut() {
+    let road_goal = goal_query.get_single().ok().map(|t| t.translation.truncate());
+    for (entity, transform, velocity, mut astar_car, mut brain) in query.iter_mut() {
+        if let Some(road_goal) = road_goal.filter(|_| !brain.is_manual_goal) {
+            brain.goal = road_goal;
+        }
+        if pathfinding_config.is_changed() {
+            let recalc_interval = Duration::from_secs_f32(pathfinding_config.recalc_interval);
+            let check_interval = Duration::from_secs_f32(pathfinding_config.obstacle_check_interval);
+            astar_car.recalculate_timer.set_duration(recalc_interval);
+            astar_car.obstacle_check_timer.set_duration(check_interval);
+        }
+        astar_car.recalculate_timer.tick(time.delta());
+        astar_car.obstacle_check_timer.tick(time.delta());
+        
+        let current_pos = Vec2::new(transform.translation.x, transform.translation.y);
+        
+        if brain.is_goal_reached {
+            continue;
+        }
+        
+        let goal = brain.current_goal();
+        if current_pos.distance(goal) <= brain.grid.cell_size {
+            astar_car.path.clear();
+            if !brain.is_manual_goal && brain.next_checkpoint < brain.checkpoints.len() {
+                // On to the next checkpoint straight away
+                brain.next_checkpoint += 1;
+                astar_car.force_replan = true;
+            } else {
+                brain.is_goal_reached = true;
+                commands.entity(entity).insert(CarOutcome::Reached);
+            }
+            continue;
+        }
+        
+        // Recalculate path periodically or when car has moved significantly
+        let mut should_replan = astar_car.recalculate_timer.finished()
+            || brain.last_position.distance(current_pos) > pathfinding_config.replan_distance
+            || astar_car.force_replan;
+        if should_replan || astar_car.obstacle_check_timer.finished() {
+            // Update obstacle map
+            let scan_radius = pathfinding_config.scan_radius(velocity.linvel.length());
+            if settings.is_incremental_scan && !settings.force_rescan {
+                brain.grid.update_obstacles_incremental(
+                    &rapier_context,
+                    current_pos,
+                    scan_radius,
+                    time.elapsed_seconds(),
+                    settings.scan_freshness_seconds,
+                );
+            } else {
+                brain.grid.update_obstacles(
+                    &rapier_context,
+                    current_pos,
+                    scan_radius,
+                    time.elapsed_seconds(),
+                    settings.scan_freshness_seconds,
+                    settings.force_rescan,
+                );
+            }
+            let inflation_radius = brain.grid.inflation_radius;
+            brain.grid.inflate_obstacles(inflation_radius);
+            // The car is already sitting in its cell, never pad it shut
+            let start_cell = brain.grid.world_to_grid(current_pos);
+            brain.grid.inflated_obstacles.remove(&start_cell);
+        }
+        
+        // Something new on the road ahead can't wait for the timer
+        let remaining_path = astar_car.path.get(astar_car.current_target..).unwrap_or(&[]);
+        if !should_replan
+            && astar_car.obstacle_check_timer.finished()
+            && brain.grid.path_blocked(remaining_path)
+        {
+            should_replan = true;
+        }
+        
+        if should_replan {
+            // The cached path is what got the car stuck
+            if astar_car.force_replan {
+                astar_car.force_replan = false;
+                brain.path_cache_key = None;
+            }
+            
+            brain.last_position = current_pos;
+            brain.pending_search = Some((current_pos, goal));
+        }
+    }
+}
+
+// Each brain owns its grid, so the searches can run side by side
+fn astar_search_system(
+    settings: Res<Settings>,
+    diagnostics: Option<Res<Diagnostics>>,
+    mut sim_stats: ResMut<SimStats>,
+    // Searches allowed per frame, None while frames are within the target
+    mut search_budget: Local<Option<usize>>,
+    mut query: Query<(&mut AStarCar, &mut PathfindingBrain), (With<AStarAgent>, With<Car>)>,
+) {
+    // Headless runs have no frame time diagnostics and never throttle
+    let frame_ms = diagnostics
+        .as_ref()
+        .and_then(|diagnostics| diagnostics.get(FrameTimeDiagnosticsPlugin::FPS))
+        .and_then(|fps| fps.smoothed())
+        .filter(|&fps| fps > 0.0)
+        .map(|fps| (1000.0 / fps) as f32);
+    let num_pending = query.iter().filter(|(_, brain)| brain.pending_search.is_some()).count();
+    update_search_budget(&mut search_budget, frame_ms, settings.target_frame_ms, num_pending);
+
+    // Searches past the budget stay pending for a later frame
+    let budget = search_budget.unwrap_or(usize::MAX);
+    let num_started = AtomicUsize::new(0);
+    let search = |astar_car: &mut AStarCar, brain: &mut PathfindingBrain| {
+        if brain.pending_search.is_some()
+            && num_started.fetch_add(1, atomic::Ordering::Relaxed) >= budget
+        {
+            return;
+        }
+        run_pending_search(&settings, astar_car, brain);
+    };
+
+    let started_at = Instant::now();
+    if settings.is_parallel_search {
+        query.par_iter_mut().for_each_mut(|(mut astar_car, mut brain)| {
+            search(&mut astar_car, &mut brain);
+        });
+    } else {
+        for (mut astar_car, mut brain) in query.iter_mut() {
+            search(&mut astar_car, &mut brain);
+        }
+    }
+    sim_stats.total_search_secs += started_at.elapsed().as_secs_f32();
+    
+    for (_, mut brain) in query.iter_mut() {
+        if let Some((nodes_expanded, final_cost)) = brain.last_search.take() {
+            sim_stats.last_nodes_explored = nodes_expanded;
+            sim_stats.last_path_cost = final_cost;
+            sim_stats.search_samples.push((nodes_expanded, final_cost));
+        }
+    }
+    let num_samples = sim_stats.search_samples.len();
+    if num_samples > ASTAR_SEARCH_SAMPLES {
+        sim_stats.search_samples.drain(..num_samples - ASTAR_SEARCH_SAMPLES);
+    }
+}
+
+// Halves the searches allowed per frame while frames run over the target
+// time, then lets one more through each frame they're back under it
+fn update_search_budget(
+    budget: &mut Option<usize>,
+    frame_ms: Option<f32>,
+    target_ms: f32,
+    num_pending: usize,
+) {
+    let Some(frame_ms) = frame_ms.filter(|_| target_ms > 0.0) else {
+        *budget = None;
+        return;
+    };
+
+    let is_over_target = frame_ms > target_ms;
+    *budget = match (*budget, is_over_target) {
+        (None, true) if num_pending > 1 => {
+            let limit = num_pending / 2;
+            info!(
+                "Frame time {:.1}ms over the {:.1}ms target, throttling A* to {} searches per frame",
+                frame_ms, target_ms, limit
+            );
+            Some(limit)
+        }
+        (Some(limit), true) => Some((limit / 2).max(1)),
+        (Some(limit), false) if limit >= num_pending => {
+            info!("Frame time back under {:.1}ms, A* no longer throttled", target_ms);
+            None
+        }
+        (Some(limit), false) => Some(limit + 1),
+        (None, _) => None,
+    };
+}
+
+fn run_pending_search(settings: &Settings, astar_car: &mut AStarCar, brain: &mut PathfindingBrain) {
+    let Some((current_pos, goal)) = brain.pending_search.take() else {
+        return;
+    };
+    
+    // A new weight changes the search even on the same grid
+    let epsilon = settings.astar_epsilon.max(1.0);
+    if brain.grid.epsilon != epsilon {
+        brain.grid.epsilon = epsilon;
+        brain.path_cache_key = None;
+    }
+    let turn_cost = settings.astar_turn_cost.max(0);
+    if brain.grid.turn_cost != turn_cost {
+        brain.grid.turn_cost = turn_cost;
+        brain.path_cache_key = None;
+    }
+    // So does another planner or simplifying its path differently
+    if brain.planner != Some(settings.planner) {
+        brain.planner = Some(settings.planner);
+        brain.path_cache_key = None;
+    }
+    if brain.path_simplify_epsilon != settings.path_simplify_epsilon {
+        brain.path_simplify_epsilon = settings.path_simplify_epsilon;
+        brain.path_cache_key = None;
+    }
+    
+    // Same inputs as last time, the search would come out identical
+    let start_cell = brain.grid.world_to_grid(current_pos);
+    let cache_key = (start_cell, brain.grid.world_to_grid(goal), brain.grid.obstacle_hash);
+    if brain.path_cache_key == Some(cache_key) {
         if astar_car.path.is_empty() {
-            // Default forward movement if no path
-            let movement_direction = transform.rotation * Vec3::Y;
-            transform.translation += movement_direction * 100.0 * time.delta_seconds();
+            astar_car.set_path(brain.cached_path.clone());
+        }
+        return;
+    }
+    
+    // Find new path
+    let result = match settings.planner {
+        Planner::AStar => find_path_with_stats(&brain.grid, current_pos, goal),
+        Planner::ThetaStar => find_path_theta_with_stats(&brain.grid, current_pos, goal),
+        Planner::Bidirectional => find_path_bidirectional_with_stats(&brain.grid, current_pos, goal),
+        Planner::JumpPoint => find_path_jps_with_stats(&brain.grid, current_pos, goal),
+        Planner::TurnPenalized => find_path_turn_penalized_with_stats(&brain.grid, current_pos, goal),
+        Planner::DStarLite => {
+            let goal_cell = brain.grid.clamp_to_bounds(cache_key.1);
+            // A different goal invalidates everything the planner knows
+            if brain.dstar.as_ref().map_or(false, |dstar| dstar.goal() != goal_cell) {
+                brain.dstar = None;
+            }
+            brain
+                .dstar
+                .get_or_insert_with(|| DStarLite::new(&brain.grid, start_cell, goal_cell))
+                .replan(&brain.grid, start_cell)
+        }
+    };
+    brain.last_search = Some((result.nodes_expanded, result.final_cost));
+    let path = result.path;
+    astar_car.is_path_blocked = path.is_none();
+    let mut path = match path {
+        Some(path) => smooth_path(&brain.grid, &path),
+        None => forward_path(current_pos, goal),
+    };
+    if settings.path_simplify_epsilon > 0.0 {
+        path = simplify_path(&path, settings.path_simplify_epsilon);
+    }
+    astar_car.set_path(path);
+    brain.path_cache_key = Some(cache_key);
+    brain.cached_path = astar_car.path.clone();
+}
+
+fn astar_path_debug_system(
+    mut lines: ResMut<DebugLines>,
+    settings: Res<Settings>,
+    query: Query<&AStarCar, (With<AStarAgent>, With<Car>)>,
+) {
+    if !settings.draw_astar_paths {
+        return;
+    }
+
+    for astar_car in query.iter() {
+        for (start, end) in astar_car.path.iter().zip(astar_car.path.iter().skip(1)) {
+            lines.line_colored(start.extend(0.0), end.extend(0.0), 0.0, Color::CYAN);
+        }
+
+        // Cross on the waypoint the car is currently heading to
+        if let Some(target) = astar_car.path.get(astar_car.current_target) {
+            let target = target.extend(0.0);
+            let size = 8.0;
+            lines.line_colored(
+                target - Vec3::new(size, size, 0.0),
+                target + Vec3::new(size, size, 0.0),
+                0.0,
+                Color::ORANGE,
+            );
+            lines.line_colored(
+                target - Vec3::new(size, -size, 0.0),
+                target + Vec3::new(size, -size, 0.0),
+                0.0,
+                Color::ORANGE,
+            );
+        }
+    }
+}
+
+fn spawn_goal_marker(mut commands: Commands) {
+    commands.spawn((
+        SpriteBundle {
+            transform: Transform::from_xyz(0.0, 0.0, 5.0),
+            sprite: Sprite {
+                color: Color::rgb(1.0, 0.8, 0.0),
+                custom_size: Some(Vec2::splat(24.0)),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        GoalMarker,
+    ));
+}
+
+// Left click sends the lead car to the nearest walkable cell under the
+// cursor, right click hands every car back its automatic goal
+fn manual_goal_input_system(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    settings: Res<Settings>,
+    mouse_input: Res<Input<MouseButton>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    cam_query: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    mut car_query: Query<(Entity, &Transform, &mut AStarCar, &mut PathfindingBrain), With<Car>>,
+    mut manual_goal: ResMut<ManualGoal>,
+) {
+    // Ignore clicks meant for the egui panels
+    if contexts.ctx_mut().wants_pointer_input()
+        || settings.is_grid_inspector
+        || settings.is_placing_obstacles
+    {
+        return;
+    }
+
+    if mouse_input.just_pressed(MouseButton::Right) {
+        manual_goal.0 = None;
+        for (entity, _, mut astar_car, mut brain) in car_query.iter_mut() {
+            if brain.is_manual_goal {
+                brain.is_manual_goal = false;
+                brain.is_goal_reached = false;
+                commands.entity(entity).remove::<CarOutcome>();
+                astar_car.force_replan = true;
+            }
+        }
+        return;
+    }
+    if !mouse_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    let (Ok(window), Ok((camera, cam_transform))) =
+        (window_query.get_single(), cam_query.get_single()) else {
+        return;
+    };
+    if let Some(world_pos) = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(cam_transform, cursor))
+        .map(|ray| ray.origin.truncate())
+    {
+        // Clicking a car selects it for the camera instead
+        let is_on_car = car_query
+            .iter()
+            .any(|(_, t, _, _)| t.translation.truncate().distance(world_pos) < CAR_PICK_RADIUS);
+        if is_on_car {
+            return;
+        }
+
+        let Some((entity, _, mut astar_car, mut brain)) =
+            lead_car(car_query.iter_mut(), |(_, t, _, _)| t.translation.y)
+        else {
+            return;
+        };
+        let Some(cell) = brain.grid.nearest_walkable(brain.grid.world_to_grid(world_pos)) else {
+            return;
+        };
+        let goal = brain.grid.grid_to_world(cell);
+        brain.goal = goal;
+        brain.is_manual_goal = true;
+        // Gives a car that already finished something to do again
+        brain.is_goal_reached = false;
+        commands.entity(entity).remove::<CarOutcome>();
+        astar_car.force_replan = true;
+        manual_goal.0 = Some(goal);
+    }
+}
+
+// Shows the lead car's cell under the cursor, a left click toggles an
+// obstacle there and replans. The next scan may clear it again
+fn grid_inspector_system(
+    mut contexts: EguiContexts,
+    settings: Res<Settings>,
+    mouse_input: Res<Input<MouseButton>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    cam_query: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    mut car_query: Query<(&Transform, &mut AStarCar, &mut PathfindingBrain), With<Car>>,
+) {
+    if !settings.is_grid_inspector {
+        return;
+    }
+    let (Ok(window), Ok((camera, cam_transform))) =
+        (window_query.get_single(), cam_query.get_single()) else {
+        return;
+    };
+    let Some(world_pos) = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(cam_transform, cursor))
+        .map(|ray| ray.origin.truncate())
+    else {
+        return;
+    };
+    let Some((_, mut astar_car, mut brain)) =
+        lead_car(car_query.iter_mut(), |(t, _, _)| t.translation.y)
+    else {
+        return;
+    };
+
+    let cell = brain.grid.world_to_grid(world_pos);
+    let ctx = contexts.ctx_mut();
+    if mouse_input.just_pressed(MouseButton::Left)
+        && !ctx.wants_pointer_input()
+        && brain.grid.is_valid(cell)
+    {
+        match brain.grid.obstacles.contains(&cell) {
+            true => brain.grid.remove_obstacle(cell),
+            false => brain.grid.add_obstacle(cell),
+        }
+        let inflation_radius = brain.grid.inflation_radius;
+        brain.grid.inflate_obstacles(inflation_radius);
+        astar_car.force_replan = true;
+    }
+
+    egui::Area::new("grid_inspector")
+        .anchor(egui::Align2::LEFT_BOTTOM, egui::vec2(10.0, -10.0))
+        .show(ctx, |ui| {
+            ui.label(format!(
+                "Cell ({}, {})  walkable: {}  cost: {}",
+                cell.0,
+                cell.1,
+                brain.grid.is_walkable(cell),
+                brain.grid.cost(cell)
+            ));
+            ui.label(format!(
+                "Obstacles: {}  fingerprint: {:016x}",
+                brain.grid.obstacles.len(),
+                brain.grid.obstacle_fingerprint()
+            ));
+        });
+}
+
+fn goal_marker_system(
+    manual_goal: Res<ManualGoal>,
+    mut marker_query: Query<(&mut Transform, &mut Visibility), With<GoalMarker>>,
+) {
+    if !manual_goal.is_changed() {
+        return;
+    }
+
+    for (mut transform, mut visibility) in marker_query.iter_mut() {
+        match manual_goal.0 {
+            Some(goal) => {
+                transform.translation.x = goal.x;
+                transform.translation.y = goal.y;
+                *visibility = Visibility::Visible;
+            }
+            None => *visibility = Visibility::Hidden,
+        }
+    }
+}
+
+fn astar_movement_system(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    mut query: Query<
+        (&Transform, &mut Velocity, &mut AStarCar, &PathfindingBrain),
+        (With<AStarAgent>, With<Car>),
+    >,
+) {
+    for (transform, mut velocity, mut astar_car, brain) in query.iter_mut() {
+        if settings.is_changed() {
+            astar_car.speed = settings.astar_speed;
+            astar_car.turn_rate = settings.astar_turn_rate;
+            astar_car.allow_reverse = settings.astar_allow_reverse;
+        }
+        if brain.is_goal_reached {
+            *velocity = Velocity::zero();
+            continue;
+        }
+        
+        let heading = transform.local_y().truncate();
+        let current_pos = transform.translation.truncate();
+        
+        astar_car.check_stuck(current_pos);
+        astar_car.check_target();
+        astar_car.advance_target(current_pos);
+        let Some(target) = astar_car.look_ahead_point(current_pos) else {
+            // No path, or reached the end of it, keep driving forward until
+            // the next recalculation
+            astar_car.path.clear();
+            astar_car.current_target = 0;
+            velocity.linvel = heading * astar_car.speed;
+            velocity.angvel = 0.0;
+            continue;
+        };
+        
+        let direction = (target - current_pos).normalize_or_zero();
+        if direction == Vec2::ZERO {
+            velocity.linvel = heading * astar_car.speed;
+            velocity.angvel = 0.0;
             continue;
         }
         
-        // Follow the path
-        if astar_car.current_target < astar_car.path.len() {
-            let target = astar_car.path[astar_car.current_target];
-            let current_pos = Vec2::new(transform.translation.x, transform.translation.y);
-            let distance_to_target = current_pos.distance(target);
-            
-            // Check if reached current target (increased threshold for easier reaching)
-            if distance_to_target < 50.0 {
-                astar_car.current_target += 1;
-                
-                // If reached end of path, generate new path ahead
-                if astar_car.current_target >= astar_car.path.len() {
-                    astar_car.path.clear();
-                }
+        let (angle_diff, gear) = pick_gear(
+            normalize_angle(direction.y.atan2(direction.x) - heading.y.atan2(heading.x)),
+            astar_car.allow_reverse,
+        );
+        let dt = time.delta_seconds();
+        
+        if astar_car.min_turn_radius > 0.0 {
+            // Drive along the current heading, turning no faster than
+            // speed / radius so the car follows arcs
+            let max_turn_rate = astar_car.speed / astar_car.min_turn_radius;
+            velocity.linvel = heading * gear * astar_car.speed;
+            velocity.angvel = turn_toward(angle_diff, dt, max_turn_rate);
+            continue;
+        }
+        
+        // Head straight for the target and rotate to face it on the way
+        velocity.linvel = direction * astar_car.speed;
+        velocity.angvel = turn_toward(angle_diff, dt, astar_car.turn_rate);
+    }
+}
+
+// Turn still to make and the gear to drive in, 1 forward and -1 reverse. A
+// target behind the car needs less turning to reach tail first, the same
+// rotation swings the rear toward it
+fn pick_gear(angle_diff: f32, allow_reverse: bool) -> (f32, f32) {
+    if allow_reverse && angle_diff.abs() > std::f32::consts::FRAC_PI_2 {
+        return (normalize_angle(angle_diff - std::f32::consts::PI), -1.0);
+    }
+    (angle_diff, 1.0)
+}
+
+// Angular velocity toward a heading angle_diff away. Turning angle_diff / dt
+// lands exactly on it this frame and the clamp only slows that down, so frame
+// rate can't make the car overshoot. Within ASTAR_HEADING_EPSILON it snaps to
+// no turn at all to stop the jitter
+fn turn_toward(angle_diff: f32, dt: f32, max_turn_rate: f32) -> f32 {
+    if angle_diff.abs() < ASTAR_HEADING_EPSILON {
+        return 0.0;
+    }
+    (angle_diff / dt.max(f32::EPSILON)).clamp(-max_turn_rate, max_turn_rate)
+}
+
+// Crashing into a truck takes the car out of the run, same as the NN cars
+fn astar_collision_system(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut collision_events: EventReader<CollisionEvent>,
+    enemy_query: Query<(), With<EnemyType>>,
+    mut car_query: Query<&mut Velocity, (With<AStarAgent>, With<Car>)>,
+) {
+    for collision_event in collision_events.iter() {
+        let CollisionEvent::Started(entity1, entity2, _) = collision_event else {
+            continue;
+        };
+        if !settings.is_astar_death {
+            continue;
+        }
+
+        for (car, other) in [(*entity1, *entity2), (*entity2, *entity1)] {
+            if !enemy_query.contains(other) {
                 continue;
             }
-            
-            let direction = (target - current_pos).normalize();
-            
-            // Check if direction is valid
-            if direction.is_nan() {
-                let movement_direction = transform.rotation * Vec3::Y;
-                transform.translation += movement_direction * 100.0 * time.delta_seconds();
-                continue;
+            if let Ok(mut velocity) = car_query.get_mut(car) {
+                *velocity = Velocity::zero();
+                commands.entity(car).insert(CarOutcome::Crashed).remove::<Car>();
             }
-            
-            // Simple direct movement towards target (no rotation for now)
-            let movement = direction * 100.0 * time.delta_seconds();
-            transform.translation += Vec3::new(movement.x, movement.y, 0.0);
-            
-            // Optional: rotate to face movement direction
-            let target_angle = direction.y.atan2(direction.x) - std::f32::consts::PI / 2.0;
-            let current_angle = transform.rotation.z;
-            let angle_diff = target_angle - current_angle;
-            
-            // Normalize angle difference to [-π, π]
-            let normalized_diff = ((angle_diff + std::f32::consts::PI) % (2.0 * std::f32::consts::PI)) - std::f32::consts::PI;
-            transform.rotate_z(normalized_diff * 2.0 * time.delta_seconds());
-            
-        } else {
-            // Reached end of path, clear it to trigger recalculation
-            astar_car.path.clear();
-            astar_car.current_target = 0;
         }
     }
 }
 
+// Rotation about z that turns the car's local forward (+Y) from `from`
+// toward `to`, 0 when they're on top of each other
+fn heading_toward(from: Vec2, to: Vec2) -> f32 {
+    let direction = to - from;
+    match direction == Vec2::ZERO {
+        true => 0.0,
+        false => (-direction.x).atan2(direction.y),
+    }
+}
+
+fn normalize_angle(angle: f32) -> f32 {
+    let mut angle = angle % (2.0 * std::f32::consts::PI);
+    if angle > std::f32::consts::PI {
+        angle -= 2.0 * std::f32::consts::PI;
+    } else if angle < -std::f32::consts::PI {
+        angle += 2.0 * std::f32::consts::PI;
+    }
+    angle
+}
+
 // Bundle for A* cars
 #[derive(Bundle)]
 pub struct AStarCarBundle {
     sprite_bundle: SpriteBundle,
     car: Car,
+    trail: Trail,
     astar_agent: AStarAgent, // Marker component
+    timeout: CarTimeout,
     astar: AStarCar,
     pathfinding_brain: PathfindingBrain,
     velocity: Velocity,
@@ -390,10 +2668,19 @@ pub struct AStarCarBundle {
 }
 
 impl AStarCarBundle {
-    pub fn new(asset_server: &AssetServer, spawn_x: f32, spawn_y: f32) -> Self {
+    // `spawn_heading` is the car's rotation about z, 0 faces +Y. Movement
+    // only ever drives along the car's local_y so any heading works
+    pub fn new(
+        asset_server: &AssetServer,
+        grid_config: &GridConfig,
+        spawn_x: f32,
+        spawn_y: f32,
+        spawn_heading: f32,
+    ) -> Self {
         Self {
             sprite_bundle: SpriteBundle {
                 transform: Transform::from_xyz(spawn_x, spawn_y, 0.0)
+                    .with_rotation(Quat::from_rotation_z(spawn_heading))
                     .with_scale(vec3(2.5, 2.5, 1.0)),
                 texture: asset_server.load("agent.png"),
                 sprite: Sprite {
@@ -403,63 +2690,1052 @@ impl AStarCarBundle {
                 ..default()
             },
             car: Car,
+            trail: Trail::default(),
             astar_agent: AStarAgent, // Add the marker component
+            timeout: CarTimeout::default(),
             astar: AStarCar::new(),
-            pathfinding_brain: PathfindingBrain::new(),
+            pathfinding_brain: PathfindingBrain::new(grid_config),
             velocity: Velocity::zero(),
             mass: ColliderMassProperties::Mass(3000.0),
             rigid_body: RigidBody::Dynamic,
             collider: Collider::cuboid(5.0, 8.0),
             events: ActiveEvents::COLLISION_EVENTS,
             damping: Damping {
-                angular_damping: 100.0,
-                linear_damping: 100.0,
+                // Velocity is set every frame, heavy damping would eat most of it
+                angular_damping: 1.0,
+                linear_damping: 1.0,
             },
             sleep: Sleeping::disabled(),
             ccd: Ccd::enabled(),
-            collision_groups: CollisionGroups {
-                memberships: Group::GROUP_1,
-                filters: Group::GROUP_2,
-            },
+            collision_groups: CAR_COLLISION_GROUPS,
         }
     }
 }
 
+#[derive(Serialize)]
+struct PathPoint {
+    x: f32,
+    y: f32,
+}
+
+// Path as a polyline over the grid's obstacles, in world coordinates with y
+// flipped so the far end of the road is at the top
+pub fn export_path_svg(path: &[Vec2], grid: &Grid, out: &mut impl Write) -> io::Result<()> {
+    let width = grid.width as f32 * grid.cell_size;
+    let height = grid.height as f32 * grid.cell_size;
+    let top = grid.origin.y + height;
+
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} 0 {} {}">"#,
+        grid.origin.x, width, height
+    )?;
+    writeln!(
+        out,
+        r#"<rect x="{}" y="0" width="{}" height="{}" fill="white"/>"#,
+        grid.origin.x, width, height
+    )?;
+
+    let mut obstacles: Vec<_> = grid.obstacles.union(&grid.static_obstacles).collect();
+    obstacles.sort();
+    for &cell in obstacles {
+        let corner = grid.grid_to_world(cell);
+        writeln!(
+            out,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="black"/>"#,
+            corner.x,
+            top - corner.y - grid.cell_size,
+            grid.cell_size,
+            grid.cell_size
+        )?;
+    }
+
+    let points: Vec<String> = path
+        .iter()
+        .map(|p| format!("{},{}", p.x, top - p.y))
+        .collect();
+    writeln!(
+        out,
+        r#"<polyline points="{}" fill="none" stroke="red" stroke-width="2"/>"#,
+        points.join(" ")
+    )?;
+    writeln!(out, "</svg>")
+}
+
+pub fn export_path_json(path: &[Vec2], out: &mut impl Write) -> io::Result<()> {
+    let points: Vec<PathPoint> = path.iter().map(|p| PathPoint { x: p.x, y: p.y }).collect();
+    serde_json::to_writer_pretty(&mut *out, &points)?;
+    writeln!(out)
+}
+
+fn write_path_files(path: &[Vec2], grid: &Grid) -> io::Result<()> {
+    let mut svg = BufWriter::new(File::create(ASTAR_PATH_SVG_PATH)?);
+    export_path_svg(path, grid, &mut svg)?;
+    svg.flush()?;
+
+    let mut json = BufWriter::new(File::create(ASTAR_PATH_JSON_PATH)?);
+    export_path_json(path, &mut json)?;
+    json.flush()
+}
+
+// Writes the lead car's current path when asked from the GUI
+fn astar_path_export_system(
+    mut settings: ResMut<Settings>,
+    query: Query<(&Transform, &AStarCar, &PathfindingBrain), (With<AStarAgent>, With<Car>)>,
+) {
+    if !settings.export_astar_path {
+        return;
+    }
+    settings.export_astar_path = false;
+
+    let Some((_, astar_car, brain)) = lead_car(query.iter(), |(t, _, _)| t.translation.y) else {
+        return;
+    };
+    match write_path_files(&astar_car.path, &brain.grid) {
+        Ok(_) => info!("Exported A* path to {} and {}", ASTAR_PATH_SVG_PATH, ASTAR_PATH_JSON_PATH),
+        Err(e) => error!("Failed to export A* path: {}", e),
+    }
+}
+
 // Separate plugin for A* population management
 pub struct AStarPopulationPlugin;
 
 impl Plugin for AStarPopulationPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
+        let is_astar = || in_mode(AlgorithmMode::AStar);
         app.insert_resource(MaxDistanceTravelled(0.0))  // Add this missing resource
             .insert_resource(BrainToDisplay::default())  // Add this too for GUI compatibility
-            .add_startup_system(setup_astar_cars)
-            .add_system(astar_stats_system);
+            .add_system(setup_astar_cars.run_if(mode_entered(AlgorithmMode::AStar)))
+            // Before `settings_system` so the generation flags still reach
+            // us and the fresh cars keep their Car component
+            .add_system(
+                astar_reset_system
+                    .in_base_set(CoreSet::PreUpdate)
+                    .run_if(is_astar()),
+            )
+            .add_system(astar_stats_system.run_if(is_astar()));
+    }
+}
+
+fn setup_astar_cars(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    grid_config: Res<GridConfig>,
+    asset_server: Res<AssetServer>,
+    mut sim_rng: ResMut<SimRng>,
+    track: Option<Res<Track>>,
+) {
+    spawn_astar_cars(
+        &mut commands,
+        &settings,
+        &grid_config,
+        &asset_server,
+        &mut sim_rng.0,
+        track.as_deref(),
+    );
+}
+
+// A* cars have nothing to breed, a new generation or a restart just puts
+// fresh cars back on the start line to run the same scenario again
+fn astar_reset_system(
+    mut commands: Commands,
+    mut settings: ResMut<Settings>,
+    mut sim_stats: ResMut<SimStats>,
+    mut max_distance_travelled: ResMut<MaxDistanceTravelled>,
+    mut history: ResMut<SimStatsHistory>,
+    grid_config: Res<GridConfig>,
+    generation_seed: Res<GenerationSeed>,
+    asset_server: Res<AssetServer>,
+    mut sim_rng: ResMut<SimRng>,
+    track: Option<Res<Track>>,
+    cars_query: Query<Entity, With<AStarAgent>>,
+) {
+    if !settings.start_next_generation && !settings.restart_sim {
+        return;
     }
+
+    if settings.restart_sim {
+        *sim_stats = SimStats::default();
+    } else {
+        // The car counts are still the ended generation's, the fresh cars
+        // only show up in them next frame
+        history.record(SimStatsSnapshot::ended(
+            &sim_stats,
+            &generation_seed,
+            sim_stats.num_cars_alive,
+            sim_stats.num_cars_finished,
+        ));
+        sim_stats.generation_count += 1;
+    }
+    settings.start_next_generation = false;
+    settings.restart_sim = false;
+    max_distance_travelled.0 = 0.0;
+
+    for entity in cars_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<FollowTarget>();
+    spawn_astar_cars(
+        &mut commands,
+        &settings,
+        &grid_config,
+        &asset_server,
+        &mut sim_rng.0,
+        track.as_deref(),
+    );
 }
 
-fn setup_astar_cars(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn spawn_astar_cars(
+    commands: &mut Commands,
+    settings: &Settings,
+    grid_config: &GridConfig,
+    asset_server: &AssetServer,
+    rng: &mut impl Rng,
+    track: Option<&Track>,
+) {
+    grid_config.validate(goal_position(track));
+
+    // Every car faces down the road, from the middle of the spawn area to
+    // the goal, so the formation stays lined up whichever way the road runs
+    let area = spawn_area(track);
+    let (left, right, start_y, _) = area;
+    let road_start = Vec2::new((left + right) / 2.0, start_y);
+    let spawn_heading = heading_toward(road_start, goal_position(track));
+
     // Spawn only A* cars
-    for i in 0..NUM_AI_CARS {
-        let spawn_x = 850.0 + (i as f32 % 10.0) * 15.0; // Arrange in rows
-        let spawn_y = WINDOW_HEIGHT / 2.0 + (i as f32 / 10.0).floor() * 30.0;
-        commands.spawn(AStarCarBundle::new(&asset_server, spawn_x, spawn_y));
+    let spawns = settings.spawn_formation.positions(settings.num_cars, area, rng);
+    for spawn in spawns {
+        let mut car =
+            AStarCarBundle::new(asset_server, grid_config, spawn.x, spawn.y, spawn_heading);
+        car.astar.speed = settings.astar_speed;
+        car.astar.turn_rate = settings.astar_turn_rate;
+        car.astar.allow_reverse = settings.astar_allow_reverse;
+        if let Some(track) = track {
+            car.pathfinding_brain.checkpoints = track.checkpoints.clone();
+        }
+        commands.spawn(car);
     }
 }
 
 fn astar_stats_system(
     mut sim_stats: ResMut<SimStats>,
     mut max_distance_travelled: ResMut<MaxDistanceTravelled>,
-    query: Query<&Transform, With<AStarAgent>>,
+    goal_query: Query<&Transform, With<Goal>>,
+    query: Query<(&Transform, &PathfindingBrain, Option<&Car>), With<AStarAgent>>,
+    outcome_query: Query<Option<&CarOutcome>, With<AStarAgent>>,
 ) {
     let mut max_distance = 0.0;
-    sim_stats.num_cars_alive = query.iter().len();
+    let num_cars = outcome_query.iter().len().max(1);
+    let num_reached = outcome_query
+        .iter()
+        .filter(|outcome| *outcome == Some(&CarOutcome::Reached))
+        .count();
+    sim_stats.success_rate = num_reached as f32 / num_cars as f32;
+    // Crashed cars lose their Car component
+    sim_stats.num_cars_alive = query.iter().filter(|(_, _, car)| car.is_some()).count();
+    sim_stats.num_cars_finished = query.iter().filter(|(_, b, _)| b.is_goal_reached).count();
+    sim_stats.checkpoints_cleared = query.iter().map(|(_, b, _)| b.next_checkpoint).collect();
 
-    for transform in query.iter() {
-        let distance = transform.translation.y / 340.0; // Same fitness calculation as NN cars
+    let Ok(goal) = goal_query.get_single() else {
+        return;
+    };
+    for (transform, _, _) in query.iter() {
+        // Same fitness calculation as NN cars
+        let distance = Goal::score(goal.translation.truncate(), transform.translation.truncate());
         if distance > max_distance {
             max_distance = distance;
             sim_stats.max_current_score = distance;
             max_distance_travelled.0 = transform.translation.y;
         }
     }
-}
\ No newline at end of file
+}
+
+// Snapshots the lead car's grid when asked from the GUI, numbered so
+// successive dumps can be diffed
+fn astar_grid_dump_system(
+    mut settings: ResMut<Settings>,
+    mut dumps: Local<u32>,
+    query: Query<(&Transform, &PathfindingBrain), (With<AStarAgent>, With<Car>)>,
+) {
+    if !settings.dump_astar_grid {
+        return;
+    }
+    settings.dump_astar_grid = false;
+
+    let Some((_, brain)) = lead_car(query.iter(), |(t, _)| t.translation.y) else {
+        return;
+    };
+    let path = format!("{}_{}.json", ASTAR_GRID_DUMP_PREFIX, *dumps);
+    match brain.grid.save(Path::new(&path)) {
+        Ok(_) => {
+            info!(
+                "Dumped A* grid to {} (fingerprint {:016x})",
+                path,
+                brain.grid.obstacle_fingerprint()
+            );
+            *dumps += 1;
+        }
+        Err(e) => error!("Failed to dump A* grid: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    // Unit cells from the origin, so cell (x, y) sits at world (x, y)
+    fn grid(width: i32, height: i32, walls: &[(i32, i32)]) -> Grid {
+        let mut grid = Grid::new(width, height, 1.0, Vec2::ZERO);
+        walls.iter().for_each(|&pos| grid.add_obstacle(pos));
+        grid
+    }
+
+    fn at(pos: (i32, i32)) -> Vec2 {
+        Vec2::new(pos.0 as f32, pos.1 as f32)
+    }
+
+    fn cells(grid: &Grid, path: &[Vec2]) -> Vec<(i32, i32)> {
+        path.iter().map(|&point| grid.world_to_grid(point)).collect()
+    }
+
+    #[test]
+    fn straight_path_on_empty_grid() {
+        let grid = grid(10, 3, &[]);
+        let result = find_path_with_stats(&grid, at((0, 1)), at((9, 1)));
+        let path = result.path.expect("no path on an empty grid");
+        assert_eq!(cells(&grid, &path), (0..10).map(|x| (x, 1)).collect::<Vec<_>>());
+        assert_eq!(result.final_cost, 9 * STRAIGHT_MOVE_COST);
+    }
+
+    #[test]
+    fn detours_around_obstacle() {
+        // Wall up column 2 with a gap on the top row
+        let walls = [(2, 0), (2, 1), (2, 2), (2, 3)];
+        let grid = grid(5, 5, &walls);
+        let result = find_path_with_stats(&grid, at((0, 0)), at((4, 0)));
+        let path = cells(&grid, &result.path.expect("no path through the gap"));
+        assert_eq!(path.first(), Some(&(0, 0)));
+        assert_eq!(path.last(), Some(&(4, 0)));
+        assert!(path.contains(&(2, 4)));
+        assert!(path.iter().all(|cell| !walls.contains(cell)));
+        assert!(path.windows(2).all(|pair| step_cost(&grid, pair[0], pair[1]).is_some()));
+        // Up to the gap, through it and back down, no corner cutting
+        let climb = DIAGONAL_MOVE_COST + 3 * STRAIGHT_MOVE_COST;
+        assert_eq!(result.final_cost, 2 * climb + 2 * STRAIGHT_MOVE_COST);
+    }
+
+    #[test]
+    fn unreachable_goal_has_no_path() {
+        let walls: Vec<_> = (0..5).map(|y| (2, y)).collect();
+        let grid = grid(5, 5, &walls);
+        assert!(find_path(&grid, at((0, 0)), at((4, 4))).is_none());
+    }
+
+    // Cheapest cost from start to every reachable cell, with the same moves
+    // as the A* searches
+    fn dijkstra(grid: &Grid, start: (i32, i32)) -> HashMap<(i32, i32), i32> {
+        let mut costs = HashMap::default();
+        costs.insert(start, 0);
+        let mut open_set = BinaryHeap::from([Reverse((0, start))]);
+        while let Some(Reverse((cost, cell))) = open_set.pop() {
+            if costs.get(&cell).map_or(false, |&best| cost > best) {
+                continue;
+            }
+            for &(dx, dy) in neighbor_offsets(grid) {
+                let neighbor = (cell.0 + dx, cell.1 + dy);
+                let Some(step) = step_cost(grid, cell, neighbor) else {
+                    continue;
+                };
+                if costs.get(&neighbor).map_or(true, |&best| cost + step < best) {
+                    costs.insert(neighbor, cost + step);
+                    open_set.push(Reverse((cost + step, neighbor)));
+                }
+            }
+        }
+        costs
+    }
+
+    fn random_grid(rng: &mut impl Rng, width: i32, height: i32) -> Grid {
+        let mut grid = grid(width, height, &[]);
+        for x in 0..width {
+            for y in 0..height {
+                match rng.gen_range(0..10) {
+                    0..=1 => grid.add_obstacle((x, y)),
+                    2..=4 => {
+                        grid.costs.insert((x, y), rng.gen_range(5..60));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        grid.remove_obstacle((0, 0));
+        grid.remove_obstacle((width - 1, height - 1));
+        grid
+    }
+
+    // Mixed terrain and diagonals reach cells by a dearer route first, the
+    // search has to take the cheaper one found later
+    #[test]
+    fn weighted_terrain_paths_are_optimal() {
+        let mut rng = StdRng::seed_from_u64(269);
+        for _ in 0..50 {
+            let grid = random_grid(&mut rng, 12, 12);
+            let goal = (11, 11);
+            let result = find_path_with_stats(&grid, at((0, 0)), at(goal));
+            let expected = dijkstra(&grid, (0, 0)).get(&goal).copied();
+            assert_eq!(result.path.as_ref().map(|_| result.final_cost), expected);
+            if let Some(path) = result.path {
+                let path = cells(&grid, &path);
+                let cost: i32 = path
+                    .windows(2)
+                    .map(|pair| step_cost(&grid, pair[0], pair[1]).expect("invalid step"))
+                    .sum();
+                assert_eq!(cost, result.final_cost);
+            }
+        }
+    }
+
+    // Physics world holding one thin wall along y = x, from about (30, 30)
+    // to (170, 170)
+    fn diagonal_wall() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(TransformPlugin)
+            .add_plugin(HierarchyPlugin)
+            .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0));
+        app.world.spawn((
+            Collider::cuboid(100.0, 1.0),
+            OBSTACLE_COLLISION_GROUPS,
+            TransformBundle::from(
+                Transform::from_xyz(100.0, 100.0, 0.0)
+                    .with_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_4)),
+            ),
+        ));
+        // Colliders are synced on the first update, queryable after a step
+        for _ in 0..3 {
+            app.update();
+        }
+        app
+    }
+
+    // Cell (2, 3) is scanned from (40, 60). The wall only crosses the corner
+    // of its square at (50, 50), out of reach of the cardinal rays
+    #[test]
+    fn diagonal_rays_catch_walls_across_corners() {
+        let app = diagonal_wall();
+        let rapier_context = app.world.resource::<RapierContext>();
+        let scan = |include_diagonals: bool| {
+            let mut grid = Grid::new(10, 10, 20.0, Vec2::ZERO);
+            grid.scan_directions = scan_directions(include_diagonals);
+            let center = Vec2::new(100.0, 100.0);
+            grid.update_obstacles(rapier_context, center, 100.0, 0.0, 0.25, true);
+            grid
+        };
+
+        let cardinal = scan(false);
+        assert!(cardinal.is_obstacle((3, 3)));
+        assert!(!cardinal.is_obstacle((2, 3)));
+        assert!(!cardinal.is_obstacle((3, 2)));
+
+        let diagonal = scan(true);
+        assert!(diagonal.is_obstacle((3, 3)));
+        assert!(diagonal.is_obstacle((2, 3)));
+        assert!(diagonal.is_obstacle((3, 2)));
+        assert!(!diagonal.is_obstacle((2, 6)));
+    }
+
+    // Skipping a fresh cell doesn't skip its decay, a truck seen once still
+    // ages out while its cell is too fresh to rescan
+    #[test]
+    fn fresh_cells_are_skipped_but_still_decay() {
+        let rapier_context = RapierContext::default();
+        let mut grid = grid(20, 20, &[]);
+        let scan = |grid: &mut Grid, now: f32, force: bool| {
+            grid.update_obstacles(&rapier_context, at((10, 10)), 5.0, now, 0.25, force);
+        };
+
+        scan(&mut grid, 0.0, false);
+        grid.add_obstacle((12, 12));
+        scan(&mut grid, 0.1, false);
+        assert_eq!(grid.scanned_at.get(&(12, 12)), Some(&0.0));
+        assert!(grid.is_obstacle((12, 12)));
+
+        // Forced, the miss is cast but only decay takes the cell away
+        scan(&mut grid, 0.2, true);
+        assert_eq!(grid.scanned_at.get(&(12, 12)), Some(&0.2));
+        assert!(grid.is_obstacle((12, 12)));
+
+        scan(&mut grid, 0.8, false);
+        assert!(!grid.is_obstacle((12, 12)));
+        assert!(!grid.obstacles.contains(&(12, 12)));
+    }
+
+    #[test]
+    fn incremental_scan_rescans_stale_cells() {
+        let rapier_context = RapierContext::default();
+        let mut grid = grid(20, 20, &[]);
+        let window: Vec<_> = (5..15).flat_map(|x| (5..15).map(move |y| (x, y))).collect();
+
+        grid.update_obstacles_incremental(&rapier_context, at((10, 10)), 5.0, 0.0, 0.25);
+        assert!(window.iter().all(|cell| grid.scanned_at.get(cell) == Some(&0.0)));
+        // Still fresh, nothing is cast again
+        grid.update_obstacles_incremental(&rapier_context, at((10, 10)), 5.0, 0.1, 0.25);
+        assert!(window.iter().all(|cell| grid.scanned_at.get(cell) == Some(&0.0)));
+        // Stale, so the clear cells that never left the window are rescanned
+        grid.update_obstacles_incremental(&rapier_context, at((10, 10)), 5.0, 1.0, 0.25);
+        assert!(window.iter().all(|cell| grid.scanned_at.get(cell) == Some(&1.0)));
+    }
+
+    fn scattered_walls(rng: &mut impl Rng, width: i32, height: i32, density: f64) -> Grid {
+        let mut grid = grid(width, height, &[]);
+        for x in 0..width {
+            for y in 0..height {
+                if rng.gen_bool(density) {
+                    grid.add_obstacle((x, y));
+                }
+            }
+        }
+        grid.remove_obstacle((0, 0));
+        grid.remove_obstacle((width - 1, height - 1));
+        grid
+    }
+
+    #[test]
+    fn jps_paths_cost_the_same_as_astar() {
+        let mut rng = StdRng::seed_from_u64(298);
+        for _ in 0..50 {
+            let grid = scattered_walls(&mut rng, 16, 16, 0.25);
+            let goal = at((15, 15));
+            let astar = find_path_with_stats(&grid, at((0, 0)), goal);
+            let jps = find_path_jps_with_stats(&grid, at((0, 0)), goal);
+            assert_eq!(jps.path.is_some(), astar.path.is_some());
+            let Some(path) = jps.path else {
+                continue;
+            };
+            assert_eq!(jps.final_cost, astar.final_cost);
+            let path = cells(&grid, &path);
+            assert!(path.windows(2).all(|pair| step_cost(&grid, pair[0], pair[1]).is_some()));
+        }
+    }
+
+    #[test]
+    fn jps_skips_open_road() {
+        let grid = grid(100, 200, &[]);
+        let astar = find_path_with_stats(&grid, at((3, 0)), at((90, 199)));
+        let jps = find_path_jps_with_stats(&grid, at((3, 0)), at((90, 199)));
+        assert_eq!(jps.final_cost, astar.final_cost);
+        assert!(jps.nodes_expanded * 10 <= astar.nodes_expanded);
+    }
+
+    fn direction_changes(path: &[(i32, i32)]) -> usize {
+        let steps: Vec<_> = path.windows(2).map(|p| (p[1].0 - p[0].0, p[1].1 - p[0].1)).collect();
+        steps.windows(2).filter(|pair| pair[0] != pair[1]).count()
+    }
+
+    // Turn penalized paths can be longer but never turn more, and on
+    // cluttered layouts they turn a lot less
+    #[test]
+    fn turn_penalty_straightens_paths() {
+        let mut rng = StdRng::seed_from_u64(332);
+        let (mut plain_turns, mut penalized_turns) = (0, 0);
+        for _ in 0..50 {
+            let grid = scattered_walls(&mut rng, 16, 16, 0.25);
+            let goal = at((15, 15));
+            let (Some(plain), Some(penalized)) = (
+                find_path(&grid, at((0, 0)), goal),
+                find_path_turn_penalized(&grid, at((0, 0)), goal),
+            ) else {
+                continue;
+            };
+            let plain = direction_changes(&cells(&grid, &plain));
+            let penalized = direction_changes(&cells(&grid, &penalized));
+            assert!(penalized <= plain);
+            plain_turns += plain;
+            penalized_turns += penalized;
+        }
+        assert!(penalized_turns < plain_turns);
+    }
+
+    #[test]
+    fn bidirectional_paths_cost_the_same_as_astar() {
+        let mut rng = StdRng::seed_from_u64(292);
+        for _ in 0..50 {
+            let grid = scattered_walls(&mut rng, 16, 16, 0.25);
+            let goal = at((15, 15));
+            let astar = find_path_with_stats(&grid, at((0, 0)), goal);
+            let bidirectional = find_path_bidirectional_with_stats(&grid, at((0, 0)), goal);
+            assert_eq!(bidirectional.path.is_some(), astar.path.is_some());
+            let Some(path) = bidirectional.path else {
+                continue;
+            };
+            assert_eq!(bidirectional.final_cost, astar.final_cost);
+            let path = cells(&grid, &path);
+            assert_eq!((path[0], path[path.len() - 1]), ((0, 0), (15, 15)));
+            assert!(path.windows(2).all(|pair| step_cost(&grid, pair[0], pair[1]).is_some()));
+        }
+    }
+
+    // Goal at the bottom of a cup facing away from the start. Forward A*
+    // floods the wall's near side, the backward half walks straight out
+    #[test]
+    fn bidirectional_explores_less_around_walled_goal() {
+        let mut walls: Vec<_> = (45..=55).map(|x| (x, 170)).collect();
+        walls.extend((170..=180).flat_map(|y| [(45, y), (55, y)]));
+        let mut grid = grid(100, 200, &walls);
+        grid.max_nodes = 50_000;
+        let (start, goal) = (at((50, 0)), at((50, 175)));
+        let astar = find_path_with_stats(&grid, start, goal);
+        let bidirectional = find_path_bidirectional_with_stats(&grid, start, goal);
+        assert_eq!(bidirectional.final_cost, astar.final_cost);
+        assert!(bidirectional.nodes_expanded < astar.nodes_expanded);
+    }
+
+    // One frame of astar_search_system with 50 cars all due a search on
+    // the same cluttered road, returns how long it took
+    fn time_search_frame(settings: Settings) -> Duration {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(settings)
+            .insert_resource(SimStats::default())
+            .add_system(astar_search_system);
+        let mut rng = StdRng::seed_from_u64(274);
+        for _ in 0..50 {
+            let mut brain = PathfindingBrain::new(&GridConfig::default());
+            brain.grid = scattered_walls(&mut rng, 60, 200, 0.2);
+            brain.grid.max_nodes = 50_000;
+            brain.pending_search = Some((at((0, 0)), at((59, 199))));
+            app.world.spawn((AStarCar::new(), brain, AStarAgent, Car));
+        }
+
+        let started_at = Instant::now();
+        app.update();
+        let elapsed = started_at.elapsed();
+        let mut brains = app.world.query::<&PathfindingBrain>();
+        assert!(brains.iter(&app.world).all(|brain| brain.pending_search.is_none()));
+        elapsed
+    }
+
+    #[test]
+    fn search_samples_stay_capped() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(Settings::default())
+            .insert_resource(SimStats {
+                search_samples: vec![(0, 0); ASTAR_SEARCH_SAMPLES],
+                ..default()
+            })
+            .add_system(astar_search_system);
+        for _ in 0..5 {
+            let mut brain = PathfindingBrain::new(&GridConfig::default());
+            brain.grid = grid(10, 10, &[]);
+            brain.pending_search = Some((at((0, 0)), at((9, 9))));
+            app.world.spawn((AStarCar::new(), brain, AStarAgent, Car));
+        }
+
+        app.update();
+        let samples = &app.world.resource::<SimStats>().search_samples;
+        assert_eq!(samples.len(), ASTAR_SEARCH_SAMPLES);
+        // Oldest dropped to make room for every car's search
+        assert_eq!(samples.iter().filter(|&&sample| sample != (0, 0)).count(), 5);
+    }
+
+    // cargo test --release -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_search() {
+        let planners = [
+            (Planner::AStar, "A*"),
+            (Planner::ThetaStar, "Theta*"),
+            (Planner::Bidirectional, "Bidirectional"),
+            (Planner::JumpPoint, "JPS"),
+            (Planner::TurnPenalized, "Turn penalized"),
+            (Planner::DStarLite, "D* Lite"),
+        ];
+        for (planner, name) in planners {
+            let time = |is_parallel_search| {
+                time_search_frame(Settings {
+                    planner,
+                    is_parallel_search,
+                    ..default()
+                })
+            };
+            let (serial, parallel) = (time(false), time(true));
+            println!(
+                "{:<15} 50 cars serial {:>8.2}ms, parallel {:>8.2}ms, {:.1}x",
+                name,
+                serial.as_secs_f32() * 1000.0,
+                parallel.as_secs_f32() * 1000.0,
+                serial.as_secs_f32() / parallel.as_secs_f32().max(f32::EPSILON)
+            );
+        }
+    }
+
+    // A repeated search is served from the cache until the planner or the
+    // simplification it was made with changes
+    #[test]
+    fn changing_planner_misses_path_cache() {
+        let mut brain = PathfindingBrain::new(&GridConfig::default());
+        let mut astar_car = AStarCar::new();
+        let (start, goal) = (Vec2::new(ROAD_CENTER_X, 100.0), Vec2::new(ROAD_CENTER_X, 1000.0));
+        let mut searches = |settings: &Settings| {
+            brain.pending_search = Some((start, goal));
+            run_pending_search(settings, &mut astar_car, &mut brain);
+            brain.last_search.take().is_some()
+        };
+        let mut settings = Settings::default();
+        assert!(searches(&settings));
+        assert!(!searches(&settings));
+        settings.planner = Planner::ThetaStar;
+        assert!(searches(&settings));
+        settings.path_simplify_epsilon += 1.0;
+        assert!(searches(&settings));
+        assert!(!searches(&settings));
+    }
+
+    #[test]
+    fn reachable_stops_at_node_budget() {
+        // Goal walled into the far corner of a grid too big to flood
+        let mut grid = grid(100, 100, &[(98, 99), (98, 98), (99, 98)]);
+        assert!(!grid.reachable((0, 0), (99, 99)));
+        grid.max_nodes = 50;
+        assert!(grid.reachable((0, 0), (99, 99)));
+        assert!(!grid.reachable((0, 0), (98, 98)));
+    }
+
+    // The straight-line tie-breaker keeps an open grid's frontier down to
+    // the path itself, without it every cell in the start-goal box ties
+    #[test]
+    fn open_grid_expands_only_the_path() {
+        let grid = grid(100, 200, &[]);
+        for (start, goal) in [((50, 0), (50, 199)), ((3, 0), (90, 199)), ((99, 10), (0, 150))] {
+            let result = find_path_with_stats(&grid, at(start), at(goal));
+            let path = result.path.expect("no path on an open grid");
+            assert!(result.nodes_expanded < path.len(), "{} expanded", result.nodes_expanded);
+        }
+    }
+
+    // Many routes tie on cost here, these are the ones the open set's
+    // ordering picks
+    #[test]
+    fn tied_paths_are_pinned() {
+        let mut open = grid(5, 5, &[]);
+        open.allow_diagonal = false;
+        let path = find_path(&open, at((0, 0)), at((3, 3))).expect("no path");
+        let expected = [(0, 0), (0, 1), (1, 1), (1, 2), (2, 2), (2, 3), (3, 3)];
+        assert_eq!(cells(&open, &path), expected);
+
+        let mut walled = grid(6, 6, &[(2, 2), (3, 2), (2, 3)]);
+        let path = find_path(&walled, at((0, 0)), at((5, 5))).expect("no path");
+        let expected = [(0, 0), (1, 1), (1, 2), (1, 3), (1, 4), (2, 5), (3, 5), (4, 5), (5, 5)];
+        assert_eq!(cells(&walled, &path), expected);
+
+        walled.allow_diagonal = false;
+        let path = find_path(&walled, at((0, 0)), at((5, 5))).expect("no path");
+        let expected = [
+            (0, 0), (0, 1), (1, 1), (1, 2), (1, 3), (1, 4), (2, 4), (3, 4), (4, 4), (4, 5), (5, 5),
+        ];
+        assert_eq!(cells(&walled, &path), expected);
+    }
+
+    #[test]
+    fn nodes_equal_by_their_ordering_key() {
+        let node = |g_cost, h_cost, parent| Node {
+            position: (1, 1),
+            g_cost,
+            h_cost,
+            cross: 0,
+            parent,
+        };
+        // Same key through different parents
+        let (a, b) = (node(10, 20, Some((0, 0))), node(10, 20, Some((0, 1))));
+        assert!(a == b && a.cmp(&b) == Ordering::Equal);
+        // Same f, split differently between g and h
+        let (a, b) = (node(10, 20, None), node(15, 15, None));
+        assert!(a != b && a.cmp(&b) != Ordering::Equal);
+    }
+
+    #[test]
+    fn scan_radius_grows_with_speed() {
+        let config = PathfindingConfig::default();
+        assert_eq!(config.scan_radius(0.0), config.base_scan_radius);
+        let cruising = config.scan_radius(ASTAR_MOVEMENT_SPEED);
+        assert!(cruising > config.base_scan_radius);
+        assert!(config.scan_radius(2.0 * ASTAR_MOVEMENT_SPEED) > cruising);
+    }
+
+    // A faster car's scan covers more cells around it
+    #[test]
+    fn fast_cars_scan_more_cells() {
+        let rapier_context = RapierContext::default();
+        let config = PathfindingConfig::default();
+        let scanned_cells = |speed: f32| {
+            let mut grid = Grid::new(100, 100, 20.0, Vec2::ZERO);
+            let (center, radius) = (Vec2::splat(1000.0), config.scan_radius(speed));
+            grid.update_obstacles(&rapier_context, center, radius, 0.0, 0.25, true);
+            grid.scanned_at.len()
+        };
+        assert!(scanned_cells(2.0 * ASTAR_MOVEMENT_SPEED) > scanned_cells(0.0));
+    }
+
+    fn default_grid() -> Grid {
+        let config = GridConfig::default();
+        Grid::new(config.width, config.height, config.cell_size, config.origin)
+    }
+
+    // Reaches past both walls from the road's start to its far end
+    #[test]
+    fn default_grid_covers_road() {
+        let grid = default_grid();
+        for x in [ROAD_LEFT_X, ROAD_RIGHT_X] {
+            for y in [0.0, ROAD_END_Y] {
+                let cell = grid.world_to_grid(Vec2::new(x, y));
+                assert!(grid.is_valid(cell), "({}, {}) is off the grid", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn road_walls_fall_on_cell_boundaries() {
+        let offsets = GridConfig::default().wall_offsets();
+        let is_aligned = offsets.iter().all(|&offset| offset <= ASTAR_WALL_ALIGN_TOLERANCE);
+        assert!(is_aligned, "walls {:?} off a cell boundary", offsets);
+        let grid = default_grid();
+        let first_road_cell = grid.world_to_grid(Vec2::new(ROAD_LEFT_X + 1.0, 0.0));
+        let last_road_cell = grid.world_to_grid(Vec2::new(ROAD_RIGHT_X - 1.0, 0.0));
+        assert_eq!(last_road_cell.0 - first_road_cell.0 + 1, ASTAR_ROAD_WIDTH_CELLS);
+    }
+
+    // The finish line goal is a cell of its own, not clamped into the top
+    // row, so a search from the spawn area ends on it
+    #[test]
+    fn default_goal_is_on_default_grid() {
+        let grid = default_grid();
+        let goal = goal_position(None);
+        let goal_cell = grid.world_to_grid(goal);
+        assert!(grid.is_valid(goal_cell));
+        assert!(goal_cell.1 < grid.height - 1);
+        let start = Vec2::new(ROAD_CENTER_X, SPAWN_AREA_DEPTH);
+        let path = find_path(&grid, start, goal).expect("no path down the open road");
+        assert_eq!(cells(&grid, &path).last(), Some(&goal_cell));
+    }
+
+    #[test]
+    fn bad_cell_sizes_fall_back_to_default() {
+        for cell_size in [0.0, -5.0, f32::NAN, f32::INFINITY] {
+            let grid = Grid::new(10, 10, cell_size, Vec2::ZERO);
+            assert_eq!(grid.cell_size, ASTAR_CELL_SIZE);
+            assert_eq!(grid.world_to_grid(Vec2::new(45.0, 45.0)), (2, 2));
+            assert!(find_path(&grid, Vec2::ZERO, Vec2::new(100.0, 100.0)).is_some());
+        }
+    }
+
+    #[test]
+    fn empty_grids_keep_one_cell() {
+        let grid = Grid::new(0, -4, 20.0, Vec2::ZERO);
+        assert_eq!((grid.width, grid.height), (1, 1));
+        assert!(grid.is_valid((0, 0)));
+    }
+
+    #[test]
+    fn world_to_grid_floors_below_origin() {
+        let grid = Grid::new(10, 10, 20.0, Vec2::new(600.0, 0.0));
+        assert_eq!(grid.world_to_grid(Vec2::new(605.0, 5.0)), (0, 0));
+        assert_eq!(grid.world_to_grid(Vec2::new(595.0, 5.0)), (-1, 0));
+        assert_eq!(grid.world_to_grid(Vec2::new(580.0, -5.0)), (-1, -1));
+        assert_eq!(grid.world_to_grid(Vec2::new(575.0, -25.0)), (-2, -2));
+        // Round trips from a cell's corner land back in it on both sides
+        for cell in [(-3, -1), (0, 0), (4, 2)] {
+            assert_eq!(grid.world_to_grid(grid.grid_to_world(cell)), cell);
+        }
+    }
+
+    #[test]
+    fn clamps_cells_outside_grid() {
+        let grid = grid(10, 20, &[]);
+        assert_eq!(grid.clamp_to_bounds((4, 35)), (4, 19));
+        assert_eq!(grid.clamp_to_bounds((4, -3)), (4, 0));
+        assert_eq!(grid.clamp_to_bounds((-2, 7)), (0, 7));
+        assert_eq!(grid.clamp_to_bounds((12, 7)), (9, 7));
+        assert_eq!(grid.clamp_to_bounds((5, 5)), (5, 5));
+    }
+
+    // A goal past the top of the grid is headed for through the top row
+    #[test]
+    fn goal_above_grid_paths_to_top_row() {
+        let grid = grid(10, 20, &[]);
+        let path = find_path(&grid, at((4, 0)), at((4, 500))).expect("no path to the top row");
+        assert_eq!(cells(&grid, &path).last(), Some(&(4, 19)));
+    }
+
+    fn following(path: &[(i32, i32)]) -> AStarCar {
+        let mut astar_car = AStarCar::new();
+        astar_car.set_path(path.iter().map(|&cell| at(cell) * 20.0).collect());
+        astar_car
+    }
+
+    // Drives a car down a straight path by small steps toward its look-ahead
+    // point, every waypoint is passed in turn
+    #[test]
+    fn straight_path_is_followed_without_skipping() {
+        let mut astar_car = following(&(1..=10).map(|y| (0, y)).collect::<Vec<_>>());
+        let mut position = Vec2::ZERO;
+        let mut reached = Vec::new();
+        for _ in 0..100 {
+            let last_target = astar_car.current_target;
+            astar_car.advance_target(position);
+            assert!(astar_car.current_target <= last_target + 1);
+            reached.extend(last_target..astar_car.current_target);
+            let Some(target) = astar_car.look_ahead_point(position) else {
+                break;
+            };
+            assert_eq!(target.x, 0.0);
+            assert!(target.y > position.y);
+            position += (target - position).normalize() * 5.0;
+        }
+        assert_eq!(reached, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn look_ahead_point_follows_path_around_corner() {
+        let mut astar_car = following(&[(0, 1), (0, 2), (1, 2)]);
+        astar_car.look_ahead = 50.0;
+        assert_eq!(astar_car.look_ahead_point(Vec2::ZERO), Some(Vec2::new(10.0, 40.0)));
+        // Past the end it's clamped to the last waypoint
+        astar_car.look_ahead = 500.0;
+        assert_eq!(astar_car.look_ahead_point(Vec2::ZERO), Some(Vec2::new(20.0, 40.0)));
+    }
+
+    #[test]
+    fn stuck_car_drops_its_path() {
+        let mut astar_car = following(&[(0, 1), (0, 2), (0, 3)]);
+        // Rocking back and forth on the spot, never reaching a waypoint
+        for frame in 0..ASTAR_STUCK_FRAMES {
+            assert!(!astar_car.path.is_empty(), "dropped after {} frames", frame);
+            astar_car.check_stuck(Vec2::new((frame % 2) as f32 * 5.0, 0.0));
+        }
+        assert!(astar_car.path.is_empty());
+        assert!(astar_car.force_replan);
+        assert_eq!(astar_car.stuck_frames, 0);
+    }
+
+    #[test]
+    fn moving_car_is_never_stuck() {
+        let mut astar_car = following(&[(0, 1), (0, 2), (0, 3)]);
+        for frame in 0..3 * ASTAR_STUCK_FRAMES {
+            astar_car.check_stuck(Vec2::new(0.0, frame as f32 * ASTAR_STUCK_RADIUS));
+        }
+        assert!(!astar_car.force_replan);
+        assert_eq!(astar_car.path.len(), 3);
+    }
+
+    // Rotated by heading_toward, a spawned car's local forward points at
+    // the goal whichever way the road runs
+    #[test]
+    fn spawn_heading_faces_goal() {
+        let start = Vec2::new(900.0, 300.0);
+        for goal in [
+            Vec2::new(900.0, 5000.0),
+            Vec2::new(900.0, -2000.0),
+            Vec2::new(3000.0, 300.0),
+            Vec2::new(-400.0, 1600.0),
+        ] {
+            let rotation = Quat::from_rotation_z(heading_toward(start, goal));
+            let forward = Transform::from_rotation(rotation).local_y().truncate();
+            let expected = (goal - start).normalize();
+            assert!(forward.distance(expected) < 1e-5, "{} faces {}", goal, forward);
+        }
+        assert_eq!(heading_toward(start, start), 0.0);
+    }
+
+    // The path doubles back right behind the car, reversing needs no turn
+    // at all where driving forward needs a half circle
+    #[test]
+    fn u_turn_reverses_instead_of_looping() {
+        let heading = Vec2::Y;
+        let behind = Vec2::new(0.0, -40.0);
+        let angle_diff = normalize_angle(behind.y.atan2(behind.x) - heading.y.atan2(heading.x));
+        let (turn, gear) = pick_gear(angle_diff, true);
+        assert_eq!(gear, -1.0);
+        assert!(turn.abs() < ASTAR_HEADING_EPSILON);
+        let (turn, gear) = pick_gear(angle_diff, false);
+        assert_eq!(gear, 1.0);
+        assert!((turn.abs() - std::f32::consts::PI).abs() < ASTAR_HEADING_EPSILON);
+    }
+
+    #[test]
+    fn targets_ahead_keep_driving_forward() {
+        for angle_diff in [0.0, 1.0, -1.5, std::f32::consts::FRAC_PI_2] {
+            assert_eq!(pick_gear(angle_diff, true), (angle_diff, 1.0));
+        }
+        // Just past square to the side, reversing turns the other way
+        let (turn, gear) = pick_gear(2.0, true);
+        assert_eq!(gear, -1.0);
+        assert!((turn - (2.0 - std::f32::consts::PI)).abs() < 1e-6);
+    }
+
+    // Integrates the turn over frames of uneven length, the way rapier
+    // applies angvel, until the heading settles
+    #[test]
+    fn turning_converges_without_overshoot() {
+        let frame_times = [1.0 / 30.0, 1.0 / 144.0, 1.0 / 60.0, 0.1];
+        for target in [2.5, -1.0, 0.3] {
+            let mut heading: f32 = 0.0;
+            let mut steps = 0;
+            loop {
+                let dt = frame_times[steps % frame_times.len()];
+                let angle_diff = normalize_angle(target - heading);
+                let angvel = turn_toward(angle_diff, dt, ASTAR_ROTATION_SPEED);
+                if angvel == 0.0 {
+                    break;
+                }
+                assert!(angvel.abs() <= ASTAR_ROTATION_SPEED);
+                let before = (target - heading).abs();
+                heading += angvel * dt;
+                assert!((target - heading).abs() < before);
+                // Never swings past the target
+                assert!((target - heading) * target >= -1e-4);
+                steps += 1;
+                assert!(steps < 200, "still turning toward {} after 200 frames", target);
+            }
+            assert!((target - heading).abs() < ASTAR_HEADING_EPSILON);
+        }
+    }
+
+    // The shortcut across the corner sees past the obstacle but passes
+    // closer to it than the car's half width
+    #[test]
+    fn smoothing_keeps_corner_the_car_cant_clear() {
+        let mut grid = Grid::new(10, 10, 20.0, Vec2::ZERO);
+        let path: Vec<_> = [(1, 1), (1, 5), (5, 5)].map(|cell| grid.grid_to_world(cell)).into();
+        assert_eq!(smooth_path(&grid, &path), vec![path[0], path[2]]);
+
+        grid.add_obstacle((2, 4));
+        assert!(has_line_of_sight(&grid, path[0], path[2]));
+        assert!(!grid.clear_for_radius(path[0], path[2], ASTAR_CAR_HALF_EXTENT));
+        assert_eq!(smooth_path(&grid, &path), path);
+    }
+
+    #[test]
+    fn simplify_collapses_l_shaped_path() {
+        let across = (0..=5).map(|x| at((x, 0)));
+        let path: Vec<_> = across.chain((1..=5).map(|y| at((5, y)))).collect();
+        assert_eq!(simplify_path(&path, 0.5), vec![at((0, 0)), at((5, 0)), at((5, 5))]);
+    }
+
+    #[test]
+    fn simplify_keeps_detours_above_epsilon() {
+        let path = [at((0, 0)), at((1, 0)), at((2, 1)), at((3, 0)), at((4, 0))];
+        assert_eq!(simplify_path(&path, 2.0), vec![at((0, 0)), at((4, 0))]);
+        assert_eq!(simplify_path(&path, 0.5), vec![at((0, 0)), at((2, 1)), at((4, 0))]);
+    }
+
+    #[test]
+    fn start_at_goal_is_one_waypoint() {
+        let grid = grid(5, 5, &[]);
+        let result = find_path_with_stats(&grid, at((2, 2)), at((2, 2)));
+        assert_eq!(result.path, Some(vec![at((2, 2))]));
+        assert_eq!(result.final_cost, 0);
+    }
+}
@@ -1,59 +1,241 @@
-use bevy::prelude::*;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+use bevy::{app::AppExit, prelude::*};
 use rand::distributions::WeightedIndex;
 use rand::prelude::Distribution;
+use rand::Rng;
 
-use crate::car::{Brain, Car, CarBundle, Fitness};
-use crate::enemy::{spawn_bound_trucks, spawn_enemies, BoundControlTruck, Enemy};
+use crate::car::{sensor_angles_deg, Brain, Car, CarBundle, CarOutcome, Fitness};
+use crate::enemy::{
+    spawn_bound_trucks, spawn_enemies, spawn_moving_trucks, BoundControlTruck, Enemy,
+    TruckSpawnConfig,
+};
+use crate::history::{SimStatsHistory, SimStatsSnapshot};
 use crate::nn::Net;
+use crate::save::{load_brain, load_population, save_brain, save_population};
+use crate::track::{spawn_area, Track};
 use crate::*;
 
 pub struct PopulationPlugin;
 
+// Loaded brain the next restarted population is grown from
+#[derive(Resource, Default)]
+struct SeedBrain(Option<Net>);
+
+// Every car's final fitness in the generation that just ended
+#[derive(Resource, Default)]
+struct GenerationFitness {
+    generation: u32,
+    cars: Vec<(u32, f32)>,
+}
+
+// Forward progress reached by a car, used for reward shaping
+#[derive(Component, Default)]
+pub(crate) struct Progress {
+    max_cell: i32,
+    is_finished: bool,
+}
+
 impl Plugin for PopulationPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
+        let is_nn = || in_mode(AlgorithmMode::NeuralNetwork);
         app.insert_resource(MaxDistanceTravelled(0.0))
-            .add_startup_system(setup)
-            .add_system(population_stats_system)
-            .add_system(generation_reset_system);
+            .insert_resource(SeedBrain::default())
+            .init_resource::<GenerationFitness>()
+            .add_system(setup.run_if(mode_entered(AlgorithmMode::NeuralNetwork)))
+            .add_system(brain_save_load_system.run_if(is_nn()))
+            // Runs after the window-close systems so it sees the exit event
+            .add_system(
+                autosave_system
+                    .in_base_set(CoreSet::Last)
+                    .run_if(is_nn()),
+            )
+            .add_system(population_stats_system.run_if(is_nn()))
+            .add_system(generation_end_system.run_if(is_sim_running).run_if(is_nn()))
+            .add_system(generation_reset_system.run_if(is_nn()))
+            .add_system(
+                car_fitness_export_system
+                    .after(generation_reset_system)
+                    .run_if(is_nn()),
+            );
     }
 }
 
-fn setup(mut commands: Commands, mut settings: ResMut<Settings>, asset_server: Res<AssetServer>) {
-    spawn_cars(&mut commands, &asset_server, &mut settings, None);
+fn setup(
+    mut commands: Commands,
+    mut settings: ResMut<Settings>,
+    mut sim_stats: ResMut<SimStats>,
+    mut sim_rng: ResMut<SimRng>,
+    generation_seed: Res<GenerationSeed>,
+    asset_server: Res<AssetServer>,
+    track: Option<Res<Track>>,
+) {
+    // Sensors are fixed from here on, so is the width of every brain's input
+    let num_sensors = sensor_angles_deg(&settings).len();
+    settings.network_arch.inputs = num_sensors;
+    let resumed = settings.resume_path.clone().and_then(|path| {
+        match load_population(&path, &settings.network_arch) {
+            Ok((brains, generation)) => {
+                info!(
+                    "Resuming generation {} with {} brains from {}",
+                    generation,
+                    brains.len(),
+                    path.display()
+                );
+                Some((brains, generation))
+            }
+            Err(e) => {
+                error!("Failed to load population {}: {}", path.display(), e);
+                None
+            }
+        }
+    });
+
+    let (brains, generation) = match resumed {
+        Some((brains, generation)) => {
+            let brains = brains.into_iter().map(|brain| brain.nn).collect();
+            (Some(fit_population(brains, &settings, &mut sim_rng.0)), generation)
+        }
+        None => (None, 0),
+    };
+    sim_stats.generation_count = generation;
+    sim_stats.seed = generation_seed.for_generation(generation);
+    let mut env_rng = generation_seed.rng(generation);
+    spawn_cars(
+        &mut commands,
+        &asset_server,
+        &mut settings,
+        &mut env_rng,
+        &mut sim_rng.0,
+        brains,
+        track.as_deref(),
+    );
+}
+
+// Resized to the current car count. Saved brains are best first so extra
+// ones are dropped from the end, missing ones are mutated copies of the rest
+fn fit_population(mut brains: Vec<Net>, settings: &Settings, rng: &mut impl Rng) -> Vec<Net> {
+    let num_cars = settings.num_cars as usize;
+    if brains.len() != num_cars {
+        warn!(
+            "Population has {} brains but {} cars are running, resizing it",
+            brains.len(),
+            num_cars
+        );
+    }
+
+    let num_saved = brains.len();
+    brains.truncate(num_cars);
+    while brains.len() < num_cars {
+        let mut brain = brains[brains.len() % num_saved].clone();
+        brain.mutate(rng, settings.mutation_rate.clamp(0.0, 1.0), settings.mutation_strength);
+        brains.push(brain);
+    }
+    brains
 }
 
 fn population_stats_system(
     mut sim_stats: ResMut<SimStats>,
     mut max_distance_travelled: ResMut<MaxDistanceTravelled>,
     mut brain_on_display: ResMut<BrainToDisplay>,
-    mut query: Query<(&Transform, &Brain, &mut Fitness), With<Car>>,
+    mut brain_weights: ResMut<BrainWeightsToDisplay>,
+    settings: Res<Settings>,
+    goal_query: Query<(&Transform, &Goal)>,
+    mut query: Query<(&Transform, &Brain, &mut Fitness, &mut Progress), With<Car>>,
 ) {
     let mut max_fitness = 0.0;
     sim_stats.num_cars_alive = query.iter().len();
+    let Ok((goal_transform, goal)) = goal_query.get_single() else {
+        return;
+    };
+    let goal_pos = goal_transform.translation.truncate();
 
-    for (transform, brain, mut fitness) in query.iter_mut() {
-        fitness.0 = calc_fitness(transform);
+    for (transform, brain, mut fitness, mut progress) in query.iter_mut() {
+        fitness.0 = match settings.is_reward_shaping {
+            true => calc_shaped_fitness(transform, goal_pos, goal, &mut progress, &settings),
+            false => calc_fitness(transform, goal_pos),
+        };
         if fitness.0 > max_fitness {
             max_fitness = fitness.0;
             brain_on_display.0 = brain.nn_outputs.clone();
-            sim_stats.max_current_score = fitness.0;
+            brain_weights.0 = brain.nn.weights();
+            sim_stats.max_current_score = calc_fitness(transform, goal_pos);
             max_distance_travelled.0 = transform.translation.y;
         }
     }
 }
 
+// Cuts the generation short by Settings::generation_end, and stops the run
+// at the target generation or once the best fitness has plateaued
+fn generation_end_system(
+    time: Res<Time>,
+    mut settings: ResMut<Settings>,
+    sim_stats: Res<SimStats>,
+    mut exit_events: EventWriter<AppExit>,
+    // Generation being timed and the simulated seconds it has run
+    mut timed: Local<Option<(u32, f32)>>,
+    cars_query: Query<With<Car>>,
+) {
+    let (generation, elapsed) = timed.get_or_insert((sim_stats.generation_count, 0.0));
+    if *generation != sim_stats.generation_count {
+        *generation = sim_stats.generation_count;
+        *elapsed = 0.0;
+        if let Some(reason) = stop_reason(&settings, &sim_stats) {
+            info!("Stopping at generation {}: {}", sim_stats.generation_count, reason);
+            exit_events.send(AppExit);
+            return;
+        }
+    }
+    *elapsed += time.delta_seconds();
+
+    if let GenerationEnd::TimeLimit { secs } = settings.generation_end {
+        // Cars still out there are scored where they stand
+        if *elapsed >= secs && !cars_query.is_empty() && !settings.start_next_generation {
+            info!("Generation {} hit its {}s time limit", sim_stats.generation_count, secs);
+            settings.start_next_generation = true;
+        }
+    }
+}
+
+fn stop_reason(settings: &Settings, sim_stats: &SimStats) -> Option<String> {
+    if settings.max_generations > 0 && sim_stats.generation_count >= settings.max_generations {
+        return Some("reached the target generation".to_string());
+    }
+
+    // Best of the last K generations against the best of everything before
+    let plateau = settings.plateau_generations as usize;
+    if plateau == 0 || sim_stats.fitness.len() <= plateau {
+        return None;
+    }
+    let (before, recent) = sim_stats.fitness.split_at(sim_stats.fitness.len() - plateau);
+    let best_before = before.iter().copied().fold(f32::MIN, f32::max);
+    let best_recent = recent.iter().copied().fold(f32::MIN, f32::max);
+    (best_recent <= best_before)
+        .then(|| format!("no improvement in {} generations", plateau))
+}
+
 fn generation_reset_system(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut settings: ResMut<Settings>,
     mut sim_stats: ResMut<SimStats>,
-    cars_query: Query<(Entity, &Brain, &Fitness)>,
+    mut seed_brain: ResMut<SeedBrain>,
+    mut sim_rng: ResMut<SimRng>,
+    mut generation_fitness: ResMut<GenerationFitness>,
+    mut history: ResMut<SimStatsHistory>,
+    generation_seed: Res<GenerationSeed>,
+    truck_config: Res<TruckSpawnConfig>,
+    track: Option<Res<Track>>,
+    cars_query: Query<(Entity, &Brain, &Fitness, Option<&CarOutcome>)>,
     cars_count_query: Query<With<Car>>,
     enemy_query: Query<Entity, With<Enemy>>,
     bounds_truck_query: Query<Entity, With<BoundControlTruck>>,
 ) {
     let num_cars = cars_count_query.iter().count();
-    if num_cars > 0 {
+    // Nothing to breed from until setup's first population shows up
+    if num_cars > 0 || cars_query.is_empty() {
         return;
     }
 
@@ -62,83 +244,512 @@ fn generation_reset_system(
 
     let mut fitnesses = Vec::new();
     let mut old_brains = Vec::new();
-    for (e, brain, fitness) in cars_query.iter() {
+    let (mut num_reached, mut num_survived) = (0, 0);
+    generation_fitness.generation = sim_stats.generation_count;
+    generation_fitness.cars.clear();
+    for (e, brain, fitness, outcome) in cars_query.iter() {
+        if outcome == Some(&CarOutcome::Reached) {
+            num_reached += 1;
+        }
+        if outcome != Some(&CarOutcome::Crashed) {
+            num_survived += 1;
+        }
+        generation_fitness.cars.push((e.index(), fitness.0));
         fitnesses.push(fitness.0);
         old_brains.push(brain.nn.clone());
 
         commands.entity(e).despawn();
     }
 
-    let (max_fitness, gene_pool) = create_gene_pool(fitnesses);
-    let mut rng = rand::thread_rng();
-    let mut new_brains = Vec::new();
-
-    for _ in 0..NUM_AI_CARS {
-        let brain_idx = gene_pool.sample(&mut rng);
-        let mut rand_brain = old_brains[brain_idx].clone();
-        rand_brain.mutate();
-        new_brains.push(rand_brain);
-    }
+    let rng = &mut sim_rng.0;
+    let (max_fitness, new_brains) = match seed_brain.0.take() {
+        Some(brain) => {
+            // Generation 0 from a saved brain, keep one exact copy of it
+            settings.restart_sim = false;
+            let mutation_rate = settings.mutation_rate.clamp(0.0, 1.0);
+            let mut new_brains = vec![brain.clone()];
+            for _ in 1..settings.num_cars {
+                let mut rand_brain = brain.clone();
+                rand_brain.mutate(rng, mutation_rate, settings.mutation_strength);
+                new_brains.push(rand_brain);
+            }
+            (fitnesses.iter().copied().fold(0.0, f32::max), new_brains)
+        }
+        None => breed(&old_brains, &fitnesses, &settings, rng),
+    };
 
-    // update stats
-    sim_stats.generation_count += 1;
+    // update stats, every car is gone by now so the ended generation's
+    // survivors are the ones that didn't crash
     sim_stats.fitness.push(max_fitness);
+    sim_stats.population_diversity = population_diversity(&old_brains);
+    sim_stats.diversity.push(sim_stats.population_diversity);
+    sim_stats.success_rate = num_reached as f32 / old_brains.len().max(1) as f32;
+    history.record(SimStatsSnapshot::ended(
+        &sim_stats,
+        &generation_seed,
+        num_survived,
+        num_reached,
+    ));
+    sim_stats.generation_count += 1;
+    sim_stats.seed = generation_seed.for_generation(sim_stats.generation_count);
+    info!(
+        "Generation {} seed {}",
+        sim_stats.generation_count, sim_stats.seed
+    );
 
-    // respawn everything
-    spawn_enemies(&mut commands, &asset_server);
+    // respawn everything, environment randomness comes from the generation seed
+    let mut env_rng = generation_seed.rng(sim_stats.generation_count);
+    spawn_enemies(&mut commands, &asset_server, &mut env_rng);
+    spawn_moving_trucks(&mut commands, &asset_server, &truck_config);
     spawn_bound_trucks(&mut commands, &asset_server);
     spawn_cars(
         &mut commands,
         &asset_server,
         &mut settings,
+        &mut env_rng,
+        &mut sim_rng.0,
         Some(new_brains),
+        track.as_deref(),
     );
 }
 
+// The first write of a run starts the file over, later generations append
+fn car_fitness_export_system(
+    settings: Res<Settings>,
+    generation_fitness: Res<GenerationFitness>,
+    mut is_started: Local<bool>,
+) {
+    if !settings.is_export_car_fitness
+        || !generation_fitness.is_changed()
+        || generation_fitness.cars.is_empty()
+    {
+        return;
+    }
+
+    let path = Path::new(CAR_FITNESS_CSV_PATH);
+    match write_car_fitness(path, &generation_fitness, !*is_started) {
+        Ok(_) => *is_started = true,
+        Err(e) => error!("Failed to write {}: {}", CAR_FITNESS_CSV_PATH, e),
+    }
+}
+
+fn write_car_fitness(path: &Path, fitness: &GenerationFitness, is_new_file: bool) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(!is_new_file)
+        .truncate(is_new_file)
+        .open(path)?;
+    if is_new_file {
+        writeln!(file, "generation,car_id,fitness")?;
+    }
+    for (car_id, car_fitness) in fitness.cars.iter() {
+        writeln!(file, "{},{},{}", fitness.generation, car_id, car_fitness)?;
+    }
+    Ok(())
+}
+
+fn brain_save_load_system(
+    mut settings: ResMut<Settings>,
+    mut seed_brain: ResMut<SeedBrain>,
+    sim_stats: Res<SimStats>,
+    cars_query: Query<(&Brain, &Fitness)>,
+) {
+    if settings.save_population {
+        settings.save_population = false;
+        let mut ranked: Vec<(&Brain, &Fitness)> = cars_query.iter().collect();
+        ranked.sort_by(|(_, a), (_, b)| b.0.total_cmp(&a.0));
+        let brains: Vec<&Brain> = ranked.iter().map(|(brain, _)| *brain).collect();
+        let path = Path::new(POPULATION_SAVE_PATH);
+        match save_population(path, &brains, sim_stats.generation_count) {
+            Ok(_) => info!(
+                "Saved {} brains of generation {} to {}",
+                brains.len(),
+                sim_stats.generation_count,
+                POPULATION_SAVE_PATH
+            ),
+            Err(e) => error!("Failed to save population: {}", e),
+        }
+    }
+
+    if settings.save_best_brain {
+        settings.save_best_brain = false;
+        let best = cars_query
+            .iter()
+            .max_by(|(_, a), (_, b)| a.0.total_cmp(&b.0))
+            .map(|(brain, _)| brain);
+        if let Some(brain) = best {
+            match save_brain(Path::new(BRAIN_SAVE_PATH), brain) {
+                Ok(_) => info!("Saved best brain to {}", BRAIN_SAVE_PATH),
+                Err(e) => error!("Failed to save brain: {}", e),
+            }
+        }
+    }
+
+    if settings.load_saved_brain {
+        settings.load_saved_brain = false;
+        match load_brain(Path::new(BRAIN_SAVE_PATH), &settings.network_arch) {
+            Ok(brain) => {
+                info!("Loaded brain from {}", BRAIN_SAVE_PATH);
+                seed_brain.0 = Some(brain.nn);
+                settings.restart_sim = true;
+            }
+            Err(e) => error!("Failed to load brain: {}", e),
+        }
+    }
+}
+
+// Keeps the training when the window is closed or Esc is hit, the best brain
+// is saved as a one brain population so --resume picks it back up at the
+// same generation
+fn autosave_system(
+    mut exit_events: EventReader<AppExit>,
+    settings: Res<Settings>,
+    sim_stats: Res<SimStats>,
+    cars_query: Query<(&Brain, &Fitness)>,
+) {
+    if exit_events.iter().next().is_none() || !settings.autosave_on_exit {
+        return;
+    }
+
+    let Some((best, _)) = cars_query.iter().max_by(|(_, a), (_, b)| a.0.total_cmp(&b.0)) else {
+        return;
+    };
+    match save_population(Path::new(AUTOSAVE_PATH), &[best], sim_stats.generation_count) {
+        Ok(_) => info!(
+            "Saved best brain of generation {} to {}",
+            sim_stats.generation_count, AUTOSAVE_PATH
+        ),
+        Err(e) => error!("Failed to autosave best brain: {}", e),
+    }
+}
+
 fn spawn_cars(
     commands: &mut Commands,
     asset_server: &AssetServer,
     settings: &mut Settings,
+    rng: &mut impl Rng,
+    sim_rng: &mut impl Rng,
     brains: Option<Vec<Net>>,
+    track: Option<&Track>,
 ) {
     let brains = brains.unwrap_or(Vec::new());
     let is_new_nn = brains.is_empty() || settings.restart_sim;
     settings.restart_sim = false;
 
     // Spawn all neural network cars (full population)
-    for i in 0..NUM_AI_CARS {
-        match is_new_nn {
-            true => commands.spawn(CarBundle::new(asset_server)),
-            false => commands.spawn(CarBundle::with_brain(
-                asset_server,
-                &brains.get(i as usize).unwrap(),
-            )),
+    let spawns = settings.spawn_formation.positions(settings.num_cars, spawn_area(track), rng);
+    for (i, spawn) in spawns.into_iter().enumerate() {
+        let brain = match is_new_nn {
+            true => Net::from_arch(&settings.network_arch, sim_rng),
+            false => brains.get(i).unwrap().clone(),
         };
+        let car = CarBundle::with_brain(asset_server, rng, &brain).at(spawn);
+        commands.spawn(car).insert(Progress::default());
     }
 }
 
-fn create_gene_pool(values: Vec<f32>) -> (f32, WeightedIndex<f32>) {
-    let mut max_fitness = 0.0;
-    let mut weights = Vec::new();
+// Next generation's brains from the last one's fitness, elites carried over
+// unchanged and the rest selected, mutated and regularized. Also returns the
+// best fitness
+pub(crate) fn breed(
+    old_brains: &[Net],
+    fitnesses: &[f32],
+    settings: &Settings,
+    rng: &mut impl Rng,
+) -> (f32, Vec<Net>) {
+    // Best first, the displayed brain is always ranked at the top
+    let mut ranked: Vec<usize> = (0..fitnesses.len()).collect();
+    ranked.sort_by(|&a, &b| fitnesses[b].total_cmp(&fitnesses[a]));
+
+    let max_fitness = fitnesses.iter().copied().fold(0.0, f32::max);
+    // The roulette wheel is only built when it's spun
+    let gene_pool = match settings.selection {
+        SelectionStrategy::RouletteWheel => create_gene_pool(fitnesses),
+        SelectionStrategy::Tournament { .. } => None,
+    };
+    let mutation_rate = settings.mutation_rate.clamp(0.0, 1.0);
+    let mut new_brains = Vec::new();
+
+    let num_elites = settings.elite_count.min(settings.num_cars as usize);
+    for &brain_idx in ranked.iter().take(num_elites) {
+        new_brains.push(old_brains[brain_idx].clone());
+    }
+    while new_brains.len() < settings.num_cars as usize {
+        let parent_a = select_parent(fitnesses, settings.selection, gene_pool.as_ref(), rng);
+        let parent_b = select_parent(fitnesses, settings.selection, gene_pool.as_ref(), rng);
+        let mut rand_brain =
+            old_brains[parent_a].crossover(&old_brains[parent_b], settings.crossover, rng);
+        rand_brain.mutate(rng, mutation_rate, settings.mutation_strength);
+        regularize_brain(&mut rand_brain, settings);
+        new_brains.push(rand_brain);
+    }
+
+    (max_fitness, new_brains)
+}
+
+fn regularize_brain(brain: &mut Net, settings: &Settings) {
+    // Zero disables either step
+    if settings.weight_decay > 0.0 {
+        brain.decay_weights(settings.weight_decay as f64);
+    }
+    if settings.weight_clamp > 0.0 {
+        brain.clamp_weights(settings.weight_clamp as f64);
+    }
+}
 
-    for v in values.iter() {
-        if *v > max_fitness {
-            max_fitness = *v;
+// Mean pairwise L2 distance between the brains' weights. Past
+// NN_DIVERSITY_EXACT_MAX_CARS it's approximated by the mean distance to the
+// centroid, which is O(n) and tracks the same collapse
+fn population_diversity(brains: &[Net]) -> f32 {
+    if brains.len() < 2 {
+        return 0.0;
+    }
+    let weights: Vec<Vec<f64>> = brains.iter().map(|b| b.flat_weights()).collect();
+    let distance = |a: &[f64], b: &[f64]| -> f64 {
+        a.iter().zip(b.iter()).map(|(x, y)| (x - y).powi(2)).sum::<f64>().sqrt()
+    };
+
+    if weights.len() > NN_DIVERSITY_EXACT_MAX_CARS {
+        let mut centroid = vec![0.0; weights[0].len()];
+        for w in weights.iter() {
+            centroid.iter_mut().zip(w.iter()).for_each(|(c, x)| *c += x);
+        }
+        centroid.iter_mut().for_each(|c| *c /= weights.len() as f64);
+        let total: f64 = weights.iter().map(|w| distance(w, &centroid)).sum();
+        return (total / weights.len() as f64) as f32;
+    }
+
+    let mut total = 0.0;
+    let mut num_pairs = 0;
+    for (i, a) in weights.iter().enumerate() {
+        for b in weights[i + 1..].iter() {
+            total += distance(a, b);
+            num_pairs += 1;
         }
-        weights.push(*v);
     }
+    (total / num_pairs as f64) as f32
+}
+
+// Index of the fittest of `size` brains sampled with replacement
+fn tournament_select(fitnesses: &[f32], size: usize, rng: &mut impl Rng) -> usize {
+    (0..size.max(1))
+        .map(|_| rng.gen_range(0..fitnesses.len()))
+        .max_by(|&a, &b| fitnesses[a].total_cmp(&fitnesses[b]))
+        .unwrap()
+}
+
+// Index of one parent, from a spin of the roulette wheel or a tournament
+fn select_parent(
+    fitnesses: &[f32],
+    selection: SelectionStrategy,
+    gene_pool: Option<&WeightedIndex<f32>>,
+    rng: &mut impl Rng,
+) -> usize {
+    match (selection, gene_pool) {
+        (SelectionStrategy::Tournament { size }, _) => tournament_select(fitnesses, size, rng),
+        (SelectionStrategy::RouletteWheel, Some(gene_pool)) => gene_pool.sample(rng),
+        // Nothing scored, every car is as good a parent as any other
+        (SelectionStrategy::RouletteWheel, None) => rng.gen_range(0..fitnesses.len()),
+    }
+}
 
-    (
-        max_fitness,
-        WeightedIndex::new(&weights).expect("Failed to generate gene pool"),
-    )
+// Weighted by fitness, None when no car scored anything
+fn create_gene_pool(fitnesses: &[f32]) -> Option<WeightedIndex<f32>> {
+    WeightedIndex::new(fitnesses).ok()
 }
 
-fn calc_fitness(transform: &Transform) -> f32 {
+// Tops out at GOAL_SCORE level with the goal
+pub(crate) fn calc_fitness(transform: &Transform, goal_pos: Vec2) -> f32 {
     let y = transform.translation.y;
     if y <= 600.0 {
         return 0.1;
     }
 
-    return transform.translation.y / 340.0;
-}
\ No newline at end of file
+    return Goal::score(goal_pos, transform.translation.truncate());
+}
+
+pub(crate) fn calc_shaped_fitness(
+    transform: &Transform,
+    goal_pos: Vec2,
+    goal: &Goal,
+    progress: &mut Progress,
+    settings: &Settings,
+) -> f32 {
+    let y = transform.translation.y;
+
+    // Reward every new cell of forward progress, never taking it back
+    let cell = ((y - 600.0) / PROGRESS_CELL_SIZE).floor() as i32;
+    progress.max_cell = progress.max_cell.max(cell);
+    progress.is_finished |= goal.is_reached(goal_pos, transform.translation.truncate());
+
+    let mut fitness = calc_fitness(transform, goal_pos);
+    fitness += progress.max_cell as f32 * settings.progress_cell_reward;
+    if progress.is_finished {
+        fitness += settings.finish_bonus;
+    }
+
+    fitness
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn bred_children(selection: SelectionStrategy) -> (Vec<Net>, Vec<Net>) {
+        let mut rng = StdRng::seed_from_u64(294);
+        let settings = Settings {
+            num_cars: 20,
+            elite_count: 0,
+            mutation_rate: 0.0,
+            selection,
+            crossover: CrossoverStrategy::Uniform,
+            ..default()
+        };
+        let parents: Vec<_> = (0..2).map(|_| Net::new(vec![10, 10, 3], &mut rng)).collect();
+        let (_, children) = breed(&parents, &[1.0, 1.0], &settings, &mut rng);
+        (parents, children)
+    }
+
+    // Children holding weights from both parents, about half of them when
+    // the two are equally fit
+    fn mixed_children(parents: &[Net], children: &[Net]) -> usize {
+        let (a, b) = (parents[0].flat_weights(), parents[1].flat_weights());
+        children
+            .iter()
+            .filter(|child| {
+                let weights = child.flat_weights();
+                let from_a = weights.iter().zip(a.iter()).filter(|(w, x)| w == x).count();
+                let from_b = weights.iter().zip(b.iter()).filter(|(w, x)| w == x).count();
+                from_a > 0 && from_b > 0 && from_a + from_b == weights.len()
+            })
+            .count()
+    }
+
+    #[test]
+    fn roulette_children_cross_over() {
+        let (parents, children) = bred_children(SelectionStrategy::RouletteWheel);
+        assert_eq!(children.len(), 20);
+        assert!(mixed_children(&parents, &children) > 0);
+    }
+
+    #[test]
+    fn tournament_children_cross_over() {
+        let (parents, children) = bred_children(SelectionStrategy::Tournament { size: 2 });
+        assert!(mixed_children(&parents, &children) > 0);
+    }
+
+    fn max_abs_weight(brains: &[Net]) -> f64 {
+        brains
+            .iter()
+            .flat_map(|brain| brain.flat_weights())
+            .fold(0.0, |max, w| max.max(w.abs()))
+    }
+
+    #[test]
+    fn weight_clamp_caps_children() {
+        let mut rng = StdRng::seed_from_u64(204);
+        let settings = Settings {
+            num_cars: 10,
+            mutation_strength: 10.0,
+            weight_clamp: 0.2,
+            ..default()
+        };
+        let parents: Vec<_> = (0..10).map(|_| Net::new(vec![5, 8, 3], &mut rng)).collect();
+        assert!(max_abs_weight(&parents) > 0.2);
+        let (_, children) = breed(&parents, &[1.0; 10], &settings, &mut rng);
+        // Elites are carried over untouched
+        assert!(max_abs_weight(&children[settings.elite_count..]) <= 0.2);
+    }
+
+    // Without mutation or fitness differences, decay is all that changes
+    // the weights and every generation shrinks them
+    #[test]
+    fn weight_decay_shrinks_toward_zero() {
+        let mut rng = StdRng::seed_from_u64(204);
+        let settings = Settings {
+            num_cars: 10,
+            elite_count: 0,
+            mutation_rate: 0.0,
+            weight_decay: 0.1,
+            ..default()
+        };
+        let mut brains: Vec<_> = (0..10).map(|_| Net::new(vec![5, 8, 3], &mut rng)).collect();
+        for _ in 0..10 {
+            let before = max_abs_weight(&brains);
+            brains = breed(&brains, &[1.0; 10], &settings, &mut rng).1;
+            assert!(max_abs_weight(&brains) <= before * 0.9 + f64::EPSILON);
+        }
+    }
+
+    fn shaped_fitness_at(ys: &[f32], settings: &Settings) -> f32 {
+        let goal = Goal {
+            is_finish_line: true,
+            radius: 0.0,
+        };
+        let goal_pos = Vec2::new(0.0, 3000.0);
+        let mut progress = Progress::default();
+        ys.iter()
+            .map(|&y| {
+                let transform = Transform::from_xyz(0.0, y, 0.0);
+                calc_shaped_fitness(&transform, goal_pos, &goal, &mut progress, settings)
+            })
+            .last()
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn shaped_fitness_grows_with_progress() {
+        let settings = Settings {
+            is_reward_shaping: true,
+            ..default()
+        };
+        let fitnesses: Vec<_> = (0..=32)
+            .map(|step| shaped_fitness_at(&[step as f32 * 100.0], &settings))
+            .collect();
+        assert!(fitnesses.windows(2).all(|pair| pair[0] <= pair[1]));
+        // Past the goal the finish bonus is added on top
+        let finished = shaped_fitness_at(&[3000.0], &settings);
+        assert!(finished >= shaped_fitness_at(&[2999.0], &settings) + settings.finish_bonus);
+    }
+
+    // Progress already reached counts even after the car falls back
+    #[test]
+    fn shaped_fitness_keeps_furthest_cell() {
+        let settings = Settings {
+            is_reward_shaping: true,
+            ..default()
+        };
+        let fell_back = shaped_fitness_at(&[2000.0, 1000.0], &settings);
+        let stayed = shaped_fitness_at(&[1000.0], &settings);
+        assert!(fell_back > stayed);
+    }
+
+    // Fitness rising with the index, so every brain should win more
+    // tournaments than the one before it
+    #[test]
+    fn tournaments_favour_fitter_brains() {
+        let mut rng = StdRng::seed_from_u64(281);
+        let fitnesses: Vec<f32> = (1..=10).map(|f| f as f32).collect();
+        let mut wins = [0; 10];
+        for _ in 0..10_000 {
+            wins[tournament_select(&fitnesses, 3, &mut rng)] += 1;
+        }
+        assert!(wins.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", wins);
+    }
+
+    #[test]
+    fn breeds_without_any_fitness() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let settings = Settings {
+            num_cars: 4,
+            ..default()
+        };
+        let parents: Vec<_> = (0..4).map(|_| Net::new(vec![3, 4, 3], &mut rng)).collect();
+        let (max_fitness, children) = breed(&parents, &[0.0; 4], &settings, &mut rng);
+        assert_eq!(max_fitness, 0.0);
+        assert_eq!(children.len(), 4);
+    }
+}
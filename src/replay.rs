@@ -0,0 +1,327 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use bevy::{app::AppExit, math::vec3, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::car::Car;
+use crate::*;
+
+pub struct RecordPlugin;
+
+pub struct ReplayPlugin;
+
+// One car's transform on one recorded frame
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TransformSample {
+    pub frame: u32,
+    pub entity: u32,
+    pub position: (f32, f32),
+    pub rotation: f32,
+}
+
+// Everything needed to play a run back, plus the seeds that produced it
+#[derive(Resource, Default, Serialize, Deserialize)]
+pub struct Recording {
+    pub rng_seed: Option<u64>,
+    pub base_seed: u64,
+    pub num_cars: u32,
+    pub samples: Vec<TransformSample>,
+}
+
+// Playback state, the recording's samples grouped by frame. Frames without
+// any cars have no entry
+#[derive(Resource)]
+pub struct Replay {
+    pub frames: BTreeMap<u32, Vec<TransformSample>>,
+    pub frame: u32,
+    entities: HashMap<u32, Entity>,
+}
+
+#[derive(Component)]
+pub struct PlaybackCar;
+
+// Every live car's path this generation, and the best one from the last
+// generation that the ghost replays frame for frame
+#[derive(Resource, Default)]
+pub struct GhostTrack {
+    paths: HashMap<Entity, Vec<(Vec2, f32)>>,
+    best: Vec<(Vec2, f32)>,
+    frame: usize,
+    generation: u32,
+}
+
+#[derive(Component)]
+struct Ghost;
+
+impl Plugin for RecordPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Recording::default())
+            .insert_resource(GhostTrack::default())
+            .add_startup_system(spawn_ghost)
+            .add_system(record_system.run_if(is_sim_running))
+            .add_system(ghost_record_system.run_if(is_sim_running))
+            .add_system(ghost_system.after(ghost_record_system))
+            // Runs after the window-close systems so it sees the exit event
+            .add_system(recording_save_system.in_base_set(CoreSet::Last));
+    }
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(replay_setup_system)
+            .add_system(replay_system.run_if(is_sim_running));
+    }
+}
+
+impl Recording {
+    // Number of distinct cars seen over the whole run
+    pub fn car_count(&self) -> usize {
+        self.samples.iter().map(|s| s.entity).collect::<HashSet<_>>().len()
+    }
+}
+
+impl Replay {
+    pub fn new(recording: &Recording) -> Self {
+        let mut frames: BTreeMap<u32, Vec<TransformSample>> = BTreeMap::new();
+        for sample in recording.samples.iter() {
+            frames.entry(sample.frame).or_default().push(sample.clone());
+        }
+
+        Self {
+            frames,
+            frame: 0,
+            entities: HashMap::new(),
+        }
+    }
+}
+
+pub fn save_recording(path: &Path, recording: &Recording) -> io::Result<()> {
+    let json =
+        serde_json::to_string(recording).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+    fs::write(path, json)
+}
+
+// Samples are written frame by frame, a file out of that order was edited
+// or corrupted
+pub fn load_recording(path: &Path) -> io::Result<Recording> {
+    let json = fs::read_to_string(path)?;
+    let recording: Recording =
+        serde_json::from_str(&json).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+    let is_ordered = recording.samples.windows(2).all(|pair| pair[0].frame <= pair[1].frame);
+    if !is_ordered {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "recording samples are out of frame order",
+        ));
+    }
+    Ok(recording)
+}
+
+fn record_system(
+    settings: Res<Settings>,
+    generation_seed: Res<GenerationSeed>,
+    mut recording: ResMut<Recording>,
+    mut frame: Local<u32>,
+    car_query: Query<(Entity, &Transform), With<Car>>,
+) {
+    if !settings.recording {
+        *frame = 0;
+        return;
+    }
+
+    // Fresh recording every time it's switched on
+    if *frame == 0 {
+        *recording = Recording {
+            rng_seed: settings.rng_seed,
+            base_seed: generation_seed.base_seed,
+            num_cars: settings.num_cars,
+            samples: Vec::new(),
+        };
+    }
+    for (entity, transform) in car_query.iter() {
+        let (_, _, rotation) = transform.rotation.to_euler(EulerRot::XYZ);
+        recording.samples.push(TransformSample {
+            frame: *frame,
+            entity: entity.index(),
+            position: (transform.translation.x, transform.translation.y),
+            rotation,
+        });
+    }
+    *frame += 1;
+}
+
+fn spawn_ghost(mut commands: Commands, asset_server: Res<AssetServer>) {
+    // No collider, it only shows where the last champion was
+    commands.spawn((
+        SpriteBundle {
+            transform: Transform::from_xyz(0.0, 0.0, 1.0).with_scale(vec3(2.5, 2.5, 1.0)),
+            texture: asset_server.load("agent.png"),
+            sprite: Sprite {
+                color: Color::rgba(1.0, 1.0, 1.0, 0.35),
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        Ghost,
+    ));
+}
+
+// Logs the live cars' paths, when a new generation starts the one that got
+// closest to the goal becomes the ghost
+fn ghost_record_system(
+    sim_stats: Res<SimStats>,
+    mut ghost_track: ResMut<GhostTrack>,
+    goal_query: Query<&Transform, With<Goal>>,
+    car_query: Query<(Entity, &Transform), With<Car>>,
+) {
+    if sim_stats.generation_count != ghost_track.generation {
+        ghost_track.generation = sim_stats.generation_count;
+        let goal_pos = goal_query
+            .get_single()
+            .map_or(Vec2::ZERO, |goal| goal.translation.truncate());
+        let best = ghost_track
+            .paths
+            .drain()
+            .filter_map(|(_, path)| {
+                let (end, _) = *path.last()?;
+                Some((Goal::score(goal_pos, end), path))
+            })
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, path)| path);
+        // A generation nobody drove in keeps the old ghost
+        if let Some(best) = best {
+            ghost_track.best = best;
+        }
+        ghost_track.frame = 0;
+    }
+
+    for (entity, transform) in car_query.iter() {
+        let (_, _, rotation) = transform.rotation.to_euler(EulerRot::XYZ);
+        let position = transform.translation.truncate();
+        ghost_track.paths.entry(entity).or_default().push((position, rotation));
+    }
+    ghost_track.frame += 1;
+}
+
+// Puts the ghost where the last best car was this many frames into its run,
+// hidden once that car had crashed or finished
+fn ghost_system(
+    settings: Res<Settings>,
+    ghost_track: Res<GhostTrack>,
+    mut ghost_query: Query<(&mut Transform, &mut Visibility), With<Ghost>>,
+) {
+    let Ok((mut transform, mut visibility)) = ghost_query.get_single_mut() else {
+        return;
+    };
+    let sample = ghost_track.best.get(ghost_track.frame.saturating_sub(1));
+    match sample.filter(|_| settings.show_ghost) {
+        Some(&(position, rotation)) => {
+            transform.translation = position.extend(transform.translation.z);
+            transform.rotation = Quat::from_rotation_z(rotation);
+            *visibility = Visibility::Visible;
+        }
+        None => *visibility = Visibility::Hidden,
+    }
+}
+
+// Writes the recording when it's switched off or the app closes
+fn recording_save_system(
+    mut exit_events: EventReader<AppExit>,
+    settings: Res<Settings>,
+    recording: Res<Recording>,
+    mut was_recording: Local<bool>,
+) {
+    let is_stopped = *was_recording && !settings.recording;
+    let is_exiting = exit_events.iter().next().is_some() && settings.recording;
+    *was_recording = settings.recording;
+    if !is_stopped && !is_exiting {
+        return;
+    }
+
+    match save_recording(Path::new(RECORDING_PATH), &recording) {
+        Ok(_) => info!(
+            "Saved {} recorded samples to {}",
+            recording.samples.len(),
+            RECORDING_PATH
+        ),
+        Err(e) => error!("Failed to write recording: {}", e),
+    }
+}
+
+fn replay_setup_system(recording: Res<Recording>, settings: Res<Settings>) {
+    // Playback cars come from the file, so a different car count just
+    // means more or fewer of them than a live run would spawn
+    let car_count = recording.car_count();
+    if recording.num_cars != settings.num_cars {
+        warn!(
+            "Recording was made with {} cars, replaying it with {} instead of {}",
+            recording.num_cars, car_count, settings.num_cars
+        );
+    }
+    info!(
+        "Replaying {} cars, seed {:?}, base seed {}",
+        car_count, recording.rng_seed, recording.base_seed
+    );
+}
+
+fn replay_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut replay: ResMut<Replay>,
+    mut max_distance_travelled: ResMut<MaxDistanceTravelled>,
+    mut car_query: Query<(&mut Transform, &mut Visibility), With<PlaybackCar>>,
+) {
+    let Some(&last_frame) = replay.frames.keys().next_back() else {
+        return;
+    };
+    if replay.frame > last_frame {
+        return;
+    }
+    let samples = replay.frames.get(&replay.frame).cloned().unwrap_or_default();
+    replay.frame += 1;
+    if replay.frame > last_frame {
+        info!("Replay finished after {} frames", replay.frame);
+    }
+
+    // Cars that died or weren't spawned yet stay hidden this frame
+    car_query
+        .iter_mut()
+        .for_each(|(_, mut visibility)| *visibility = Visibility::Hidden);
+    for sample in samples.iter() {
+        let translation = vec3(sample.position.0, sample.position.1, 0.0);
+        let rotation = Quat::from_rotation_z(sample.rotation);
+        max_distance_travelled.0 = max_distance_travelled.0.max(translation.y);
+
+        let entity = replay.entities.get(&sample.entity).copied();
+        match entity.and_then(|e| car_query.get_mut(e).ok()) {
+            Some((mut transform, mut visibility)) => {
+                transform.translation = translation;
+                transform.rotation = rotation;
+                *visibility = Visibility::Visible;
+            }
+            None => {
+                let entity = commands
+                    .spawn((
+                        SpriteBundle {
+                            transform: Transform::from_translation(translation)
+                                .with_rotation(rotation)
+                                .with_scale(vec3(2.5, 2.5, 1.0)),
+                            texture: asset_server.load("agent.png"),
+                            ..default()
+                        },
+                        PlaybackCar,
+                    ))
+                    .id();
+                replay.entities.insert(sample.entity, entity);
+            }
+        }
+    }
+}
@@ -1,36 +1,550 @@
+use std::cmp::Ordering;
+use std::path::PathBuf;
+
 use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::configs::*;
+use crate::nn::NetworkArch;
 
 #[derive(Resource, Default)]
 pub struct SimStats {
     pub num_cars_alive: usize,
+    pub num_cars_finished: usize,
     pub fitness: Vec<f32>,
     pub generation_count: u32,
     pub max_current_score: f32,
+    pub seed: u64,
+    pub last_nodes_explored: usize,
+    pub last_path_cost: i32,
+    // (nodes explored, path cost) of recent searches, oldest first
+    pub search_samples: Vec<(usize, i32)>,
+    // Wall clock time spent searching for A* paths since startup
+    pub total_search_secs: f32,
+    // Checkpoints cleared by each A* car
+    pub checkpoints_cleared: Vec<usize>,
+    // Spread of the last bred generation's weights, one entry per generation
+    pub population_diversity: f32,
+    pub diversity: Vec<f32>,
+    // Share of cars that reached the goal, per generation for the NN and
+    // live for A*
+    pub success_rate: f32,
+    // Best results so far, kept across generations until a restart
+    pub leaderboard: Vec<LeaderboardEntry>,
+}
+
+// One car's result, recorded when it finishes, crashes or times out
+#[derive(Clone)]
+pub struct LeaderboardEntry {
+    pub generation: u32,
+    pub car: Entity,
+    pub color: Color,
+    // Seconds from spawn to the goal, None when it never got there
+    pub finish_secs: Option<f32>,
+    pub distance: f32,
 }
 
+impl LeaderboardEntry {
+    // Finishers by time first, then everyone else by distance
+    pub fn cmp_rank(&self, other: &Self) -> Ordering {
+        match (self.finish_secs, other.finish_secs) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => other.distance.total_cmp(&self.distance),
+        }
+    }
+}
+
+// Shared RNG for the genetic algorithm (initial weights, selection, mutation)
 #[derive(Resource)]
+pub struct SimRng(pub StdRng);
+
+// Seeds the stochastic environment (enemy layout, spawn jitter) so that
+// generation N always plays out on the same track across runs
+#[derive(Resource)]
+pub struct GenerationSeed {
+    pub base_seed: u64,
+}
+
+// Which algorithm drives the cars, switchable at runtime from the GUI. Every
+// mode specific system is gated on it with in_mode
+#[derive(Resource, Clone, Copy, PartialEq, Serialize)]
+pub enum AlgorithmMode {
+    NeuralNetwork,
+    AStar,
+}
+
+// Search used by the A* cars to plan their paths
+#[derive(Clone, Copy, PartialEq, Serialize)]
+pub enum Planner {
+    // Grid A*, smoothed afterwards
+    AStar,
+    // Any-angle A* that shortcuts through line of sight while searching
+    ThetaStar,
+    // A* from both ends at once, meeting in the middle
+    Bidirectional,
+    // Jump Point Search, A* that skips runs of open cells
+    JumpPoint,
+    // Incremental replanning that reuses the previous search
+    DStarLite,
+    // A* that pays extra for every change of direction
+    TurnPenalized,
+}
+
+// How parents are picked when breeding the next generation
+#[derive(Clone, Copy, PartialEq, Serialize)]
+pub enum SelectionStrategy {
+    // Fitness proportional, one parent per child
+    RouletteWheel,
+    // Fittest of `size` random brains, two parents crossed over per child
+    Tournament { size: usize },
+}
+
+// How two parents' weights are combined, cut points are taken over all
+// weights laid out layer by layer, node by node
+#[derive(Clone, Copy, PartialEq, Serialize)]
+pub enum CrossoverStrategy {
+    // Everything after one cut comes from the second parent
+    SinglePoint,
+    // Everything between two cuts comes from the second parent
+    TwoPoint,
+    // Each weight comes from either parent with equal odds
+    Uniform,
+}
+
+// Where both populations put their cars at the start of a generation,
+// laid out so the car bodies don't overlap wherever the area allows
+#[derive(Clone, Copy, PartialEq, Serialize)]
+pub enum SpawnFormation {
+    // Rows of up to `cols` cars with `spacing` between their bodies, odd
+    // columns staggered half a row
+    Grid { cols: u32, spacing: f32 },
+    // One row across the road, spread out as far as it allows
+    Line,
+    // Anywhere in the first `area` units up the road
+    Random { area: f32 },
+}
+
+// When a NN generation is over and the next one is bred
+#[derive(Clone, Copy, PartialEq, Serialize)]
+pub enum GenerationEnd {
+    // Once every car has crashed, timed out or reached the goal
+    AllDone,
+    // After `secs` of simulated time, or sooner if every car is done
+    TimeLimit { secs: f32 },
+}
+
+#[derive(Resource, Clone, Serialize)]
 pub struct Settings {
     pub is_show_rays: bool,
     pub is_hide_rays_at_start: bool,
     pub start_next_generation: bool,
+    pub paused: bool,
+    // Advance a single update while paused
+    pub step_frame: bool,
     pub restart_sim: bool,
     pub is_camera_follow: bool,
+    pub camera_fit_all: bool,
+    pub num_cars: u32,
+    // Simulation speed relative to real time
+    pub time_scale: f32,
+    // Fixed seed for SimRng, None seeds it from entropy
+    pub rng_seed: Option<u64>,
+    pub show_trails: bool,
+    // Translucent replay of the previous generation's best car
+    pub show_ghost: bool,
+    pub draw_astar_paths: bool,
+    // Show the lead car's grid cell under the cursor, clicks toggle obstacles
+    pub is_grid_inspector: bool,
+    // Dragging on the road places a solid block instead of panning
+    pub is_placing_obstacles: bool,
+    pub clear_placed_obstacles: bool,
+    // Sensors are laid out once at startup and brains are sized to match,
+    // so changing the ray count mid-run needs a restart with fresh brains
+    pub sensor_ray_count: u32,
+    pub sensor_max_length: f32,
+    pub sensor_fov_start_deg: f32,
+    pub sensor_fov_end_deg: f32,
+    // Angle ranges (min, max) in degrees where no ray is cast
+    pub sensor_dead_zones: Vec<(f32, f32)>,
+    pub scan_freshness_seconds: f32,
+    pub force_rescan: bool,
+    pub is_incremental_scan: bool,
+    pub planner: Planner,
+    // Off keeps crashed A* cars driving, handy when debugging paths
+    pub is_astar_death: bool,
+    pub spawn_formation: SpawnFormation,
+    // Run each car's path search on the compute task pool
+    pub is_parallel_search: bool,
+    pub path_simplify_epsilon: f32,
+    // Weighted A*, 1 is exact and higher trades path cost for fewer nodes
+    pub astar_epsilon: f32,
+    // Charged per direction change by the turn penalized planner
+    pub astar_turn_cost: i32,
+    // Frame time A* planning is throttled to stay under, 0 disables it
+    pub target_frame_ms: f32,
+    // A* car driving speed and how fast it turns toward its target
+    pub astar_speed: f32,
+    pub astar_turn_rate: f32,
+    pub astar_allow_reverse: bool,
+    pub is_reward_shaping: bool,
+    pub progress_cell_reward: f32,
+    pub finish_bonus: f32,
+    pub weight_clamp: f32,
+    pub weight_decay: f32,
+    // Chance of each weight being mutated, read when breeding
+    pub mutation_rate: f32,
+    pub mutation_strength: f32,
+    // Best brains carried into the next generation unchanged
+    pub elite_count: usize,
+    pub selection: SelectionStrategy,
+    pub crossover: CrossoverStrategy,
+    pub generation_end: GenerationEnd,
+    // The run stops on reaching this generation, 0 never stops it
+    pub max_generations: u32,
+    // The run stops once the best fitness hasn't improved for this many
+    // generations, 0 never stops it
+    pub plateau_generations: u32,
+    // Layout of newly built brains, inputs are synced to the sensors at
+    // startup and saved brains of any other layout are rejected
+    pub network_arch: NetworkArch,
+    // Write the stats history to a JSON file when the app exits
+    pub is_export_history: bool,
+    // Append every car's fitness to a CSV at the end of each generation
+    pub is_export_car_fitness: bool,
+    // Full stats history rewritten here after every generation
+    pub stats_out_path: Option<PathBuf>,
+    pub save_best_brain: bool,
+    pub load_saved_brain: bool,
+    pub save_population: bool,
+    // Write the best brain and generation to AUTOSAVE_PATH when the app closes
+    pub autosave_on_exit: bool,
+    // Saved population the first generation resumes from
+    pub resume_path: Option<PathBuf>,
+    pub export_astar_path: bool,
+    // Snapshot the lead A* car's grid to the next ASTAR_GRID_DUMP_PREFIX file
+    pub dump_astar_grid: bool,
+    pub export_history_csv: bool,
+    // Log every car's transform each frame, saved when switched off
+    pub recording: bool,
+    // Drive one car with WASD or the arrow keys, logged as demonstration
+    // data while recording_demonstration is on
+    pub is_manual_drive: bool,
+    // Log manual driving as demonstration data, saved when switched off
+    pub recording_demonstration: bool,
 }
 
+// Set for exactly the one frame a paused step runs in
+#[derive(Resource, Default)]
+pub struct FrameStep(pub bool);
+
 #[derive(Resource, Default)]
 pub struct BrainToDisplay(pub Vec<Vec<f64>>);
 
+// Weights of the displayed brain, per layer, per node, bias first
+#[derive(Resource, Default)]
+pub struct BrainWeightsToDisplay(pub Vec<Vec<Vec<f64>>>);
+
 #[derive(Resource)]
 pub struct MaxDistanceTravelled(pub f32);
 
+// Car the camera is centred on, picked by clicking it or cycling with Tab
+#[derive(Resource)]
+pub struct FollowTarget(pub Entity);
+
+// The world camera, any other camera (say a minimap) is left alone
+#[derive(Component)]
+pub struct MainCamera;
+
+// Where a run finishes, for both algorithms. The entity's transform is the
+// A* cars' goal and the point where the NN fitness tops out
+#[derive(Component)]
+pub struct Goal {
+    // Crossing the goal's y anywhere on the road counts, otherwise the car
+    // has to get within `radius` of it
+    pub is_finish_line: bool,
+    pub radius: f32,
+}
+
+impl Goal {
+    pub fn is_reached(&self, goal_pos: Vec2, pos: Vec2) -> bool {
+        match self.is_finish_line {
+            true => pos.y >= goal_pos.y,
+            false => pos.distance(goal_pos) <= self.radius,
+        }
+    }
+
+    // Forward progress toward the goal, GOAL_SCORE once level with it
+    pub fn score(goal_pos: Vec2, pos: Vec2) -> f32 {
+        pos.y / goal_pos.y.max(1.0) * GOAL_SCORE
+    }
+}
+
 impl Default for Settings {
     fn default() -> Self {
         Self {
             is_show_rays: true,
             is_hide_rays_at_start: true,
             start_next_generation: false,
+            paused: false,
+            step_frame: false,
             restart_sim: false,
             is_camera_follow: true,
+            camera_fit_all: false,
+            num_cars: NUM_AI_CARS,
+            time_scale: 1.0,
+            rng_seed: None,
+            show_trails: false,
+            show_ghost: false,
+            draw_astar_paths: false,
+            is_grid_inspector: false,
+            is_placing_obstacles: false,
+            clear_placed_obstacles: false,
+            sensor_ray_count: NUM_RAY_CASTS,
+            sensor_max_length: RAYCAST_MAX_TOI,
+            sensor_fov_start_deg: RAYCAST_START_ANGLE_DEG,
+            sensor_fov_end_deg: RAYCAST_END_ANGLE_DEG,
+            sensor_dead_zones: Vec::new(),
+            scan_freshness_seconds: ASTAR_SCAN_FRESHNESS_SECONDS,
+            force_rescan: false,
+            is_incremental_scan: true,
+            planner: Planner::AStar,
+            is_astar_death: true,
+            spawn_formation: SpawnFormation::Grid {
+                cols: SPAWN_FORMATION_COLS,
+                spacing: SPAWN_FORMATION_SPACING,
+            },
+            is_parallel_search: true,
+            path_simplify_epsilon: ASTAR_PATH_SIMPLIFY_EPSILON,
+            astar_epsilon: ASTAR_EPSILON,
+            astar_turn_cost: ASTAR_TURN_COST,
+            target_frame_ms: ASTAR_TARGET_FRAME_MS,
+            astar_speed: ASTAR_MOVEMENT_SPEED,
+            astar_turn_rate: ASTAR_ROTATION_SPEED,
+            astar_allow_reverse: false,
+            is_reward_shaping: false,
+            progress_cell_reward: PROGRESS_CELL_REWARD,
+            finish_bonus: FINISH_BONUS,
+            weight_clamp: NN_WEIGHT_CLAMP,
+            weight_decay: NN_WEIGHT_DECAY,
+            mutation_rate: NN_MUTATION_RATE,
+            mutation_strength: NN_MUTATION_STRENGTH,
+            elite_count: NN_ELITE_COUNT,
+            selection: SelectionStrategy::RouletteWheel,
+            crossover: CrossoverStrategy::Uniform,
+            generation_end: GenerationEnd::AllDone,
+            max_generations: 0,
+            plateau_generations: 0,
+            network_arch: NetworkArch {
+                inputs: NUM_RAY_CASTS as usize,
+                hidden: vec![NUM_HIDDEN_NODES],
+                outputs: NUM_OUPUT_NODES,
+            },
+            is_export_history: false,
+            is_export_car_fitness: false,
+            stats_out_path: None,
+            save_best_brain: false,
+            load_saved_brain: false,
+            save_population: false,
+            autosave_on_exit: false,
+            resume_path: None,
+            export_astar_path: false,
+            dump_astar_grid: false,
+            export_history_csv: false,
+            recording: false,
+            is_manual_drive: false,
+            recording_demonstration: false,
+        }
+    }
+}
+
+// Run condition for everything that advances the simulation
+pub fn is_sim_running(settings: Res<Settings>, frame_step: Res<FrameStep>) -> bool {
+    !settings.paused || frame_step.0
+}
+
+// Run condition for the systems that belong to one algorithm
+pub fn in_mode(mode: AlgorithmMode) -> impl FnMut(Res<AlgorithmMode>) -> bool {
+    move |current: Res<AlgorithmMode>| *current == mode
+}
+
+// Run condition for the first frame of `mode`, at startup or right after
+// switching to it
+pub fn mode_entered(mode: AlgorithmMode) -> impl FnMut(Res<AlgorithmMode>) -> bool {
+    move |current: Res<AlgorithmMode>| current.is_changed() && *current == mode
+}
+
+// The lead car, the one furthest up the road, of any query's items given
+// how to read an item's y
+pub fn lead_car<T>(cars: impl Iterator<Item = T>, y: impl Fn(&T) -> f32) -> Option<T> {
+    cars.max_by(|a, b| y(a).total_cmp(&y(b)))
+}
+
+impl SpawnFormation {
+    // Spawn points for `count` cars in the (left, right, start_y, depth)
+    // area from `spawn_area`, centres kept off the walls
+    pub fn positions(
+        &self,
+        count: u32,
+        (left, right, start_y, depth): (f32, f32, f32, f32),
+        rng: &mut impl Rng,
+    ) -> Vec<Vec2> {
+        let car_size = Vec2::new(CAR_HALF_WIDTH, ASTAR_CAR_HALF_EXTENT) * 2.0;
+        let usable_width = (right - left - 2.0 * SPAWN_WALL_CLEARANCE).max(0.0);
+        let center_x = (left + right) / 2.0;
+
+        match *self {
+            SpawnFormation::Grid { cols, spacing } => {
+                let pitch = car_size + spacing.max(0.0);
+                let columns = ((usable_width / pitch.x) as u32 + 1).min(cols.max(1));
+                let usable_depth = (depth - pitch.y / 2.0).max(0.0);
+                let rows = (usable_depth / pitch.y) as u32 + 1;
+                // Past what fits, rows carry on up the road beyond the spawn
+                // area rather than stacking cars on top of each other
+                let capacity = columns * rows;
+                if count > capacity {
+                    warn!(
+                        "{} cars don't fit the {}x{} spawn grid, {} start past the spawn area",
+                        count,
+                        columns,
+                        rows,
+                        count - capacity
+                    );
+                }
+
+                let first_x = center_x - (columns - 1) as f32 * pitch.x / 2.0;
+                (0..count)
+                    .map(|i| {
+                        let (column, row) = (i % columns, i / columns);
+                        let stagger = (column % 2) as f32 * pitch.y / 2.0;
+                        Vec2::new(
+                            first_x + column as f32 * pitch.x,
+                            start_y + row as f32 * pitch.y + stagger,
+                        )
+                    })
+                    .collect()
+            }
+            SpawnFormation::Line => {
+                let gaps = count.saturating_sub(1).max(1) as f32;
+                let pitch = (usable_width / gaps).min(car_size.x * 2.0);
+                if count > 1 && pitch < car_size.x {
+                    warn!("{} cars don't fit side by side, they will overlap", count);
+                }
+
+                let first_x = center_x - count.saturating_sub(1) as f32 * pitch / 2.0;
+                (0..count)
+                    .map(|i| Vec2::new(first_x + i as f32 * pitch, start_y))
+                    .collect()
+            }
+            SpawnFormation::Random { area } => {
+                let area = area.clamp(0.0, depth.max(0.0));
+                let overlaps = |a: Vec2, b: Vec2| {
+                    (a.x - b.x).abs() < car_size.x && (a.y - b.y).abs() < car_size.y
+                };
+
+                let mut positions: Vec<Vec2> = Vec::with_capacity(count as usize);
+                let mut num_overlapping = 0;
+                for _ in 0..count {
+                    // Keeps the last try when nothing clear turns up
+                    let mut spawn = Vec2::ZERO;
+                    for _ in 0..SPAWN_RANDOM_ATTEMPTS {
+                        spawn = Vec2::new(
+                            center_x + (rng.gen::<f32>() - 0.5) * usable_width,
+                            start_y + rng.gen::<f32>() * area,
+                        );
+                        if !positions.iter().any(|&other| overlaps(spawn, other)) {
+                            break;
+                        }
+                    }
+                    if positions.iter().any(|&other| overlaps(spawn, other)) {
+                        num_overlapping += 1;
+                    }
+                    positions.push(spawn);
+                }
+                if num_overlapping > 0 {
+                    warn!("{} random spawns overlap another car", num_overlapping);
+                }
+                positions
+            }
+        }
+    }
+}
+
+impl AlgorithmMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            AlgorithmMode::NeuralNetwork => "Neural Network + Genetic Algorithm",
+            AlgorithmMode::AStar => "A* Pathfinding Algorithm",
+        }
+    }
+}
+
+impl SimRng {
+    pub fn new(seed: Option<u64>) -> Self {
+        match seed {
+            Some(seed) => Self(StdRng::seed_from_u64(seed)),
+            None => Self(StdRng::from_entropy()),
+        }
+    }
+}
+
+impl Default for SimRng {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl GenerationSeed {
+    pub fn for_generation(&self, generation: u32) -> u64 {
+        self.base_seed.wrapping_add(generation as u64)
+    }
+
+    pub fn rng(&self, generation: u32) -> StdRng {
+        StdRng::seed_from_u64(self.for_generation(generation))
+    }
+}
+
+impl Default for GenerationSeed {
+    fn default() -> Self {
+        Self {
+            base_seed: GENERATION_BASE_SEED,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_spaced(positions: &[Vec2]) {
+        for (i, a) in positions.iter().enumerate() {
+            for b in positions[i + 1..].iter() {
+                assert!(a.distance(*b) >= 2.0 * CAR_HALF_WIDTH, "{} and {} overlap", a, b);
+            }
         }
     }
+
+    #[test]
+    fn grid_spawns_are_a_car_width_apart() {
+        let area = (ROAD_LEFT_X, ROAD_RIGHT_X, 0.0, SPAWN_AREA_DEPTH);
+        let mut rng = StdRng::seed_from_u64(325);
+        for (cols, spacing) in [(10, 5.0), (3, 0.0), (50, 0.0), (1, 20.0)] {
+            let formation = SpawnFormation::Grid { cols, spacing };
+            let positions = formation.positions(100, area, &mut rng);
+            assert_eq!(positions.len(), 100);
+            assert_spaced(&positions);
+        }
+    }
+
+    // More cars than the spawn area holds carry on up the road
+    #[test]
+    fn overfull_grid_spawns_dont_overlap() {
+        let formation = SpawnFormation::Grid { cols: 4, spacing: 0.0 };
+        let mut rng = StdRng::seed_from_u64(1);
+        let positions = formation.positions(200, (0.0, 200.0, 0.0, 50.0), &mut rng);
+        assert_eq!(positions.len(), 200);
+        assert_spaced(&positions);
+    }
 }
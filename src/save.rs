@@ -0,0 +1,160 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::car::{Brain, Demonstration};
+use crate::nn::{Net, NetworkArch};
+
+// Bump whenever the serialized network layout changes
+pub const BRAIN_FILE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct SavedBrain {
+    version: u32,
+    nn: Net,
+}
+
+// Whole generation, enough to pick a training run back up where it stopped
+#[derive(Serialize, Deserialize)]
+struct SavedPopulation {
+    version: u32,
+    generation: u32,
+    brains: Vec<Net>,
+}
+
+pub fn save_brain(path: &Path, brain: &Brain) -> io::Result<()> {
+    let saved = SavedBrain {
+        version: BRAIN_FILE_VERSION,
+        nn: brain.nn.clone(),
+    };
+    let json = serde_json::to_string_pretty(&saved)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+    fs::write(path, json)
+}
+
+pub fn save_population(path: &Path, brains: &[&Brain], generation: u32) -> io::Result<()> {
+    let saved = SavedPopulation {
+        version: BRAIN_FILE_VERSION,
+        generation,
+        brains: brains.iter().map(|brain| brain.nn.clone()).collect(),
+    };
+    let json =
+        serde_json::to_string(&saved).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+    fs::write(path, json)
+}
+
+pub fn load_population(path: &Path, arch: &NetworkArch) -> io::Result<(Vec<Brain>, u32)> {
+    let json = fs::read_to_string(path)?;
+    let saved: SavedPopulation =
+        serde_json::from_str(&json).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    if saved.version != BRAIN_FILE_VERSION {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Population file version {} doesn't match expected {}",
+                saved.version, BRAIN_FILE_VERSION
+            ),
+        ));
+    }
+    if saved.brains.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidData, "Population file has no brains"));
+    }
+    for nn in saved.brains.iter() {
+        check_arch(nn, arch)?;
+    }
+
+    Ok((saved.brains.into_iter().map(Brain::new).collect(), saved.generation))
+}
+
+pub fn save_demonstration(path: &Path, demonstration: &Demonstration) -> io::Result<()> {
+    let json = serde_json::to_string(demonstration)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+    fs::write(path, json)
+}
+
+pub fn load_brain(path: &Path, arch: &NetworkArch) -> io::Result<Brain> {
+    let json = fs::read_to_string(path)?;
+    let saved: SavedBrain =
+        serde_json::from_str(&json).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    if saved.version != BRAIN_FILE_VERSION {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Brain file version {} doesn't match expected {}",
+                saved.version, BRAIN_FILE_VERSION
+            ),
+        ));
+    }
+    check_arch(&saved.nn, arch)?;
+
+    Ok(Brain::new(saved.nn))
+}
+
+// Weights laid out for another network can't be fed this one's inputs
+fn check_arch(nn: &Net, arch: &NetworkArch) -> io::Result<()> {
+    if !nn.is_well_formed() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Saved network has no layers or weights that don't match its layer sizes",
+        ));
+    }
+    let saved_arch = nn.arch();
+    if saved_arch != *arch {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Saved network is {} but the current one is {}", saved_arch, arch),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn net(json: &str) -> Net {
+        serde_json::from_str(json).expect("bad test network")
+    }
+
+    fn arch(hidden: Vec<usize>) -> NetworkArch {
+        NetworkArch {
+            inputs: 2,
+            hidden,
+            outputs: 1,
+        }
+    }
+
+    #[test]
+    fn accepts_matching_network() {
+        let nn = net(r#"{"n_inputs": 2, "layers": [{"nodes": [[0.1, 0.2, 0.3]]}]}"#);
+        assert!(check_arch(&nn, &arch(vec![])).is_ok());
+    }
+
+    #[test]
+    fn rejects_network_without_layers() {
+        let nn = net(r#"{"n_inputs": 2, "layers": []}"#);
+        let e = check_arch(&nn, &arch(vec![])).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    // Node counts match the expected layout, the weights per node don't
+    #[test]
+    fn rejects_short_weight_vectors() {
+        let nn = net(
+            r#"{"n_inputs": 2, "layers": [
+                {"nodes": [[0.1, 0.2, 0.3], [0.1, 0.2, 0.3]]},
+                {"nodes": [[0.1, 0.2]]}
+            ]}"#,
+        );
+        let e = check_arch(&nn, &arch(vec![2])).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::pathfinding::GridConfig;
+use crate::*;
+
+// Hand authored track, read from an ASCII map with one character per grid
+// cell: `#` wall, `S` spawn, `G` goal, `1`-`9` checkpoints visited in that
+// order before the goal, anything else is open road.
+// The first line of the file is the far end of the track
+#[derive(Resource, Clone)]
+pub struct Track {
+    pub width: i32,
+    pub height: i32,
+    pub cell_size: f32,
+    pub origin: Vec2,
+    pub walls: HashSet<(i32, i32)>,
+    pub spawn: Vec2,
+    pub goal: Vec2,
+    pub checkpoints: Vec<Vec2>,
+}
+
+impl Track {
+    pub fn cell_center(&self, cell: (i32, i32)) -> Vec2 {
+        self.origin + (Vec2::new(cell.0 as f32, cell.1 as f32) + 0.5) * self.cell_size
+    }
+
+    // A* grid covering exactly the track, with its walls already known
+    pub fn grid_config(&self) -> GridConfig {
+        GridConfig {
+            width: self.width,
+            height: self.height,
+            cell_size: self.cell_size,
+            origin: self.origin,
+            static_obstacles: self.walls.clone(),
+        }
+    }
+
+    // Open stretch of the spawn cell's row and how far the road ahead of it
+    // is clear, as (left, right, start_y, depth)
+    pub fn spawn_area(&self) -> (f32, f32, f32, f32) {
+        let spawn_cell = (
+            ((self.spawn.x - self.origin.x) / self.cell_size) as i32,
+            ((self.spawn.y - self.origin.y) / self.cell_size) as i32,
+        );
+        let is_open = |x: i32, y: i32| {
+            (0..self.width).contains(&x)
+                && (0..self.height).contains(&y)
+                && !self.walls.contains(&(x, y))
+        };
+
+        let mut left = spawn_cell.0;
+        while is_open(left - 1, spawn_cell.1) {
+            left -= 1;
+        }
+        let mut right = spawn_cell.0;
+        while is_open(right + 1, spawn_cell.1) {
+            right += 1;
+        }
+        let mut top = spawn_cell.1;
+        while is_open(spawn_cell.0, top + 1) {
+            top += 1;
+        }
+
+        let half_cell = self.cell_size / 2.0;
+        (
+            self.cell_center((left, spawn_cell.1)).x - half_cell,
+            self.cell_center((right, spawn_cell.1)).x + half_cell,
+            self.spawn.y,
+            ((top - spawn_cell.1) as f32 * self.cell_size).min(SPAWN_AREA_DEPTH),
+        )
+    }
+}
+
+// The track's goal cell, or the finish line at the end of the default road
+pub fn goal_position(track: Option<&Track>) -> Vec2 {
+    match track {
+        Some(track) => track.goal,
+        None => Vec2::new(ROAD_CENTER_X, FINISH_LINE_Y),
+    }
+}
+
+// Area both populations spawn their formation in, the default road's start
+// or the open road around a track's spawn cell
+pub fn spawn_area(track: Option<&Track>) -> (f32, f32, f32, f32) {
+    match track {
+        Some(track) => track.spawn_area(),
+        None => (ROAD_LEFT_X, ROAD_RIGHT_X, WINDOW_HEIGHT / 2.0, SPAWN_AREA_DEPTH),
+    }
+}
+
+// The one Goal entity everything reads the finish from, a band across the
+// default road or a square over a track's goal cell
+pub fn spawn_goal(commands: &mut Commands, track: Option<&Track>) {
+    let goal = goal_position(track);
+    let (size, radius) = match track {
+        Some(track) => (Vec2::splat(track.cell_size), track.cell_size),
+        None => (Vec2::new(ROAD_RIGHT_X - ROAD_LEFT_X, 6.0), 0.0),
+    };
+    commands.spawn((
+        SpriteBundle {
+            transform: Transform::from_xyz(goal.x, goal.y, -4.0),
+            sprite: Sprite {
+                color: Color::rgba(0.2, 1.0, 0.4, 0.6),
+                custom_size: Some(size),
+                ..default()
+            },
+            ..default()
+        },
+        Goal {
+            is_finish_line: track.is_none(),
+            radius,
+        },
+    ));
+}
+
+pub fn load_track(path: &Path) -> io::Result<Track> {
+    parse_track(&fs::read_to_string(path)?)
+}
+
+pub fn parse_track(map: &str) -> io::Result<Track> {
+    let rows: Vec<&str> = map.lines().filter(|line| !line.trim().is_empty()).collect();
+    let height = rows.len() as i32;
+    let width = rows.iter().map(|row| row.chars().count()).max().unwrap_or(0) as i32;
+    if width == 0 {
+        return Err(io::Error::new(ErrorKind::InvalidData, "Track map is empty"));
+    }
+
+    let mut walls = HashSet::new();
+    let mut spawn = None;
+    let mut goal = None;
+    let mut checkpoints = Vec::new();
+    for (row, line) in rows.iter().enumerate() {
+        // Top line of the file is the highest row of the grid
+        let y = height - 1 - row as i32;
+        for (x, c) in line.chars().enumerate() {
+            let cell = (x as i32, y);
+            match c {
+                '#' => {
+                    walls.insert(cell);
+                }
+                'S' => spawn = Some(cell),
+                'G' => goal = Some(cell),
+                '1'..='9' => checkpoints.push((c, cell)),
+                _ => {}
+            }
+        }
+    }
+
+    let (Some(spawn), Some(goal)) = (spawn, goal) else {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Track map needs both an S (spawn) and a G (goal) cell",
+        ));
+    };
+
+    let mut track = Track {
+        width,
+        height,
+        cell_size: ASTAR_CELL_SIZE,
+        origin: GridConfig::default().origin,
+        walls,
+        spawn: Vec2::ZERO,
+        goal: Vec2::ZERO,
+        checkpoints: Vec::new(),
+    };
+    track.spawn = track.cell_center(spawn);
+    track.goal = track.cell_center(goal);
+    checkpoints.sort_by_key(|(c, _)| *c);
+    track.checkpoints = checkpoints.iter().map(|(_, cell)| track.cell_center(*cell)).collect();
+    Ok(track)
+}
+
+pub fn spawn_track(commands: &mut Commands, track: &Track) {
+    // One collider per horizontal run of wall cells
+    for y in 0..track.height {
+        let mut x = 0;
+        while x < track.width {
+            if !track.walls.contains(&(x, y)) {
+                x += 1;
+                continue;
+            }
+
+            let run_start = x;
+            while track.walls.contains(&(x, y)) {
+                x += 1;
+            }
+            let run_len = (x - run_start) as f32;
+            let center = (track.cell_center((run_start, y)) + track.cell_center((x - 1, y))) / 2.0;
+            let size = Vec2::new(run_len * track.cell_size, track.cell_size);
+
+            commands.spawn((
+                SpriteBundle {
+                    transform: Transform::from_xyz(center.x, center.y, -5.0),
+                    sprite: Sprite {
+                        color: Color::rgb_u8(90, 90, 90),
+                        custom_size: Some(size),
+                        ..default()
+                    },
+                    ..default()
+                },
+                RigidBody::Fixed,
+                Collider::cuboid(size.x / 2.0, size.y / 2.0),
+                OBSTACLE_COLLISION_GROUPS,
+            ));
+        }
+    }
+}